@@ -0,0 +1,100 @@
+use std::time::Duration;
+
+use rodio::Source;
+
+// A source turned down on one side, pan going from -1 (left only) through
+// 0 (both as they are) to 1 (right only). Only the first two channels are
+// touched. A mono source is spread over two channels first, as there is
+// nothing to pan otherwise, unless the balance is left in the middle
+pub struct Balance<S> {
+    input: S,
+    left: f32,
+    right: f32,
+    upmix: bool,
+    // The right channel copy of the last mono sample, not yet returned
+    pending: Option<f32>,
+    channel: usize,
+}
+
+impl<S> Balance<S>
+where
+    S: Source<Item = f32>,
+{
+    pub fn new(input: S, pan: f32) -> Balance<S> {
+        let pan = pan.clamp(-1.0, 1.0);
+
+        Balance {
+            input,
+            left: (1.0 - pan).min(1.0),
+            right: (1.0 + pan).min(1.0),
+            upmix: pan != 0.0,
+            pending: None,
+            channel: 0,
+        }
+    }
+
+    fn mono(&self) -> bool {
+        self.upmix && self.input.channels() == 1
+    }
+}
+
+impl<S> Iterator for Balance<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if let Some(sample) = self.pending.take() {
+            return Some(sample);
+        }
+
+        if self.mono() {
+            let sample = self.input.next()?;
+            self.pending = Some(sample * self.right);
+            return Some(sample * self.left);
+        }
+
+        let sample = self.input.next()?;
+        let gain = match self.channel {
+            0 => self.left,
+            1 => self.right,
+            _ => 1.0,
+        };
+        self.channel += 1;
+        if self.channel >= self.input.channels() as usize {
+            self.channel = 0;
+        }
+
+        Some(sample * gain)
+    }
+}
+
+impl<S> Source for Balance<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        let pending = self.pending.is_some() as usize;
+        match self.input.current_frame_len() {
+            Some(len) if self.mono() => Some(len * 2 + pending),
+            len => len,
+        }
+    }
+
+    fn channels(&self) -> u16 {
+        if self.mono() {
+            2
+        } else {
+            self.input.channels()
+        }
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+}
@@ -1,101 +1,567 @@
+use std::collections::HashMap;
 use std::fs;
 use std::path::{Path, PathBuf};
 
-use dirs::home_dir;
+use dirs::{cache_dir, config_dir, data_dir, home_dir};
 use serde_derive::{Deserialize, Serialize};
 use toml;
 
-#[derive(Serialize, Deserialize, Debug)]
+use crate::application::equalizer::{Gains, FLAT, MAX_GAIN};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Config {
     pub music_folder: String,
     pub data_folder: String,
     pub database_path: String,
     pub stats_path: String,
+    // Where scans, skipped files and playback problems are logged, since
+    // the interface takes over the terminal
+    #[serde(default = "default_log_path")]
+    pub log_path: String,
+    // Album art and the search index, which are built again if lost
+    #[serde(default = "default_cache_folder")]
+    pub cache_folder: String,
+    #[serde(default)]
+    pub dedupe: DedupeStrategy,
+    // Let the current track finish before the sleep timer stops playback
+    #[serde(default)]
+    pub sleep_after_track: bool,
+    #[serde(default)]
+    pub replaygain: ReplayGainMode,
+    // Start playing the last track from where it was left on launch
+    #[serde(default)]
+    pub resume_on_start: bool,
+    // Ask before clearing the queue
+    #[serde(default = "default_confirm_clear")]
+    pub confirm_clear: bool,
+    // Milliseconds to fade in when playback starts and out before it
+    // pauses or stops, 0 turning fading off
+    #[serde(default = "default_fade_ms")]
+    pub fade_ms: u64,
+    #[serde(default)]
+    pub album_art: AlbumArtMode,
+    // What separates the names in artist tags crediting more than one
+    #[serde(default = "default_artist_separators")]
+    pub artist_separators: Vec<String>,
+    // An album in one folder with tracks by at least this many artists,
+    // and no album artist tag, goes under various_artists. 0 turns it off
+    #[serde(default = "default_compilation_artists")]
+    pub compilation_artists: usize,
+    #[serde(default = "default_various_artists")]
+    pub various_artists: String,
+    // Gitignore-style globs for what scanning skips, relative to the music
+    // folder, e.g. "Audiobooks/" or "*.wav"
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    // Scan into symlinked folders and files, e.g. albums kept on another
+    // drive. Symlinks that loop back on themselves are skipped
+    #[serde(default)]
+    pub follow_symlinks: bool,
+    #[serde(default)]
+    pub scan_on_startup: ScanOnStartup,
+    // Most search results shown, best matches first, 0 showing them all
+    #[serde(default = "default_search_limit")]
+    pub search_limit: usize,
+    // Click to select rows, tabs and places in the progress bar, and scroll
+    // with the wheel. Off by default, as it stops the terminal's own text
+    // selection from working
+    #[serde(default)]
+    pub mouse: bool,
+    // Moving past the end of a list or the tabs goes round to the start,
+    // and the other way around. Without it they stop at the ends
+    #[serde(default = "default_wrap")]
+    pub wrap: bool,
+    // How many played tracks are kept, both to go back through and for
+    // the recently played tab
+    #[serde(default = "default_history_length")]
+    pub history_length: usize,
+    // How playback starts out: volume in percent, what repeats, and
+    // whether tracks added to the queue go in at random places
+    #[serde(default = "default_volume")]
+    pub default_volume: u32,
+    #[serde(default)]
+    pub default_repeat: RepeatMode,
+    #[serde(default)]
+    pub default_shuffle: bool,
+    // Tracks at least this many minutes long start where they were left
+    // off when played again, 0 turning that off
+    #[serde(default = "default_resume_min_minutes")]
+    pub resume_min_minutes: u64,
+    // Gain in dB of each equalizer band, see application::equalizer.
+    // Changes made in sonik are kept in the session and win over this
+    #[serde(default)]
+    pub equalizer: Gains,
+    // Action names mapped to keys, see application::keybindings. Being a
+    // table, this has to stay below the plain values when written out
+    #[serde(default)]
+    pub keybindings: HashMap<String, String>,
+    #[serde(default)]
+    pub theme: ThemeConfig,
 }
 
-impl Config {
-    pub fn default() -> Config {
-        // This bit could probably be optimized
-        let mut music_location = home_dir().unwrap();
-        music_location.push("Music");
+// Where sonik kept everything before it followed the XDG base directories.
+// Setups from back then carry on using it
+fn legacy_folder() -> Option<PathBuf> {
+    let folder = home_dir()?.join(".sonik");
+    if folder.is_dir() {
+        Some(folder)
+    } else {
+        None
+    }
+}
+
+// The database, stats, session and log, in ~/.local/share/sonik on Linux
+// and ~/Library/Application Support/sonik on macOS
+fn default_data_folder() -> PathBuf {
+    legacy_folder()
+        .or_else(|| data_dir().map(|d| d.join("sonik")))
+        .unwrap_or_else(|| home_dir().unwrap().join(".sonik"))
+}
+
+// ~/.cache/sonik on Linux and ~/Library/Caches/sonik on macOS
+fn default_cache_folder() -> String {
+    let cache_folder = legacy_folder()
+        .or_else(|| cache_dir().map(|d| d.join("sonik")))
+        .unwrap_or_else(default_data_folder);
+
+    cache_folder.to_str().unwrap().to_owned()
+}
+
+fn default_log_path() -> String {
+    let log_path = default_data_folder().join("sonik.log");
 
-        let mut data_folder = home_dir().unwrap();
-        data_folder.push(".sonik");
+    log_path.to_str().unwrap().to_owned()
+}
 
-        let mut database_path = home_dir().unwrap();
-        database_path.push(".sonik");
-        database_path.push("library.db");
+fn default_confirm_clear() -> bool {
+    true
+}
+
+fn default_wrap() -> bool {
+    true
+}
+
+fn default_history_length() -> usize {
+    100
+}
+
+fn default_volume() -> u32 {
+    100
+}
+
+fn default_resume_min_minutes() -> u64 {
+    20
+}
+
+fn default_fade_ms() -> u64 {
+    200
+}
 
-        let mut stats_path = home_dir().unwrap();
-        stats_path.push(".sonik");
-        stats_path.push("library.stats");
+fn default_search_limit() -> usize {
+    50
+}
+
+fn default_artist_separators() -> Vec<String> {
+    vec![";".to_string(), "/".to_string()]
+}
+
+fn default_compilation_artists() -> usize {
+    3
+}
+
+fn default_various_artists() -> String {
+    "Various Artists".to_string()
+}
+
+// When the music folder is scanned on startup, -r scanning it regardless.
+// With never and no library yet, sonik starts with an empty one
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum ScanOnStartup {
+    Always,
+    #[default]
+    IfMissing,
+    Never,
+}
+
+// How the cover of the playing album is drawn in the queue tab, see ui::art.
+// auto picks the best the terminal is known to support
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum AlbumArtMode {
+    #[default]
+    Off,
+    Auto,
+    Kitty,
+    Sixel,
+    Blocks,
+}
+
+// Colors for the interface, see ui::theme. A preset is picked by name and
+// any of the colors can be set on top of it, by name or as "#rrggbb"
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ThemeConfig {
+    #[serde(default)]
+    pub preset: String,
+    pub foreground: Option<String>,
+    pub background: Option<String>,
+    pub highlight: Option<String>,
+    pub inactive: Option<String>,
+    pub accent: Option<String>,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+}
+
+// How tracks are recognized as duplicates while scanning:
+// path - the same file reached through more than one path (e.g. symlinks)
+// content - copies of a file, identified by title, artist, album, and duration
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DedupeStrategy {
+    #[default]
+    Path,
+    Content,
+}
+
+// What plays again once it ends: nothing, the current track, or the whole
+// queue going round
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RepeatMode {
+    #[default]
+    Off,
+    One,
+    All,
+}
+
+// Which ReplayGain tag is used to level out playback volume
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ReplayGainMode {
+    #[default]
+    Off,
+    Track,
+    Album,
+}
+
+impl Config {
+    pub fn default() -> Config {
+        let data_folder = default_data_folder();
+        let music_folder = home_dir().unwrap().join("Music");
 
         Config {
-            music_folder: music_location.to_str().unwrap().to_owned(),
+            music_folder: music_folder.to_str().unwrap().to_owned(),
             data_folder: data_folder.to_str().unwrap().to_owned(),
-            database_path: database_path.to_str().unwrap().to_owned(),
-            stats_path: stats_path.to_str().unwrap().to_owned(),
+            database_path: data_folder.join("library.db").to_str().unwrap().to_owned(),
+            stats_path: data_folder
+                .join("library.stats")
+                .to_str()
+                .unwrap()
+                .to_owned(),
+            log_path: default_log_path(),
+            cache_folder: default_cache_folder(),
+            dedupe: DedupeStrategy::default(),
+            sleep_after_track: false,
+            replaygain: ReplayGainMode::default(),
+            resume_on_start: false,
+            confirm_clear: default_confirm_clear(),
+            fade_ms: default_fade_ms(),
+            album_art: AlbumArtMode::default(),
+            artist_separators: default_artist_separators(),
+            compilation_artists: default_compilation_artists(),
+            various_artists: default_various_artists(),
+            exclude: Vec::new(),
+            follow_symlinks: false,
+            scan_on_startup: ScanOnStartup::default(),
+            search_limit: default_search_limit(),
+            mouse: false,
+            wrap: default_wrap(),
+            history_length: default_history_length(),
+            default_volume: default_volume(),
+            default_repeat: RepeatMode::default(),
+            default_shuffle: false,
+            resume_min_minutes: default_resume_min_minutes(),
+            equalizer: FLAT,
+            keybindings: HashMap::new(),
+            theme: ThemeConfig::default(),
         }
     }
 
     pub fn new(music_location: &str) -> Result<Config, ()> {
-        let mut data_folder = home_dir().unwrap();
-        data_folder.push(".sonik");
-
-        let mut database_path = home_dir().unwrap();
-        database_path.push(".sonik");
-        database_path.push("library.db");
-
-        let mut stats_path = home_dir().unwrap();
-        stats_path.push(".sonik");
-        stats_path.push("library.stats");
-
         let config = Config {
             music_folder: music_location.to_string(),
-            data_folder: data_folder.to_str().unwrap().to_owned(),
-            database_path: database_path.to_str().unwrap().to_owned(),
-            stats_path: stats_path.to_str().unwrap().to_owned(),
+            ..Config::default()
         };
 
-        let mut config_path: PathBuf = home_dir().unwrap();
-        config_path.push(".sonik");
-        config_path.push("config.toml");
-
-        fs::create_dir_all(&config.data_folder).unwrap();
+        if let Err(e) = config.validate() {
+            println!("Error: {}", e);
+            return Err(());
+        }
 
-        // Save the configuration info to a TOML file in the data folder
+        // Save the configuration info to a TOML file next to where it is
+        // read from
+        let config_path = config_path();
+        if let Some(folder) = config_path.parent() {
+            fs::create_dir_all(folder).ok();
+        }
         let config_as_str = toml::to_string(&config).unwrap();
-        fs::write(&config_path.to_string_lossy().into_owned(), config_as_str).ok();
+        fs::write(config_path, config_as_str).ok();
 
         Ok(config)
     }
 
-    pub fn get_config() -> Result<Config, ()> {
-        // Set path for configuration file
-        let mut config_path: PathBuf = home_dir().unwrap();
-        config_path.push(".sonik");
-        config_path.push("config.toml");
+    // Read the config file, writing a commented default first if there
+    // isn't one. Anything that stops sonik from starting is printed and
+    // makes this fail, while settings that are merely ignored come back as
+    // warnings alongside the config
+    pub fn load_or_default() -> Result<(Config, Vec<String>), ()> {
+        let config_path = config_path();
+
+        if !config_path.exists() {
+            if write_default_config(&config_path).is_none() {
+                println!(
+                    "Error: Could not write a default config to {}",
+                    config_path.display()
+                );
+                return Err(());
+            }
+            println!("Wrote a default config to {}", config_path.display());
+        }
+
+        let config_string = match fs::read_to_string(&config_path) {
+            Ok(s) => s,
+            Err(e) => {
+                println!("Error: Could not read {}: {}", config_path.display(), e);
+                return Err(());
+            }
+        };
 
-        // Return an error if unable to write a new configuration file
-        if !config_path.exists() && write_default_config(config_path.as_path()).is_none() {
-            println!("Error: Could not write default config file");
+        let mut config: Config = match toml::from_str(&config_string) {
+            Ok(c) => c,
+            Err(e) => {
+                println!(
+                    "Error: {} is not a valid config: {}",
+                    config_path.display(),
+                    e
+                );
+                return Err(());
+            }
+        };
+
+        if let Err(e) = config.validate() {
+            println!("Error: {} (in {})", e, config_path.display());
             return Err(());
         }
 
-        // Create the configuration
-        let config_string = fs::read_to_string(&config_path).unwrap();
-        let config: Config = toml::from_str(&config_string).unwrap();
+        let mut warnings = unknown_settings(&config_string);
+        warnings.extend(config.clamp());
 
-        Ok(config)
+        Ok((config, warnings))
+    }
+
+    // Bring settings that are out of range back into it, with a warning
+    // for each
+    fn clamp(&mut self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if self.default_volume > 100 {
+            warnings.push(format!(
+                "default_volume {} is more than 100, using 100",
+                self.default_volume
+            ));
+            self.default_volume = 100;
+        }
+        for gain in self.equalizer.iter_mut() {
+            if gain.abs() > MAX_GAIN {
+                warnings.push(format!(
+                    "equalizer gain {} is out of range, using {}",
+                    gain,
+                    gain.clamp(-MAX_GAIN, MAX_GAIN)
+                ));
+                *gain = gain.clamp(-MAX_GAIN, MAX_GAIN);
+            }
+        }
+
+        warnings
+    }
+
+    // Check what has to be right before anything is scanned or loaded
+    fn validate(&self) -> Result<(), String> {
+        let music_folder = Path::new(&self.music_folder);
+        if !music_folder.exists() {
+            return Err(format!("music_folder {} does not exist", self.music_folder));
+        }
+        if !music_folder.is_dir() {
+            return Err(format!(
+                "music_folder {} is not a directory",
+                self.music_folder
+            ));
+        }
+
+        if let Err(e) = fs::create_dir_all(&self.data_folder) {
+            return Err(format!(
+                "data_folder {} could not be created: {}",
+                self.data_folder, e
+            ));
+        }
+        if let Err(e) = fs::create_dir_all(&self.cache_folder) {
+            return Err(format!(
+                "cache_folder {} could not be created: {}",
+                self.cache_folder, e
+            ));
+        }
+
+        // Files can be moved out of the data folder, into folders that
+        // don't exist yet
+        let files = [
+            ("database_path", &self.database_path),
+            ("stats_path", &self.stats_path),
+            ("log_path", &self.log_path),
+        ];
+        for (name, path) in files.iter() {
+            let folder = match Path::new(path).parent() {
+                Some(folder) if !folder.as_os_str().is_empty() => folder,
+                _ => continue,
+            };
+            if let Err(e) = fs::create_dir_all(folder) {
+                return Err(format!(
+                    "{} {} could not be created in {}: {}",
+                    name,
+                    path,
+                    folder.display(),
+                    e
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Where the config file is read from: ~/.sonik/config.toml for setups
+// from before XDG directories were followed, otherwise sonik/config.toml in
+// the config directory, ~/.config on Linux
+pub fn config_path() -> PathBuf {
+    let legacy = home_dir().unwrap().join(".sonik").join("config.toml");
+    if legacy.exists() {
+        return legacy;
+    }
+
+    match config_dir() {
+        Some(folder) => folder.join("sonik").join("config.toml"),
+        None => legacy,
+    }
+}
+
+// Top-level settings in the file that sonik doesn't know about, usually
+// typos, which would otherwise be dropped without a word
+fn unknown_settings(config_string: &str) -> Vec<String> {
+    let known = match toml::Value::try_from(Config::default()) {
+        Ok(toml::Value::Table(t)) => t,
+        _ => return Vec::new(),
+    };
+
+    match toml::from_str::<toml::Value>(config_string) {
+        Ok(toml::Value::Table(t)) => t
+            .keys()
+            .filter(|k| !known.contains_key(*k))
+            .map(|k| format!("unknown setting '{}' in the config is ignored", k))
+            .collect(),
+        _ => Vec::new(),
     }
 }
 
+// Written on first run. The paths are set, while everything else is left
+// commented out at its default to show what can be changed
+const DEFAULT_CONFIG: &str = r##"# sonik configuration. Lines starting with # are left at their defaults,
+# remove the # and change the value to use something else
+
+# Where the music is, scanned on first run and with --rebuild
+music_folder = {music_folder}
+# Where the database, stats and session are kept
+data_folder = {data_folder}
+database_path = {database_path}
+stats_path = {stats_path}
+# Scans, unreadable files and playback problems are logged here
+log_path = {log_path}
+# Album art and the search index, which are built again if deleted
+cache_folder = {cache_folder}
+
+# How duplicates are recognized while scanning, "path" or "content"
+# dedupe = "path"
+# Let the current track finish before the sleep timer stops playback
+# sleep_after_track = false
+# Level out volume with ReplayGain tags, "off", "track" or "album"
+# replaygain = "off"
+# Start playing the last track from where it was left on launch
+# resume_on_start = false
+# Ask before clearing the queue
+# confirm_clear = true
+# Milliseconds to fade in and out, 0 turning fading off
+# fade_ms = 200
+# Cover of the playing album, "off", "auto", "kitty", "sixel" or "blocks"
+# album_art = "off"
+# What separates the names in artist tags crediting more than one
+# artist_separators = [";", "/"]
+# An album in one folder with tracks by this many different artists and no
+# album artist tag is grouped under various_artists. 0 turns this off
+# compilation_artists = 3
+# various_artists = "Various Artists"
+# Skipped while scanning, as in .gitignore. A .sonikignore file in any
+# folder of the library works the same way
+# exclude = ["Audiobooks/", "Samples/"]
+# Scan into symlinked folders, e.g. albums kept on another drive
+# follow_symlinks = false
+# When to scan the music folder on startup: "always", "if-missing" (only
+# when there is no library yet) or "never". Otherwise run sonik -r to scan
+# scan_on_startup = "if-missing"
+# Most search results shown, best matches first, 0 showing them all
+# search_limit = 50
+# Clicking and scrolling, which gets in the way of selecting text
+# mouse = false
+# Go round from the end of a list to the start instead of stopping
+# wrap = true
+# Played tracks remembered for going back and the recently played tab
+# history_length = 100
+# Volume to start at, in percent from 0 to 100
+# default_volume = 100
+# What repeats to start with, "off", "one" (the track) or "all" (the queue)
+# default_repeat = "off"
+# Start with tracks added to the queue going in at random places
+# default_shuffle = false
+# Tracks at least this many minutes long, like audiobooks and podcasts,
+# start where they were left off when played again. 0 turns this off
+# resume_min_minutes = 20
+# Equalizer gain in dB from -12 to 12 for 60Hz, 250Hz, 1kHz, 4kHz and
+# 12kHz. Changes made in sonik are remembered in place of this
+# equalizer = [0, 0, 0, 0, 0]
+
+# Actions mapped to one or more space-separated keys
+# [keybindings]
+# play_pause = "p"
+# first = "g home"
+
+# "classic", "gruvbox" or "nord", with any color set on top
+# [theme]
+# preset = "classic"
+# highlight = "#fabd2f"
+"##;
+
 fn write_default_config(path: &Path) -> Option<()> {
     // Get the default config and create the necessary folders
     let default_config = Config::default();
-    fs::create_dir_all(default_config.data_folder).unwrap();
+    fs::create_dir_all(&default_config.data_folder).ok()?;
+    fs::create_dir_all(path.parent()?).ok()?;
+
+    // Paths are quoted the way TOML wants them
+    let quoted = |s: &str| toml::Value::String(s.to_string()).to_string();
+    let config_as_str = DEFAULT_CONFIG
+        .replace("{music_folder}", &quoted(&default_config.music_folder))
+        .replace("{data_folder}", &quoted(&default_config.data_folder))
+        .replace("{database_path}", &quoted(&default_config.database_path))
+        .replace("{stats_path}", &quoted(&default_config.stats_path))
+        .replace("{log_path}", &quoted(&default_config.log_path))
+        .replace("{cache_folder}", &quoted(&default_config.cache_folder));
 
-    // Save the configuration info to a TOML file in the data folder
-    let config_as_str = toml::to_string(&Config::default()).unwrap();
-    fs::write(path.to_string_lossy().into_owned(), config_as_str).ok()
+    fs::write(path, config_as_str).ok()
 }
@@ -0,0 +1,284 @@
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::{self as channel, Sender};
+use log::{info, warn};
+use serde_derive::Deserialize;
+use serde_json::{json, Value};
+
+use crate::application::config::{Config, RepeatMode};
+use crate::application::queue::SonikQueue;
+use crate::application::state::{audio_channels, Audio, PlaybackState, UiChannels};
+use crate::storage::library::Library;
+use crate::storage::playlist::open_paths;
+use crate::storage::record::Track;
+
+// Commands accepted on the control socket, one JSON object per line, e.g.
+// {"command": "enqueue", "path": "/music/album"}
+#[derive(Deserialize, Debug)]
+#[serde(tag = "command", rename_all = "lowercase")]
+enum Request {
+    // Resume, or start on the queue. A path plays those files right away
+    Play {
+        #[serde(default)]
+        path: Option<String>,
+    },
+    Pause,
+    Next,
+    Enqueue {
+        path: String,
+    },
+    Status,
+    Quit,
+}
+
+// Where the control socket goes unless SONIK_SOCKET says otherwise
+pub fn socket_path(config: &Config) -> PathBuf {
+    match env::var_os("SONIK_SOCKET") {
+        Some(path) => PathBuf::from(path),
+        None => Path::new(&config.data_folder).join("sonik.sock"),
+    }
+}
+
+// Play music without the interface, taking commands from the control
+// socket instead of the keyboard. Runs until told to quit
+pub fn run(config: &Config, mut library: Library, files: Vec<Track>) -> Result<(), ()> {
+    let path = socket_path(config);
+
+    // A socket left behind by a daemon that didn't shut down cleanly
+    // would stop a new one from binding
+    if path.exists() {
+        fs::remove_file(&path).map_err(|_| ())?;
+    }
+    let listener = UnixListener::bind(&path).map_err(|_| ())?;
+    info!("Listening on {}", path.display());
+
+    let (request_tx, request_rx) = channel::unbounded();
+    thread::spawn(move || listen(listener, request_tx));
+
+    let mut player = Player::new(config)?;
+    for track in files {
+        player.queue.add(track);
+    }
+
+    loop {
+        if let Ok((request, reply)) = request_rx.recv_timeout(Duration::from_millis(100)) {
+            let quit = matches!(request, Request::Quit);
+            let response = player.handle(request, &mut library, config);
+            if reply.send(response).is_err() {
+                warn!("A sonikctl client went away before its answer");
+            }
+            if quit {
+                break;
+            }
+        }
+
+        player.check_finished(&mut library);
+    }
+
+    fs::remove_file(&path).ok();
+    library.save(config)
+}
+
+// Accept connections, each on its own thread, and pass their commands on
+// to the player along with where the answer should go
+fn listen(listener: UnixListener, requests: Sender<(Request, Sender<Value>)>) {
+    for stream in listener.incoming().filter_map(|s| s.ok()) {
+        let requests = requests.clone();
+        thread::spawn(move || serve(stream, requests));
+    }
+}
+
+fn serve(stream: UnixStream, requests: Sender<(Request, Sender<Value>)>) {
+    let mut writer = match stream.try_clone() {
+        Ok(w) => w,
+        Err(_) => return,
+    };
+
+    for line in BufReader::new(stream).lines() {
+        let line = match line {
+            Ok(l) => l,
+            Err(_) => return,
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let response = match serde_json::from_str::<Request>(&line) {
+            Ok(request) => {
+                let (reply_tx, reply_rx) = channel::bounded(1);
+                if requests.send((request, reply_tx)).is_err() {
+                    return;
+                }
+                reply_rx
+                    .recv()
+                    .unwrap_or_else(|_| error("sonik is shutting down"))
+            }
+            Err(e) => error(&format!("bad command: {}", e)),
+        };
+
+        if writeln!(writer, "{}", response).is_err() {
+            return;
+        }
+    }
+}
+
+fn error(message: &str) -> Value {
+    json!({ "ok": false, "error": message })
+}
+
+// The audio and queue without the interface around them. Tracks are played
+// one after the other from this thread, so the interface's ends of the
+// channels Audio normally uses are only kept so its sends don't fail
+struct Player {
+    audio: Audio,
+    queue: SonikQueue,
+    now_playing: Option<Track>,
+    // Playback position is elapsed_offset plus the time since resumed_at
+    elapsed_offset: Duration,
+    resumed_at: Option<Instant>,
+    _unread: UiChannels,
+}
+
+impl Player {
+    fn new(config: &Config) -> Result<Player, ()> {
+        let device = rodio::default_output_device().ok_or(())?;
+
+        let (channels, unread) = audio_channels();
+        let mut audio = Audio::new(
+            device,
+            channels,
+            config.replaygain,
+            Duration::from_millis(config.fade_ms),
+        );
+        audio.set_volume(config.default_volume as f32 / 100.0);
+        audio.set_equalizer(config.equalizer);
+
+        // There is no looping a single track here, so only repeating the
+        // whole queue carries over
+        let mut queue = SonikQueue::new();
+        queue.repeat = config.default_repeat == RepeatMode::All;
+        queue.shuffling = config.default_shuffle;
+
+        Ok(Player {
+            audio,
+            queue,
+            now_playing: None,
+            elapsed_offset: Duration::from_secs(0),
+            resumed_at: None,
+            _unread: unread,
+        })
+    }
+
+    fn handle(&mut self, request: Request, library: &mut Library, config: &Config) -> Value {
+        match request {
+            Request::Play { path: Some(path) } => {
+                let tracks = open_paths(&[Path::new(&path)], library, &config.artist_separators);
+                if tracks.is_empty() {
+                    return error("nothing to play there");
+                }
+                for track in tracks.into_iter().rev() {
+                    self.queue.add_to_front(track);
+                }
+                self.play_next();
+            }
+            Request::Play { path: None } => {
+                if self.now_playing.is_none() {
+                    self.play_next();
+                } else if self.resumed_at.is_none() {
+                    self.audio.pause_play();
+                    self.resumed_at = Some(Instant::now());
+                }
+            }
+            Request::Pause => {
+                if let Some(resumed_at) = self.resumed_at.take() {
+                    self.audio.pause_play();
+                    self.elapsed_offset += resumed_at.elapsed();
+                }
+            }
+            // Skipped tracks don't count as played
+            Request::Next => self.play_next(),
+            Request::Enqueue { path } => {
+                let tracks = open_paths(&[Path::new(&path)], library, &config.artist_separators);
+                if tracks.is_empty() {
+                    return error("nothing to enqueue there");
+                }
+                for track in tracks {
+                    self.queue.add(track);
+                }
+            }
+            Request::Status => return self.status(),
+            Request::Quit => self.audio.stop(),
+        }
+
+        json!({ "ok": true })
+    }
+
+    fn status(&self) -> Value {
+        let state = match self.audio.state() {
+            PlaybackState::Stopped => "stopped",
+            PlaybackState::Paused => "paused",
+            PlaybackState::Playing => "playing",
+        };
+        let elapsed = match self.resumed_at {
+            Some(resumed_at) => self.elapsed_offset + resumed_at.elapsed(),
+            None => self.elapsed_offset,
+        };
+
+        json!({
+            "ok": true,
+            "state": state,
+            "title": self.now_playing.as_ref().map(|t| &t.title),
+            "artist": self.now_playing.as_ref().map(|t| &t.artist),
+            "album": self.now_playing.as_ref().map(|t| &t.album),
+            "file_path": self.now_playing.as_ref().map(|t| &t.file_path),
+            "elapsed_ms": elapsed.as_millis() as u64,
+            "queue": self.queue.tracks.len(),
+        })
+    }
+
+    // Start the head of the queue, passing over anything unreadable, or
+    // stop when the queue runs out. A repeating queue gets one time round
+    // before it is given up on as unplayable
+    fn play_next(&mut self) {
+        let audio = &mut self.audio;
+        let started = self.queue.take_playable(|track| {
+            let started = audio.play(track.clone(), Duration::from_secs(0)).is_ok();
+            if !started {
+                warn!("Could not play {}", track.file_path);
+            }
+            started
+        });
+        if let Some(track) = started {
+            self.elapsed_offset = track.start_at(Duration::from_secs(0));
+            self.resumed_at = Some(Instant::now());
+            self.now_playing = Some(track);
+            return;
+        }
+
+        self.audio.stop();
+        self.now_playing = None;
+        self.elapsed_offset = Duration::from_secs(0);
+        self.resumed_at = None;
+    }
+
+    // Once the sink runs dry the track was heard to the end, so it counts
+    // as played and the queue moves on
+    fn check_finished(&mut self, library: &mut Library) {
+        if self.resumed_at.is_none() || !self.audio.sink.empty() {
+            return;
+        }
+
+        if let Some(track) = self.now_playing.take() {
+            if let Some(t) = library.find_by_path_mut(Path::new(&track.file_path)) {
+                t.play_count += 1;
+            }
+        }
+        self.play_next();
+    }
+}
@@ -0,0 +1,197 @@
+use std::f32::consts::PI;
+use std::time::Duration;
+
+use rodio::Source;
+
+// Centre frequency of each band in Hz, low to high
+pub const BANDS: [f32; 5] = [60.0, 250.0, 1000.0, 4000.0, 12000.0];
+
+// Gain of each band in dB, 0 leaving it as it is
+pub type Gains = [f32; 5];
+
+// Furthest a band can be turned up or down, in dB
+pub const MAX_GAIN: f32 = 12.0;
+
+pub const FLAT: Gains = [0.0; 5];
+
+// Settings to start from, picked in turn with the eq_preset key
+pub const PRESETS: [(&str, Gains); 3] = [
+    ("flat", FLAT),
+    ("bass boost", [6.0, 4.0, 0.0, 0.0, 0.0]),
+    ("vocal", [-2.0, -1.0, 3.0, 4.0, 1.0]),
+];
+
+// Width of each band. About an octave and a half either side, so that
+// neighbouring bands overlap and the response has no dips in between
+const Q: f32 = 1.0;
+
+// Name of the preset the gains match, if any
+pub fn preset_name(gains: &Gains) -> Option<&'static str> {
+    PRESETS
+        .iter()
+        .find(|(_, preset)| preset == gains)
+        .map(|(name, _)| *name)
+}
+
+// Peaking filter on one band, following the Audio EQ Cookbook
+// (https://www.w3.org/TR/audio-eq-cookbook/). Coefficients are
+// normalised by a0
+#[derive(Clone, Copy, Debug)]
+struct Coefficients {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+}
+
+impl Coefficients {
+    fn peaking(freq: f32, gain: f32, sample_rate: u32) -> Coefficients {
+        let a = 10f32.powf(gain / 40.0);
+        let w0 = 2.0 * PI * freq / sample_rate as f32;
+        let alpha = w0.sin() / (2.0 * Q);
+        let cos_w0 = w0.cos();
+        let a0 = 1.0 + alpha / a;
+
+        Coefficients {
+            b0: (1.0 + alpha * a) / a0,
+            b1: -2.0 * cos_w0 / a0,
+            b2: (1.0 - alpha * a) / a0,
+            a1: -2.0 * cos_w0 / a0,
+            a2: (1.0 - alpha / a) / a0,
+        }
+    }
+}
+
+// The last two samples in and out of one band on one channel
+#[derive(Clone, Copy, Debug, Default)]
+struct History {
+    x1: f32,
+    x2: f32,
+    y1: f32,
+    y2: f32,
+}
+
+impl History {
+    fn apply(&mut self, c: &Coefficients, x: f32) -> f32 {
+        let y = c.b0 * x + c.b1 * self.x1 + c.b2 * self.x2 - c.a1 * self.y1 - c.a2 * self.y2;
+        self.x2 = self.x1;
+        self.x1 = x;
+        self.y2 = self.y1;
+        self.y1 = y;
+
+        y
+    }
+}
+
+// A source run through a filter for each band that isn't flat. Each
+// channel keeps its own filter history, since the samples come in
+// interleaved
+pub struct Equalizer<S> {
+    input: S,
+    gains: Gains,
+    // Bands that do anything at the current sample rate
+    filters: Vec<Coefficients>,
+    // filters.len() entries for each channel, one channel after the other
+    history: Vec<History>,
+    sample_rate: u32,
+    channels: u16,
+    channel: usize,
+}
+
+impl<S> Equalizer<S>
+where
+    S: Source<Item = f32>,
+{
+    pub fn new(input: S, gains: Gains) -> Equalizer<S> {
+        let mut equalizer = Equalizer {
+            input,
+            gains,
+            filters: Vec::new(),
+            history: Vec::new(),
+            sample_rate: 0,
+            channels: 0,
+            channel: 0,
+        };
+        equalizer.reset();
+
+        equalizer
+    }
+
+    // Work the filters out for the input's current format. Bands at or
+    // above half the sample rate can't be played and are left out
+    fn reset(&mut self) {
+        let sample_rate = self.input.sample_rate();
+        let nyquist = sample_rate as f32 / 2.0;
+
+        self.filters = BANDS
+            .iter()
+            .zip(self.gains.iter())
+            .filter(|(freq, gain)| **gain != 0.0 && **freq < nyquist)
+            .map(|(freq, gain)| Coefficients::peaking(*freq, *gain, sample_rate))
+            .collect();
+        self.channels = self.input.channels();
+        self.history = vec![History::default(); self.filters.len() * self.channels as usize];
+        self.sample_rate = sample_rate;
+        self.channel = 0;
+    }
+}
+
+impl<S> Iterator for Equalizer<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        // The format can only change between frames
+        let last_in_frame = self.input.current_frame_len() == Some(1);
+        let sample = self.input.next()?;
+
+        let bands = self.filters.len();
+        let history = &mut self.history[self.channel * bands..(self.channel + 1) * bands];
+        let mut out = sample;
+        for (c, h) in self.filters.iter().zip(history.iter_mut()) {
+            out = h.apply(c, out);
+        }
+
+        self.channel += 1;
+        if self.channel >= self.channels as usize {
+            self.channel = 0;
+        }
+        if last_in_frame
+            && (self.input.sample_rate() != self.sample_rate
+                || self.input.channels() != self.channels)
+        {
+            self.reset();
+        }
+
+        // Boosted bands can push loud passages past full scale
+        Some(out.clamp(-1.0, 1.0))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.input.size_hint()
+    }
+}
+
+impl<S> Source for Equalizer<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.input.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.input.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+}
@@ -0,0 +1,184 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use rodio::Source;
+
+// Where the sources in one sink are fading to, set from the audio thread and
+// followed by the sources as they play, so that nothing has to wait on a fade
+#[derive(Clone)]
+pub struct Envelope(Arc<Levels>);
+
+struct Levels {
+    audible: AtomicBool,
+    // Set by the playing source once it has faded all the way out
+    silent: AtomicBool,
+}
+
+impl Envelope {
+    pub fn new(audible: bool) -> Envelope {
+        Envelope(Arc::new(Levels {
+            audible: AtomicBool::new(audible),
+            silent: AtomicBool::new(!audible),
+        }))
+    }
+
+    pub fn fade_in(&self) {
+        self.0.audible.store(true, Ordering::SeqCst);
+    }
+
+    pub fn fade_out(&self) {
+        self.0.audible.store(false, Ordering::SeqCst);
+    }
+
+    pub fn audible(&self) -> bool {
+        self.0.audible.load(Ordering::SeqCst)
+    }
+
+    pub fn is_silent(&self) -> bool {
+        !self.audible() && self.0.silent.load(Ordering::SeqCst)
+    }
+}
+
+// A source brought up and down in volume as its envelope says. Once faded out
+// it stops taking samples from the input and gives silence instead, a whole
+// frame at a time so the channels stay in order, which is what pausing is
+pub struct Fade<S> {
+    input: S,
+    envelope: Envelope,
+    fade: Duration,
+    fade_in: bool,
+    // Unset until the first sample, when the input's format is known
+    gain: Option<f32>,
+    step: f32,
+    channel: u16,
+    holding: bool,
+}
+
+impl<S> Fade<S>
+where
+    S: Source<Item = f32>,
+{
+    // A source started with fade_in comes up from silence, anything else
+    // starts where the envelope is, as a preloaded track shouldn't fade in
+    pub fn new(input: S, envelope: Envelope, fade: Duration, fade_in: bool) -> Fade<S> {
+        Fade {
+            input,
+            envelope,
+            fade,
+            fade_in,
+            gain: None,
+            step: 1.0,
+            channel: 0,
+            holding: false,
+        }
+    }
+
+    fn start(&mut self) -> f32 {
+        let samples = self.fade.as_secs_f32()
+            * self.input.sample_rate() as f32
+            * self.input.channels() as f32;
+        if samples >= 1.0 {
+            self.step = 1.0 / samples;
+        }
+
+        if self.fade_in || !self.envelope.audible() {
+            0.0
+        } else {
+            1.0
+        }
+    }
+}
+
+impl<S> Iterator for Fade<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let mut gain = match self.gain {
+            Some(gain) => gain,
+            None => self.start(),
+        };
+
+        let target = if self.envelope.audible() { 1.0 } else { 0.0 };
+        if gain < target {
+            gain = (gain + self.step).min(target);
+        } else if gain > target {
+            gain = (gain - self.step).max(target);
+        }
+        self.gain = Some(gain);
+
+        // Holding only starts and ends between frames
+        if self.channel == 0 {
+            self.holding = gain == 0.0 && target == 0.0;
+            self.envelope.0.silent.store(self.holding, Ordering::SeqCst);
+        }
+        self.channel += 1;
+        if self.channel >= self.input.channels() {
+            self.channel = 0;
+        }
+
+        if self.holding {
+            return Some(0.0);
+        }
+        self.input.next().map(|sample| sample * gain)
+    }
+}
+
+impl<S> Source for Fade<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.input.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.input.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+
+    // A second of stereo at full level. The rate and fade length make the
+    // steps exact, 32 samples from silence to full
+    fn input() -> SamplesBuffer<f32> {
+        SamplesBuffer::new(2, 1024, vec![1.0; 2048])
+    }
+
+    #[test]
+    fn fades_without_losing_samples_while_held() {
+        let envelope = Envelope::new(true);
+        let fade_len = Duration::from_micros(15625);
+        let mut fade = Fade::new(input(), envelope.clone(), fade_len, true);
+
+        let first: Vec<f32> = fade.by_ref().take(32).collect();
+        assert_eq!(first[0], 1.0 / 32.0);
+        assert_eq!(first[31], 1.0);
+        assert!(!envelope.is_silent());
+
+        envelope.fade_out();
+        let faded: Vec<f32> = fade.by_ref().take(32).collect();
+        assert_eq!(faded[31], 0.0);
+        let held: Vec<f32> = fade.by_ref().take(100).collect();
+        assert!(held.iter().all(|&s| s == 0.0));
+        assert!(envelope.is_silent());
+
+        // Everything left of the input plays once faded back in
+        envelope.fade_in();
+        assert_eq!(fade.count(), 2048 - 64);
+    }
+}
@@ -0,0 +1,455 @@
+use std::collections::HashMap;
+
+use termion::event::Key;
+
+// Everything a key can be bound to in the [keybindings] section of the config
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    Quit,
+    PlayPause,
+    Play,
+    Enqueue,
+    EnqueueShuffled,
+    OpenFolder,
+    Mark,
+    PlayNext,
+    Next,
+    Previous,
+    SeekBack,
+    SeekForward,
+    ClearQueue,
+    Shuffle,
+    DedupeQueue,
+    ClearPlayed,
+    SortQueue,
+    ShuffleArtist,
+    Repeat,
+    ShuffleMode,
+    LoopTrack,
+    SpeedUp,
+    SpeedDown,
+    SpeedReset,
+    EqPreset,
+    BalanceLeft,
+    BalanceRight,
+    SetStart,
+    AddBookmark,
+    NextBookmark,
+    SortByPlays,
+    AlbumSort,
+    Favorite,
+    RateUp,
+    RateDown,
+    Reveal,
+    GoToArtist,
+    GoToAlbum,
+    RandomAlbum,
+    RandomTrack,
+    SleepTimer,
+    CancelSleep,
+    Jump,
+    NextLetter,
+    PrevLetter,
+    Help,
+    Lyrics,
+    TabQueue,
+    TabLibrary,
+    TabSearch,
+    TabFavorites,
+    TabSettings,
+    TabRecent,
+    TabPlayed,
+    NextTab,
+    PrevTab,
+    Up,
+    Down,
+    Left,
+    Right,
+    First,
+    Last,
+    PageUp,
+    PageDown,
+}
+
+// Config name of each action, along with the keys it gets when left unmapped
+const DEFAULTS: [(Action, &str, &str); 64] = [
+    (Action::Quit, "quit", "esc"),
+    (Action::PlayPause, "play_pause", "p"),
+    (Action::Play, "play", "enter"),
+    (Action::Enqueue, "enqueue", "space"),
+    (Action::EnqueueShuffled, "enqueue_shuffled", "E"),
+    (Action::OpenFolder, "open_folder", "F"),
+    (Action::Mark, "mark", "x"),
+    (Action::PlayNext, "play_next", "n"),
+    (Action::Next, "next", ">"),
+    (Action::Previous, "previous", "<"),
+    (Action::SeekBack, "seek_back", ","),
+    (Action::SeekForward, "seek_forward", "."),
+    (Action::ClearQueue, "clear_queue", "c"),
+    (Action::Shuffle, "shuffle", "s"),
+    (Action::DedupeQueue, "dedupe_queue", "d"),
+    (Action::ClearPlayed, "clear_played", "D"),
+    (Action::SortQueue, "sort_queue", "O"),
+    (Action::ShuffleArtist, "shuffle_artist", "a"),
+    (Action::Repeat, "repeat", "r"),
+    (Action::ShuffleMode, "shuffle_mode", "ctrl-s"),
+    (Action::LoopTrack, "loop_track", "l"),
+    (Action::SpeedUp, "speed_up", "]"),
+    (Action::SpeedDown, "speed_down", "["),
+    (Action::SpeedReset, "speed_reset", "="),
+    (Action::EqPreset, "eq_preset", "e"),
+    (Action::BalanceLeft, "balance_left", "("),
+    (Action::BalanceRight, "balance_right", ")"),
+    (Action::SetStart, "set_start", "S"),
+    (Action::AddBookmark, "add_bookmark", "b"),
+    (Action::NextBookmark, "next_bookmark", "'"),
+    (Action::SortByPlays, "sort_by_plays", "m"),
+    (Action::AlbumSort, "album_sort", "o"),
+    (Action::Favorite, "favorite", "f"),
+    (Action::RateUp, "rate_up", "+"),
+    (Action::RateDown, "rate_down", "-"),
+    (Action::Reveal, "reveal", "v"),
+    (Action::GoToArtist, "go_to_artist", "A"),
+    (Action::GoToAlbum, "go_to_album", "B"),
+    (Action::RandomAlbum, "random_album", "R"),
+    (Action::RandomTrack, "random_track", "T"),
+    (Action::SleepTimer, "sleep_timer", "z"),
+    (Action::CancelSleep, "cancel_sleep", "Z"),
+    (Action::Jump, "jump", "/"),
+    (Action::NextLetter, "next_letter", "}"),
+    (Action::PrevLetter, "prev_letter", "{"),
+    (Action::Help, "help", "?"),
+    (Action::Lyrics, "lyrics", "L"),
+    (Action::TabQueue, "tab_queue", "1"),
+    (Action::TabLibrary, "tab_library", "2"),
+    (Action::TabSearch, "tab_search", "3"),
+    (Action::TabFavorites, "tab_favorites", "4"),
+    (Action::TabSettings, "tab_settings", "5"),
+    (Action::TabRecent, "tab_recent", "6"),
+    (Action::TabPlayed, "tab_played", "7"),
+    (Action::NextTab, "next_tab", "tab"),
+    (Action::PrevTab, "prev_tab", "backtab"),
+    (Action::Up, "up", "up"),
+    (Action::Down, "down", "down"),
+    (Action::Left, "left", "left"),
+    (Action::Right, "right", "right"),
+    (Action::First, "first", "g home"),
+    (Action::Last, "last", "G end"),
+    (Action::PageUp, "page_up", "pgup ctrl-u"),
+    (Action::PageDown, "page_down", "pgdn ctrl-d"),
+];
+
+// What the help overlay lists, grouped by where the keys apply. An action
+// can show up under more than one heading when it does something different
+// in each place
+const HELP: [(&str, &[(Action, &str)]); 6] = [
+    (
+        "global",
+        &[
+            (Action::PlayPause, "play/pause"),
+            (Action::Next, "next track"),
+            (Action::Previous, "previous track"),
+            (Action::SeekBack, "back 10 seconds"),
+            (Action::SeekForward, "forward 10 seconds"),
+            (Action::ClearQueue, "stop and clear the queue"),
+            (Action::Repeat, "repeat off, queue or track"),
+            (Action::ShuffleMode, "shuffle mode on/off"),
+            (Action::LoopTrack, "loop the current track"),
+            (Action::SpeedUp, "play faster"),
+            (Action::SpeedDown, "play slower"),
+            (Action::SpeedReset, "normal speed"),
+            (Action::EqPreset, "next equalizer preset"),
+            (Action::BalanceLeft, "balance towards the left"),
+            (Action::BalanceRight, "balance towards the right"),
+            (Action::SetStart, "start the track here from now on"),
+            (Action::AddBookmark, "bookmark this spot in the track"),
+            (Action::NextBookmark, "go to the track's next bookmark"),
+            (Action::OpenFolder, "queue a folder outside the library"),
+            (Action::Favorite, "star/unstar track"),
+            (Action::RateUp, "rate selected track a star higher"),
+            (Action::RateDown, "rate selected track a star lower"),
+            (Action::Reveal, "show playing track in library"),
+            (Action::GoToArtist, "show playing artist in library"),
+            (Action::GoToAlbum, "show playing album in library"),
+            (Action::RandomAlbum, "show a random album in library"),
+            (Action::RandomTrack, "play a random track now"),
+            (Action::SleepTimer, "add 15 minutes to sleep timer"),
+            (Action::CancelSleep, "cancel sleep timer"),
+            (Action::TabQueue, "queue tab"),
+            (Action::TabLibrary, "library tab"),
+            (Action::TabSearch, "search tab"),
+            (Action::TabFavorites, "favorites tab"),
+            (Action::TabSettings, "settings tab"),
+            (Action::TabRecent, "recently added tab"),
+            (Action::TabPlayed, "recently played tab"),
+            (Action::NextTab, "next tab"),
+            (Action::PrevTab, "previous tab"),
+            (Action::Up, "move up"),
+            (Action::Down, "move down"),
+            (Action::First, "jump to top of list"),
+            (Action::Last, "jump to bottom of list"),
+            (Action::PageUp, "page up"),
+            (Action::PageDown, "page down"),
+            (Action::Help, "show/hide this help"),
+            (Action::Lyrics, "show/hide lyrics of the playing track"),
+            (Action::Quit, "quit"),
+        ],
+    ),
+    (
+        "library",
+        &[
+            (Action::Play, "play (track/album) now"),
+            (Action::Enqueue, "add (track/album/artist) to queue"),
+            (
+                Action::EnqueueShuffled,
+                "add album/artist to queue, shuffled",
+            ),
+            (Action::PlayNext, "play (track/album/artist) next"),
+            (Action::Mark, "mark/unmark, then add all marked at once"),
+            (Action::ShuffleArtist, "play all by artist, shuffled"),
+            (Action::SortByPlays, "sort tracks by play count"),
+            (Action::AlbumSort, "sort albums by title/year"),
+            (Action::Left, "previous column"),
+            (Action::Right, "next column"),
+            (Action::Jump, "jump to name by typing"),
+            (Action::NextLetter, "artists under the next letter"),
+            (Action::PrevLetter, "artists under the previous letter"),
+        ],
+    ),
+    (
+        "queue",
+        &[
+            (Action::Play, "play selected track"),
+            (Action::Left, "back 10 seconds"),
+            (Action::Right, "forward 10 seconds"),
+            (Action::Shuffle, "shuffle the queue after the next track"),
+            (Action::DedupeQueue, "remove repeated tracks"),
+            (Action::ClearPlayed, "remove tracks already played"),
+            (
+                Action::SortQueue,
+                "sort by artist/album/title/length, or undo",
+            ),
+            (Action::Jump, "find in queue, up/down to cycle"),
+        ],
+    ),
+    (
+        "search",
+        &[
+            (Action::Play, "play selected result, all of an artist/album"),
+            (Action::Enqueue, "add selected result to queue"),
+            (Action::Mark, "mark/unmark, then add all marked at once"),
+        ],
+    ),
+    (
+        "settings",
+        &[
+            (Action::Play, "switch to selected output device"),
+            (Action::Left, "previous equalizer band"),
+            (Action::Right, "next equalizer band"),
+            (Action::RateUp, "turn band up 1 dB"),
+            (Action::RateDown, "turn band down 1 dB"),
+        ],
+    ),
+    (
+        "played",
+        &[
+            (Action::Play, "play selected track"),
+            (Action::Enqueue, "add selected track to queue"),
+            (
+                Action::SortByPlays,
+                "switch between recently and most played",
+            ),
+        ],
+    ),
+];
+
+impl Action {
+    fn from_name(name: &str) -> Option<Action> {
+        DEFAULTS
+            .iter()
+            .find(|(_, n, _)| *n == name)
+            .map(|(action, _, _)| *action)
+    }
+
+    pub fn name(self) -> &'static str {
+        DEFAULTS
+            .iter()
+            .find(|(action, _, _)| *action == self)
+            .map(|(_, name, _)| *name)
+            .unwrap()
+    }
+}
+
+// Lookup from a keypress to the action it is bound to
+pub struct Keybindings {
+    bindings: HashMap<Key, Action>,
+}
+
+impl Keybindings {
+    // Build the lookup from the config, where each action maps to one or
+    // more space-separated keys, e.g. `first = "g home"`. Actions left out
+    // keep their default keys. Anything that can't be used is reported
+    pub fn new(config: &HashMap<String, String>) -> Keybindings {
+        let mut bindings = HashMap::new();
+
+        // Sorted so that conflicts are reported the same way on every launch
+        let mut mapped: Vec<(&String, &String)> = config.iter().collect();
+        mapped.sort();
+
+        let mut configured = Vec::new();
+        for (name, keys) in mapped {
+            match Action::from_name(name) {
+                Some(action) => {
+                    configured.push(action);
+                    bind(&mut bindings, action, keys);
+                }
+                None => println!("Warning: unknown keybinding action '{}'", name),
+            }
+        }
+
+        for (action, _, keys) in DEFAULTS.iter() {
+            if !configured.contains(action) {
+                bind(&mut bindings, *action, keys);
+            }
+        }
+
+        Keybindings { bindings }
+    }
+
+    pub fn action(&self, key: Key) -> Option<Action> {
+        self.bindings.get(&key).copied()
+    }
+
+    // Keys currently bound to an action, as they would be written in the
+    // config
+    pub fn keys(&self, action: Action) -> Vec<String> {
+        let mut keys: Vec<String> = self
+            .bindings
+            .iter()
+            .filter(|(_, a)| **a == action)
+            .map(|(k, _)| key_name(*k))
+            .collect();
+        keys.sort();
+        keys
+    }
+
+    // Headings of the help overlay, each with the keys and what they do.
+    // Actions with no key left are skipped
+    pub fn help(&self) -> Vec<(&'static str, Vec<(String, &'static str)>)> {
+        HELP.iter()
+            .map(|(heading, actions)| {
+                let lines = actions
+                    .iter()
+                    .map(|(action, description)| (self.keys(*action).join(" "), *description))
+                    .filter(|(keys, _)| !keys.is_empty())
+                    .collect();
+                (*heading, lines)
+            })
+            .collect()
+    }
+}
+
+fn bind(bindings: &mut HashMap<Key, Action>, action: Action, keys: &str) {
+    for k in keys.split_whitespace() {
+        let key = match parse_key(k) {
+            Some(key) => key,
+            None => {
+                println!("Warning: unknown key '{}' for '{}'", k, action.name());
+                continue;
+            }
+        };
+
+        // The first binding of a key wins, configured ones come first
+        match bindings.get(&key) {
+            Some(other) if *other != action => println!(
+                "Warning: key '{}' is bound to both '{}' and '{}', using '{}'",
+                k,
+                other.name(),
+                action.name(),
+                other.name()
+            ),
+            Some(_) => {}
+            None => {
+                bindings.insert(key, action);
+            }
+        }
+    }
+}
+
+// Keys are written as a single character, a name such as "space" or "pgup",
+// or with a "ctrl-"/"alt-" prefix
+fn parse_key(k: &str) -> Option<Key> {
+    let mut chars = k.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Some(Key::Char(c));
+    }
+
+    if let Some(i) = k.find('-') {
+        let rest = single_char(&k[i + 1..]);
+        return match k[..i].to_lowercase().as_str() {
+            "ctrl" => rest.map(Key::Ctrl),
+            "alt" => rest.map(Key::Alt),
+            _ => None,
+        };
+    }
+
+    let lower = k.to_lowercase();
+    if let Some(n) = lower.strip_prefix('f').and_then(|n| n.parse().ok()) {
+        return Some(Key::F(n));
+    }
+
+    match lower.as_str() {
+        "space" => Some(Key::Char(' ')),
+        "enter" | "return" => Some(Key::Char('\n')),
+        "tab" => Some(Key::Char('\t')),
+        "backtab" => Some(Key::BackTab),
+        "esc" | "escape" => Some(Key::Esc),
+        "backspace" => Some(Key::Backspace),
+        "delete" | "del" => Some(Key::Delete),
+        "insert" | "ins" => Some(Key::Insert),
+        "up" => Some(Key::Up),
+        "down" => Some(Key::Down),
+        "left" => Some(Key::Left),
+        "right" => Some(Key::Right),
+        "home" => Some(Key::Home),
+        "end" => Some(Key::End),
+        "pgup" | "pageup" => Some(Key::PageUp),
+        "pgdn" | "pagedown" => Some(Key::PageDown),
+        _ => None,
+    }
+}
+
+// The reverse of parse_key
+fn key_name(key: Key) -> String {
+    match key {
+        Key::Char(' ') => "space".to_string(),
+        Key::Char('\n') => "enter".to_string(),
+        Key::Char('\t') => "tab".to_string(),
+        Key::Char(c) => c.to_string(),
+        Key::Ctrl(c) => format!("ctrl-{}", c),
+        Key::Alt(c) => format!("alt-{}", c),
+        Key::F(n) => format!("f{}", n),
+        Key::BackTab => "backtab".to_string(),
+        Key::Esc => "esc".to_string(),
+        Key::Backspace => "backspace".to_string(),
+        Key::Delete => "delete".to_string(),
+        Key::Insert => "insert".to_string(),
+        Key::Up => "up".to_string(),
+        Key::Down => "down".to_string(),
+        Key::Left => "left".to_string(),
+        Key::Right => "right".to_string(),
+        Key::Home => "home".to_string(),
+        Key::End => "end".to_string(),
+        Key::PageUp => "pgup".to_string(),
+        Key::PageDown => "pgdn".to_string(),
+        _ => "?".to_string(),
+    }
+}
+
+fn single_char(s: &str) -> Option<char> {
+    let mut chars = s.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(c),
+        _ => None,
+    }
+}
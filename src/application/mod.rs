@@ -1,3 +1,10 @@
+pub mod balance;
 pub mod config;
+pub mod daemon;
+pub mod equalizer;
+pub mod fade;
+pub mod keybindings;
 pub mod queue;
+pub mod session;
+pub mod skip;
 pub mod state;
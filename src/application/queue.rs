@@ -1,5 +1,5 @@
-use rand::thread_rng;
-use std::collections::VecDeque;
+use rand::{thread_rng, Rng};
+use std::collections::{HashMap, HashSet, VecDeque};
 
 use crate::storage::record::Track;
 
@@ -38,10 +38,61 @@ impl<T> LenAndSwap for VecDeque<T> {
 
 // Thanks to StackOverflow for the bit of code above.
 
+// What the queue can be sorted by. Artist and album sorts keep each
+// album's tracks in order
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QueueSortField {
+    Artist,
+    Album,
+    Title,
+    Duration,
+}
+
+impl QueueSortField {
+    // Each field in turn, then back to no sorting
+    pub fn after(field: Option<QueueSortField>) -> Option<QueueSortField> {
+        match field {
+            None => Some(QueueSortField::Artist),
+            Some(QueueSortField::Artist) => Some(QueueSortField::Album),
+            Some(QueueSortField::Album) => Some(QueueSortField::Title),
+            Some(QueueSortField::Title) => Some(QueueSortField::Duration),
+            Some(QueueSortField::Duration) => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            QueueSortField::Artist => "artist",
+            QueueSortField::Album => "album",
+            QueueSortField::Title => "title",
+            QueueSortField::Duration => "duration",
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct SonikQueue {
     pub tracks: VecDeque<Track>,
     pub total_time: u32,
+    // How many tracks at the front are the up next lane: tracks to be
+    // played next, in the order they were added, before the rest of the
+    // queue carries on. Shuffling and sorting leave them alone
+    pub up_next: usize,
+    pub sorted_by: Option<QueueSortField>,
+    pub shuffled: bool,
+    // Repeat all: each track goes back on the end of the queue as it is
+    // taken off to play, so the queue goes round. The tracks added since go
+    // in ahead of those
+    pub repeat: bool,
+    // Shuffle mode: tracks added go in at random places after the next
+    // one, rather than on the end
+    pub shuffling: bool,
+    // Paths of the tracks in the order they were queued, from before the
+    // queue was sorted or shuffled, so that can be undone
+    unsorted: Vec<String>,
+    // How many tracks at the end of the queue are ones repeat put back
+    // after they played. Sorting and shuffling leave them alone too
+    played: usize,
 }
 
 impl SonikQueue {
@@ -49,36 +100,350 @@ impl SonikQueue {
         SonikQueue {
             tracks: VecDeque::<Track>::new(),
             total_time: 0,
+            up_next: 0,
+            sorted_by: None,
+            shuffled: false,
+            repeat: false,
+            shuffling: false,
+            unsorted: Vec::new(),
+            played: 0,
         }
     }
 
     pub fn add(&mut self, track: Track) {
         self.total_time += &track.duration;
-        self.tracks.push_back(track);
+        let keep = self.up_next.max(1);
+        let end = self.to_come();
+        let index = if self.shuffling && end > keep {
+            thread_rng().gen_range(keep, end + 1)
+        } else {
+            end
+        };
+        self.tracks.insert(index, track);
+    }
+
+    // Where the tracks repeat put back start
+    fn to_come(&self) -> usize {
+        self.tracks.len() - self.played
     }
 
+    // Play this before anything else, up next included
     pub fn add_to_front(&mut self, track: Track) {
         self.total_time += &track.duration;
         self.tracks.push_front(track);
+        self.up_next += 1;
+    }
+
+    // Play this after the up next lane, ahead of the rest of the queue
+    pub fn add_next(&mut self, track: Track) {
+        self.total_time += &track.duration;
+        self.tracks.insert(self.up_next, track);
+        self.up_next += 1;
     }
 
     pub fn clear(&mut self) {
         self.tracks.clear();
         self.total_time = 0;
+        self.up_next = 0;
+        self.sorted_by = None;
+        self.shuffled = false;
+        self.unsorted.clear();
+        self.played = 0;
     }
 
     pub fn shuffle(&mut self) {
-        shuffle(&mut self.tracks, thread_rng());
+        let mut played = self.tracks.split_off(self.to_come());
+        let mut rest = self.tracks.split_off(self.up_next);
+        shuffle(&mut rest, thread_rng());
+        self.tracks.append(&mut rest);
+        self.tracks.append(&mut played);
+    }
+
+    // Shuffle what is still to come, leaving the track that plays next
+    // where it is, along with the rest of the up next lane. Undone by
+    // unsort like a sort is
+    pub fn shuffle_remaining(&mut self) {
+        let keep = self.up_next.max(1).min(self.to_come());
+        self.remember_order();
+
+        let mut played = self.tracks.split_off(self.to_come());
+        let mut rest = self.tracks.split_off(keep);
+        shuffle(&mut rest, thread_rng());
+        self.tracks.append(&mut rest);
+        self.tracks.append(&mut played);
+        self.sorted_by = None;
+        self.shuffled = true;
+    }
+
+    // Note the order tracks were queued in, unless a sort or shuffle
+    // already has
+    fn remember_order(&mut self) {
+        if self.sorted_by.is_none() && !self.shuffled {
+            self.unsorted = self.tracks.iter().map(|t| t.file_path.clone()).collect();
+        }
+    }
+
+    // Order the queue after the up next lane by a field, ignoring case. The
+    // order from before the first sort is remembered for unsort
+    pub fn sort_by_field(&mut self, field: QueueSortField) {
+        self.remember_order();
+
+        let mut played = self.tracks.split_off(self.to_come());
+        let mut tracks: Vec<Track> = self.tracks.drain(self.up_next..).collect();
+        match field {
+            QueueSortField::Artist => tracks.sort_by_cached_key(|t| {
+                (t.artist.to_lowercase(), t.album.to_lowercase(), t.track_num)
+            }),
+            QueueSortField::Album => {
+                tracks.sort_by_cached_key(|t| (t.album.to_lowercase(), t.track_num))
+            }
+            QueueSortField::Title => tracks.sort_by_cached_key(|t| t.title.to_lowercase()),
+            QueueSortField::Duration => tracks.sort_by_key(|t| t.duration),
+        }
+        self.tracks.extend(tracks);
+        self.tracks.append(&mut played);
+        self.sorted_by = Some(field);
+    }
+
+    // Put the queue back in the order it was in before it was sorted or
+    // shuffled. Tracks queued since then go after the others, as they are now
+    pub fn unsort(&mut self) {
+        let shuffled = std::mem::replace(&mut self.shuffled, false);
+        if self.sorted_by.take().is_none() && !shuffled {
+            return;
+        }
+
+        let mut position = HashMap::new();
+        for (i, path) in self.unsorted.drain(..).enumerate() {
+            position.entry(path).or_insert(i);
+        }
+
+        let mut played = self.tracks.split_off(self.to_come());
+        let mut tracks: Vec<Track> = self.tracks.drain(self.up_next..).collect();
+        tracks.sort_by_key(|t| position.get(&t.file_path).copied().unwrap_or(usize::MAX));
+        self.tracks.extend(tracks);
+        self.tracks.append(&mut played);
+    }
+
+    // Drop tracks that are already further up the queue, keeping the order
+    // of the rest. Returns how many were dropped
+    pub fn dedupe(&mut self) -> usize {
+        let before = self.tracks.len();
+        let mut seen = HashSet::new();
+        let mut index = 0;
+        let mut up_next = 0;
+        let mut played = 0;
+        let lane = self.up_next;
+        let end = self.to_come();
+        self.tracks.retain(|t| {
+            let keep = seen.insert(t.file_path.clone());
+            if keep && index < lane {
+                up_next += 1;
+            }
+            if keep && index >= end {
+                played += 1;
+            }
+            index += 1;
+            keep
+        });
+        self.up_next = up_next;
+        self.played = played;
+        self.total_time = self.tracks.iter().map(|t| t.duration).sum();
+
+        before - self.tracks.len()
+    }
+
+    // Tracks come off the queue as they play, so the only ones already
+    // heard are those repeat put back on the end. Drop those, leaving the
+    // tracks still to come. Returns how many were dropped
+    pub fn clear_played(&mut self) -> usize {
+        let played = std::mem::take(&mut self.played);
+        self.tracks.truncate(self.tracks.len() - played);
+        self.total_time = self.tracks.iter().map(|t| t.duration).sum();
+
+        played
     }
 
     pub fn is_empty(&self) -> bool {
         self.tracks.is_empty()
     }
 
-    pub fn take(&mut self) -> Track {
-        let track = self.tracks.pop_front().unwrap();
+    // Look at the next track without taking it off the queue
+    pub fn peek(&self) -> Option<&Track> {
+        self.tracks.front()
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Track> {
+        if index >= self.to_come() && index < self.tracks.len() {
+            self.played -= 1;
+        }
+        let track = self.tracks.remove(index)?;
         self.total_time -= &track.duration;
+        if index < self.up_next {
+            self.up_next -= 1;
+        }
 
+        Some(track)
+    }
+
+    // Drop everything ahead of the track at index and hand back that track
+    pub fn skip_to(&mut self, index: usize) -> Option<Track> {
+        if index >= self.tracks.len() {
+            return None;
+        }
+
+        let mut skipped: Vec<Track> = self.tracks.drain(..=index).collect();
+        self.total_time = self.tracks.iter().map(|t| t.duration).sum();
+        self.up_next = self.up_next.saturating_sub(index + 1);
+        self.played = self.played.min(self.tracks.len());
+
+        skipped.pop()
+    }
+
+    // Positions of the tracks whose title or artist contains the query,
+    // ignoring case
+    pub fn find(&self, query: &str) -> Vec<usize> {
+        let query = query.to_lowercase();
+        self.tracks
+            .iter()
+            .enumerate()
+            .filter(|(_, t)| {
+                t.title.to_lowercase().contains(&query) || t.artist.to_lowercase().contains(&query)
+            })
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    // Take the next track off the queue, None once it has run out
+    pub fn take(&mut self) -> Option<Track> {
+        self.take_at(0)
+    }
+
+    // Take tracks off the front until one of them starts, dropping the ones
+    // that don't. A repeating queue is gone round once before it is given
+    // up on, since what doesn't start goes back on the end
+    pub fn take_playable<F>(&mut self, mut start: F) -> Option<Track>
+    where
+        F: FnMut(&Track) -> bool,
+    {
+        for _ in 0..self.tracks.len() {
+            let track = self.take()?;
+            if start(&track) {
+                return Some(track);
+            }
+        }
+
+        None
+    }
+
+    // Take a track off the queue to be played. Unlike remove, with repeat
+    // on it goes back on the end
+    pub fn take_at(&mut self, index: usize) -> Option<Track> {
+        let track = self.remove(index)?;
+        if self.repeat {
+            self.played += 1;
+            self.total_time += &track.duration;
+            self.tracks.push_back(track.clone());
+        }
+
+        Some(track)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use crate::application::state::open_source;
+    use crate::storage::record::tests::silent_wav;
+
+    fn track(path: &str, duration: u32) -> Track {
+        let mut track = Track::dummy();
+        track.file_path = path.to_string();
+        track.duration = duration;
         track
     }
+
+    fn paths(queue: &SonikQueue) -> Vec<&str> {
+        queue.tracks.iter().map(|t| t.file_path.as_str()).collect()
+    }
+
+    #[test]
+    fn dedupe_keeps_the_first_of_each() {
+        let mut queue = SonikQueue::new();
+        for (path, duration) in [("a", 100), ("b", 200), ("a", 100), ("c", 300), ("b", 200)] {
+            queue.add(track(path, duration));
+        }
+        queue.add_next(track("c", 300));
+
+        assert_eq!(queue.dedupe(), 3);
+        assert_eq!(paths(&queue), vec!["c", "a", "b"]);
+        assert_eq!(queue.up_next, 1);
+        assert_eq!(queue.total_time, 600);
+    }
+
+    #[test]
+    fn clear_played_only_drops_what_repeat_put_back() {
+        let mut queue = SonikQueue::new();
+        queue.repeat = true;
+        for path in ["a", "b", "c"] {
+            queue.add(track(path, 100));
+        }
+
+        // a plays and goes round, then a second copy is queued and b is
+        // taken out, neither of which has played
+        queue.take();
+        queue.add(track("a", 100));
+        queue.remove(0);
+        assert_eq!(paths(&queue), vec!["c", "a", "a"]);
+
+        queue.add_next(track("d", 100));
+        assert_eq!(queue.clear_played(), 1);
+        assert_eq!(paths(&queue), vec!["d", "c", "a"]);
+        assert_eq!(queue.total_time, 300);
+        assert_eq!(queue.clear_played(), 0);
+
+        // Deduping keeps the copy still to come over the played one
+        queue.take();
+        queue.add(track("d", 100));
+        queue.dedupe();
+        assert_eq!(paths(&queue), vec!["c", "a", "d"]);
+        assert_eq!(queue.clear_played(), 0);
+    }
+
+    #[test]
+    fn broken_tracks_are_skipped() {
+        let dir = std::env::temp_dir().join(format!("sonik-queue-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let good = dir.join("good.wav");
+        fs::write(&good, silent_wav()).unwrap();
+        let broken = dir.join("missing.wav");
+
+        let mut queue = SonikQueue::new();
+        queue.add(track(broken.to_str().unwrap(), 1000));
+        queue.add(track(good.to_str().unwrap(), 1000));
+        let started = queue.take_playable(|t| open_source(&t.file_path).is_ok());
+        assert_eq!(started.unwrap().file_path, good.to_str().unwrap());
+        assert!(queue.is_empty());
+        assert_eq!(queue.total_time, 0);
+
+        // Nothing in a repeating queue starts, so it is gone round only once
+        // and left as it was
+        queue.repeat = true;
+        queue.add(track(broken.to_str().unwrap(), 1000));
+        queue.add(track(broken.to_str().unwrap(), 1000));
+        let mut tries = 0;
+        let started = queue.take_playable(|t| {
+            tries += 1;
+            open_source(&t.file_path).is_ok()
+        });
+        assert!(started.is_none());
+        assert_eq!(tries, 2);
+        assert_eq!(queue.tracks.len(), 2);
+        assert_eq!(queue.total_time, 2000);
+
+        fs::remove_dir_all(&dir).ok();
+    }
 }
@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde_derive::{Deserialize, Serialize};
+use toml;
+
+use crate::application::config::Config;
+use crate::application::equalizer::Gains;
+use crate::application::state::UI;
+
+// Things remembered from one run of sonik to the next
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Session {
+    #[serde(default)]
+    pub now_playing: String,
+    // Position into now_playing, in milliseconds
+    #[serde(default)]
+    pub position: u64,
+    // Where the interface was left: the open tab, the active library
+    // column, and the selection in each column
+    #[serde(default)]
+    pub tab: usize,
+    #[serde(default)]
+    pub library_column: usize,
+    #[serde(default)]
+    pub artist: usize,
+    #[serde(default)]
+    pub album: usize,
+    #[serde(default)]
+    pub track: usize,
+    // Paths of the tracks played before now_playing, oldest first
+    #[serde(default)]
+    pub history: Vec<String>,
+    // Band gains once they have been changed in sonik, which then take
+    // over from the config
+    #[serde(default)]
+    pub equalizer: Option<Gains>,
+    // Left/right balance, 0 being the middle
+    #[serde(default)]
+    pub balance: f32,
+}
+
+impl Session {
+    pub fn from_ui(ui: &UI) -> Session {
+        Session {
+            now_playing: ui.now_playing.file_path.clone(),
+            position: ui.elapsed().as_millis() as u64,
+            tab: ui.tabs.index,
+            library_column: ui.lib_cols.current_active,
+            artist: ui.lib_cols.artists.selected,
+            album: ui.lib_cols.albums.selected,
+            track: ui.lib_cols.tracks.selected,
+            history: ui.history.iter().map(|t| t.file_path.clone()).collect(),
+            equalizer: if ui.equalizer == ui.config.equalizer {
+                None
+            } else {
+                Some(ui.equalizer)
+            },
+            balance: ui.balance,
+        }
+    }
+
+    // A missing or unreadable session file just means a fresh start
+    pub fn load(config: &Config) -> Session {
+        match fs::read_to_string(session_path(config)) {
+            Ok(session_string) => toml::from_str(&session_string).unwrap_or_default(),
+            Err(_) => Session::default(),
+        }
+    }
+
+    pub fn save(&self, config: &Config) -> Result<(), ()> {
+        let session_as_str = toml::to_string(self).map_err(|_| ())?;
+        fs::write(session_path(config), session_as_str).map_err(|_| ())
+    }
+}
+
+// Where long tracks were left off, in milliseconds by file path, so they
+// pick up from there when played again. See Config::resume_min_minutes
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ResumePositions {
+    #[serde(default)]
+    positions: HashMap<String, u64>,
+}
+
+impl ResumePositions {
+    pub fn load(config: &Config) -> ResumePositions {
+        match fs::read_to_string(positions_path(config)) {
+            Ok(positions_string) => toml::from_str(&positions_string).unwrap_or_default(),
+            Err(_) => ResumePositions::default(),
+        }
+    }
+
+    pub fn save(&self, config: &Config) -> Result<(), ()> {
+        let positions_as_str = toml::to_string(self).map_err(|_| ())?;
+        fs::write(positions_path(config), positions_as_str).map_err(|_| ())
+    }
+
+    pub fn get(&self, file_path: &str) -> Option<Duration> {
+        self.positions
+            .get(file_path)
+            .map(|ms| Duration::from_millis(*ms))
+    }
+
+    pub fn set(&mut self, file_path: &str, position: Duration) {
+        self.positions
+            .insert(file_path.to_string(), position.as_millis() as u64);
+    }
+
+    pub fn remove(&mut self, file_path: &str) {
+        self.positions.remove(file_path);
+    }
+}
+
+fn positions_path(config: &Config) -> PathBuf {
+    let mut positions_path = PathBuf::from(&config.data_folder);
+    positions_path.push("positions.toml");
+
+    positions_path
+}
+
+fn session_path(config: &Config) -> PathBuf {
+    let mut session_path = PathBuf::from(&config.data_folder);
+    session_path.push("session.toml");
+
+    session_path
+}
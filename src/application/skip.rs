@@ -0,0 +1,94 @@
+use std::time::Duration;
+
+use rodio::{Sample, Source};
+
+// A source started partway in. rodio can't seek, so the input is run
+// forward to the position and the samples in between are thrown away. That
+// is left until the first sample is asked for, so it happens as the sink
+// starts playing rather than holding up whoever appended the source
+pub struct Skip<S> {
+    input: S,
+    position: Option<Duration>,
+}
+
+impl<S> Skip<S>
+where
+    S: Source,
+    S::Item: Sample,
+{
+    pub fn new(input: S, position: Duration) -> Skip<S> {
+        Skip {
+            input,
+            position: Some(position),
+        }
+    }
+
+    fn skip(&mut self, position: Duration) {
+        let samples = position.as_millis() as u64
+            * u64::from(self.input.sample_rate())
+            * u64::from(self.input.channels())
+            / 1000;
+
+        for _ in 0..samples {
+            if self.input.next().is_none() {
+                break;
+            }
+        }
+    }
+}
+
+impl<S> Iterator for Skip<S>
+where
+    S: Source,
+    S::Item: Sample,
+{
+    type Item = S::Item;
+
+    fn next(&mut self) -> Option<S::Item> {
+        if let Some(position) = self.position.take() {
+            self.skip(position);
+        }
+
+        self.input.next()
+    }
+}
+
+impl<S> Source for Skip<S>
+where
+    S: Source,
+    S::Item: Sample,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.input.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.input.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+
+    #[test]
+    fn skips_on_the_first_sample() {
+        let samples: Vec<i16> = (0..2000).collect();
+        let input = SamplesBuffer::new(2, 1000, samples);
+        let mut skip = Skip::new(input, Duration::from_millis(250));
+
+        // Nothing is thrown away until then
+        assert_eq!(skip.input.size_hint(), (2000, Some(2000)));
+        assert_eq!(skip.next(), Some(500));
+        assert_eq!(skip.count(), 1499);
+    }
+}
@@ -1,14 +1,36 @@
+use std::cmp::Reverse;
+use std::collections::{HashSet, VecDeque};
 use std::fs::File;
 use std::io::BufReader;
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
-use crossbeam_channel::{Receiver, Sender};
-use rodio::{Device, Sink};
+use crossbeam_channel::{self as channel, Receiver, Sender};
+use log::{info, warn};
+use rand::seq::SliceRandom;
+use rand::thread_rng;
+use rodio::{Device, DeviceTrait, Sink, Source};
+use tui::layout::Rect;
+use unicode_normalization::UnicodeNormalization;
 
-use crate::application::queue::SonikQueue;
+use crate::application::balance::Balance;
+use crate::application::config::{Config, RepeatMode, ReplayGainMode};
+use crate::application::equalizer::{self, Equalizer, Gains, BANDS, MAX_GAIN, PRESETS};
+use crate::application::fade::{Envelope, Fade};
+use crate::application::queue::{QueueSortField, SonikQueue};
+use crate::application::session::ResumePositions;
+use crate::application::skip::Skip;
 use crate::storage::database::search as db_search;
-use crate::storage::database::{EngineGroup, SearchResult};
-use crate::storage::record::{Album, Artist, Media, Stats, Track};
-use crate::storage::terms::SearchQuery;
+use crate::storage::database::{match_spans, EngineGroup, SearchResult};
+use crate::storage::formats::missing_decoder;
+use crate::storage::library;
+use crate::storage::lyrics::{self, Lyrics};
+use crate::storage::playlist::read_folder;
+use crate::storage::record::{clock, Album, Artist, Media, Record, Stats, Track, MAX_RATING};
+use crate::storage::terms::{SearchQuery, Term};
+use crate::ui::art::{detect_graphics, CoverArt};
+use crate::ui::theme::Theme;
 
 // Tabs only need name and ordering information
 pub struct TabsState<'a> {
@@ -21,16 +43,14 @@ impl<'a> TabsState<'a> {
     pub fn new(titles: Vec<&'a str>) -> TabsState {
         TabsState { titles, index: 0 }
     }
-    pub fn next(&mut self) {
-        self.index = (self.index + 1) % self.titles.len();
+    // Either way round, wrap goes from the last tab to the first rather
+    // than stopping there
+    pub fn next(&mut self, wrap: bool) {
+        self.index = step_next(self.index, self.titles.len(), wrap);
     }
 
-    pub fn previous(&mut self) {
-        if self.index > 0 {
-            self.index -= 1;
-        } else {
-            self.index = self.titles.len() - 1;
-        }
+    pub fn previous(&mut self, wrap: bool) {
+        self.index = step_previous(self.index, self.titles.len(), wrap);
     }
 }
 
@@ -38,6 +58,8 @@ impl<'a> TabsState<'a> {
 pub struct ListState<I> {
     pub items: Vec<I>,
     pub selected: usize,
+    // Items marked to be added to the queue together, by index
+    pub marked: HashSet<usize>,
 }
 
 impl<I> ListState<I>
@@ -48,19 +70,124 @@ where
         ListState {
             items: items.to_vec(),
             selected: 0,
+            marked: HashSet::new(),
         }
     }
 
-    fn select_previous(&mut self) {
-        if self.selected > 0 {
-            self.selected -= 1;
-        } else {
-            self.selected = self.items.len() - 1;
+    // Nothing is selected in an empty list, e.g. a library not scanned yet
+    fn selected_item(&self) -> Option<&I> {
+        self.items.get(self.selected)
+    }
+
+    fn toggle_mark(&mut self) {
+        if !self.items.is_empty() {
+            toggle_mark(&mut self.marked, self.selected);
+        }
+    }
+
+    // The marked items in list order, unmarking them
+    fn take_marked(&mut self) -> Vec<I> {
+        let mut indices: Vec<usize> = self.marked.drain().collect();
+        indices.sort();
+        indices
+            .into_iter()
+            .filter_map(|i| self.items.get(i).cloned())
+            .collect()
+    }
+
+    fn select_previous(&mut self, wrap: bool) {
+        self.selected = step_previous(self.selected, self.items.len(), wrap);
+    }
+
+    fn select_next(&mut self, wrap: bool) {
+        self.selected = step_next(self.selected, self.items.len(), wrap);
+    }
+
+    fn select_first(&mut self) {
+        self.selected = 0;
+    }
+
+    fn select_last(&mut self) {
+        self.selected = self.items.len().saturating_sub(1);
+    }
+
+    fn page_up(&mut self, n: usize) {
+        self.selected = self.selected.saturating_sub(n);
+    }
+
+    fn page_down(&mut self, n: usize) {
+        self.selected = (self.selected + n).min(self.items.len().saturating_sub(1));
+    }
+}
+
+impl<I> ListState<I>
+where
+    I: Record + std::clone::Clone,
+{
+    // Select the first item whose name starts with the prefix, ignoring case
+    fn select_prefix(&mut self, prefix: &str) -> bool {
+        let prefix = prefix.to_lowercase();
+        match self
+            .items
+            .iter()
+            .position(|i| i.name().to_lowercase().starts_with(&prefix))
+        {
+            Some(index) => {
+                self.selected = index;
+                true
+            }
+            None => false,
         }
     }
+}
+
+// The artist column's index, "#" holding names that don't start with a
+// letter. Accented letters are filed under the plain one
+pub const INDEX_LETTERS: &str = "#ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+
+pub fn index_letter(name: &str) -> char {
+    match name.nfd().next() {
+        Some(c) if c.is_ascii_alphabetic() => c.to_ascii_uppercase(),
+        _ => '#',
+    }
+}
 
-    fn select_next(&mut self) {
-        self.selected = (self.selected + 1) % self.items.len();
+// How long a status message stays in the status bar, with errors staying
+// up a bit longer
+pub const STATUS_TIMEOUT: Duration = Duration::from_secs(4);
+// How near the start or end of a long track playback has to be for it to
+// start from the top next time
+const RESUME_MARGIN: Duration = Duration::from_secs(10);
+// The playback failure message stays up longer, as nothing else will work
+const AUDIO_LOST_TIMEOUT: Duration = Duration::from_secs(60);
+pub const ERROR_TIMEOUT: Duration = Duration::from_secs(8);
+
+// How far into a track going back starts it over rather than going to the
+// one before
+const RESTART_AFTER: Duration = Duration::from_secs(3);
+
+// How far the seek keys move through a track
+pub const SEEK_STEP: i64 = 10;
+
+// How long the library jump prompt waits for the next keypress
+const JUMP_TIMEOUT: Duration = Duration::from_secs(1);
+
+// Order of the albums in the album column. Title is the order the
+// database keeps them in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AlbumSort {
+    Title,
+    YearAscending,
+    YearDescending,
+}
+
+impl AlbumSort {
+    fn next(self) -> AlbumSort {
+        match self {
+            AlbumSort::Title => AlbumSort::YearAscending,
+            AlbumSort::YearAscending => AlbumSort::YearDescending,
+            AlbumSort::YearDescending => AlbumSort::Title,
+        }
     }
 }
 
@@ -70,9 +197,187 @@ pub struct LibraryCols {
     pub albums: ListState<Album>,
     pub tracks: ListState<Track>,
     pub current_active: usize,
+    pub sort_by_plays: bool,
+    pub album_sort: AlbumSort,
+    // Whether moving past either end of a column goes round to the other
+    pub wrap: bool,
 }
 
 impl LibraryCols {
+    // Rebuild the album column (and so the track column) from the selected artist
+    fn reload_albums(&mut self) {
+        self.albums = ListState::new(self.artists.selected_item().map_or(&[], |a| &a.albums));
+        match self.album_sort {
+            AlbumSort::Title => {}
+            // Albums from the same year stay in title order
+            AlbumSort::YearAscending => self.albums.items.sort_by_key(|al| al.year),
+            AlbumSort::YearDescending => self.albums.items.sort_by_key(|al| Reverse(al.year)),
+        }
+        self.reload_tracks();
+    }
+
+    // Rebuild the track column from the selected album
+    fn reload_tracks(&mut self) {
+        self.tracks = ListState::new(self.albums.selected_item().map_or(&[], |al| &al.tracks));
+        if self.sort_by_plays {
+            self.tracks.items.sort_by_key(|t| Reverse(t.play_count));
+        }
+    }
+
+    // Copy changes made to the artist column into the album and track
+    // columns without losing the current selections
+    fn refresh(&mut self) {
+        let album_selected = self.albums.selected;
+        let track_selected = self.tracks.selected;
+        let album_marked = std::mem::take(&mut self.albums.marked);
+        let track_marked = std::mem::take(&mut self.tracks.marked);
+
+        self.reload_albums();
+        self.albums.selected = album_selected;
+        self.albums.marked = album_marked;
+        self.reload_tracks();
+        self.tracks.selected = track_selected;
+        self.tracks.marked = track_marked;
+    }
+
+    // Mark or unmark the selected item in the active column. Marks go when
+    // the column is refilled, e.g. on moving to another album
+    pub fn toggle_mark(&mut self) {
+        match self.current_active {
+            0 => self.artists.toggle_mark(),
+            1 => self.albums.toggle_mark(),
+            2 => self.tracks.toggle_mark(),
+            _ => {}
+        };
+    }
+
+    // Every track of what is marked in the active column, in order, with
+    // the marks cleared
+    fn take_marked_tracks(&mut self) -> Vec<Track> {
+        match self.current_active {
+            0 => self
+                .artists
+                .take_marked()
+                .into_iter()
+                .flat_map(|a| a.albums.into_iter())
+                .flat_map(|al| al.tracks.into_iter())
+                .collect(),
+            1 => self
+                .albums
+                .take_marked()
+                .into_iter()
+                .flat_map(|al| al.tracks.into_iter())
+                .collect(),
+            2 => self.tracks.take_marked(),
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn toggle_sort_by_plays(&mut self) {
+        self.sort_by_plays = !self.sort_by_plays;
+        self.reload_tracks();
+    }
+
+    // Go to the next album order, keeping the selected album selected
+    pub fn cycle_album_sort(&mut self) {
+        let selected = self.albums.selected_item().map(|al| al.title.clone());
+
+        self.album_sort = self.album_sort.next();
+        self.reload_albums();
+
+        if let Some(index) = self
+            .albums
+            .items
+            .iter()
+            .position(|al| Some(&al.title) == selected.as_ref())
+        {
+            self.albums.selected = index;
+            self.reload_tracks();
+        }
+    }
+
+    // Artist and album indices of the album holding a track
+    fn locate(&self, file_path: &str) -> Option<(usize, usize)> {
+        self.artists
+            .items
+            .iter()
+            .enumerate()
+            .find_map(|(i, artist)| {
+                let j = artist
+                    .albums
+                    .iter()
+                    .position(|al| al.tracks.iter().any(|t| t.file_path == file_path))?;
+                Some((i, j))
+            })
+    }
+
+    // Point all three columns at a track and make column the active one.
+    // Tracks from outside the library, e.g. from a playlist, can't be shown
+    // and leave the columns untouched. Returns whether the track was found
+    pub fn reveal(&mut self, file_path: &str, column: usize) -> bool {
+        if let Some((artist, _)) = self.locate(file_path) {
+            self.artists.selected = artist;
+            self.reload_albums();
+
+            // Look the album up in the column, which may be sorted by year
+            if let Some(album) = self
+                .albums
+                .items
+                .iter()
+                .position(|al| al.tracks.iter().any(|t| t.file_path == file_path))
+            {
+                self.albums.selected = album;
+            }
+            self.reload_tracks();
+
+            // Look the track up in the column itself, which may be reordered
+            if let Some(track) = self
+                .tracks
+                .items
+                .iter()
+                .position(|t| t.file_path == file_path)
+            {
+                self.tracks.selected = track;
+            }
+
+            self.current_active = column.min(2);
+            return true;
+        }
+
+        false
+    }
+
+    // Put the selections back the way a previous session left them. The
+    // library may have shrunk since, so each one is kept within its column
+    pub fn restore(&mut self, column: usize, artist: usize, album: usize, track: usize) {
+        if self.artists.items.is_empty() {
+            return;
+        }
+
+        self.artists.selected = artist.min(self.artists.items.len() - 1);
+        self.reload_albums();
+        self.albums.selected = album.min(self.albums.items.len().saturating_sub(1));
+        self.reload_tracks();
+        self.tracks.selected = track.min(self.tracks.items.len().saturating_sub(1));
+        self.current_active = column.min(2);
+    }
+
+    // Cover art of the album a track belongs to
+    pub fn cover_of(&self, file_path: &str) -> Option<&PathBuf> {
+        let (artist, album) = self.locate(file_path)?;
+        self.artists.items[artist].albums[album].cover_path.as_ref()
+    }
+
+    pub fn find_track(&self, file_path: &str) -> Option<&Track> {
+        library::tracks(&self.artists.items).find(|t| t.file_path == file_path)
+    }
+
+    // The library's own copy of a track, as opposed to the clones handed
+    // out to the queue and the columns
+    pub fn find_track_mut(&mut self, file_path: &str) -> Option<&mut Track> {
+        library::tracks_mut(&mut self.artists.items).find(|t| t.file_path == file_path)
+    }
+
     pub fn switch_left(&mut self) {
         if self.current_active > 0 {
             self.current_active -= 1;
@@ -85,19 +390,157 @@ impl LibraryCols {
         }
     }
 
+    // Select an item in a column and make that column the active one, as
+    // when it is clicked
+    pub fn select(&mut self, column: usize, index: usize) {
+        match column {
+            0 if index < self.artists.items.len() => {
+                if index != self.artists.selected {
+                    self.artists.selected = index;
+                    self.reload_albums();
+                }
+            }
+            1 if index < self.albums.items.len() => {
+                if index != self.albums.selected {
+                    self.albums.selected = index;
+                    self.reload_tracks();
+                }
+            }
+            2 if index < self.tracks.items.len() => self.tracks.selected = index,
+            _ => return,
+        };
+        self.current_active = column;
+    }
+
     pub fn on_up(&mut self) {
         // List states need to be refreshed when scrolling through each column
         match self.current_active {
             0 => {
-                self.artists.select_previous();
-                self.albums = ListState::new(&self.artists.items[self.artists.selected].albums);
-                self.tracks = ListState::new(&self.albums.items[self.albums.selected].tracks);
+                self.artists.select_previous(self.wrap);
+                self.reload_albums();
+            }
+            1 => {
+                self.albums.select_previous(self.wrap);
+                self.reload_tracks();
+            }
+            2 => self.tracks.select_previous(self.wrap),
+            _ => {}
+        };
+    }
+
+    pub fn on_first(&mut self) {
+        match self.current_active {
+            0 => {
+                self.artists.select_first();
+                self.reload_albums();
+            }
+            1 => {
+                self.albums.select_first();
+                self.reload_tracks();
+            }
+            2 => self.tracks.select_first(),
+            _ => {}
+        };
+    }
+
+    pub fn on_last(&mut self) {
+        match self.current_active {
+            0 => {
+                self.artists.select_last();
+                self.reload_albums();
+            }
+            1 => {
+                self.albums.select_last();
+                self.reload_tracks();
+            }
+            2 => self.tracks.select_last(),
+            _ => {}
+        };
+    }
+
+    pub fn on_page_up(&mut self, n: usize) {
+        match self.current_active {
+            0 => {
+                self.artists.page_up(n);
+                self.reload_albums();
+            }
+            1 => {
+                self.albums.page_up(n);
+                self.reload_tracks();
+            }
+            2 => self.tracks.page_up(n),
+            _ => {}
+        };
+    }
+
+    pub fn on_page_down(&mut self, n: usize) {
+        match self.current_active {
+            0 => {
+                self.artists.page_down(n);
+                self.reload_albums();
             }
             1 => {
-                self.albums.select_previous();
-                self.tracks = ListState::new(&self.albums.items[self.albums.selected].tracks);
+                self.albums.page_down(n);
+                self.reload_tracks();
+            }
+            2 => self.tracks.page_down(n),
+            _ => {}
+        };
+    }
+
+    // Letter of the index an artist is filed under
+    pub fn artist_letter(&self, index: usize) -> Option<char> {
+        self.artists
+            .items
+            .get(index)
+            .map(|a| index_letter(&a.title))
+    }
+
+    // Go to the first artist under the next letter of the index that has
+    // any, or the previous one
+    pub fn step_letter(&mut self, forward: bool) {
+        let current = match self.artist_letter(self.artists.selected) {
+            Some(letter) => letter,
+            None => return,
+        };
+        let present: Vec<char> = INDEX_LETTERS
+            .chars()
+            .filter(|l| {
+                self.artists
+                    .items
+                    .iter()
+                    .any(|a| index_letter(&a.title) == *l)
+            })
+            .collect();
+        let here = match present.iter().position(|l| *l == current) {
+            Some(here) => here,
+            None => return,
+        };
+
+        let target = if forward {
+            step_next(here, present.len(), self.wrap)
+        } else {
+            step_previous(here, present.len(), self.wrap)
+        };
+        if let Some(index) = self
+            .artists
+            .items
+            .iter()
+            .position(|a| index_letter(&a.title) == present[target])
+        {
+            self.artists.selected = index;
+            self.current_active = 0;
+            self.reload_albums();
+        }
+    }
+
+    pub fn jump_to_prefix(&mut self, prefix: &str) {
+        match self.current_active {
+            0 if self.artists.select_prefix(prefix) => self.reload_albums(),
+            1 if self.albums.select_prefix(prefix) => self.reload_tracks(),
+            2 => {
+                self.tracks.select_prefix(prefix);
             }
-            2 => self.tracks.select_previous(),
             _ => {}
         };
     }
@@ -105,71 +548,469 @@ impl LibraryCols {
     pub fn on_down(&mut self) {
         match self.current_active {
             0 => {
-                self.artists.select_next();
-                self.albums = ListState::new(&self.artists.items[self.artists.selected].albums);
-                self.tracks = ListState::new(&self.albums.items[self.albums.selected].tracks);
+                self.artists.select_next(self.wrap);
+                self.reload_albums();
             }
             1 => {
-                self.albums.select_next();
-                self.tracks = ListState::new(&self.albums.items[self.albums.selected].tracks);
+                self.albums.select_next(self.wrap);
+                self.reload_tracks();
             }
-            2 => self.tracks.select_next(),
+            2 => self.tracks.select_next(self.wrap),
             _ => {}
         };
     }
 }
 
-// t - track, b - bool, p - play/pause
-// Sends information to the UI when audio is not playing,
-// and also receives tracks from the queue as well as play/pause events
+// Where playback is at, as the audio thread sees it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PlaybackState {
+    Stopped,
+    Playing,
+    Paused,
+}
+
+impl PlaybackState {
+    // Shown ahead of the playing track
+    pub fn symbol(self) -> &'static str {
+        match self {
+            PlaybackState::Stopped => "⏹",
+            PlaybackState::Playing => "▶",
+            PlaybackState::Paused => "⏸",
+        }
+    }
+}
+
+// The audio thread's ends of the channels between it and the interface.
+// t - track, b - playback state, p - play/pause, l - load next,
+// a - advanced, e - unreadable, d - output device, s - speed, eq -
+// equalizer, bal - balance
+pub struct AudioChannels {
+    pub trx: Receiver<(Vec<Track>, Duration)>,
+    pub btx: Sender<PlaybackState>,
+    pub prx: Receiver<bool>,
+    pub lrx: Receiver<Track>,
+    pub atx: Sender<()>,
+    pub etx: Sender<String>,
+    pub drx: Receiver<String>,
+    pub looprx: Receiver<bool>,
+    pub srx: Receiver<f32>,
+    pub eqrx: Receiver<Gains>,
+    pub balrx: Receiver<f32>,
+}
+
+// The interface's ends of the same channels
+pub struct UiChannels {
+    pub rx: Receiver<PlaybackState>,
+    pub tx: Sender<(Vec<Track>, Duration)>,
+    pub ptx: Sender<bool>,
+    pub arx: Receiver<()>,
+    pub erx: Receiver<String>,
+    pub ltx: Sender<Track>,
+    pub dtx: Sender<String>,
+    pub looptx: Sender<bool>,
+    pub stx: Sender<f32>,
+    pub eqtx: Sender<Gains>,
+    pub baltx: Sender<f32>,
+}
+
+// Every channel between the audio thread and the interface, both ends
+pub fn audio_channels() -> (AudioChannels, UiChannels) {
+    // The playback state, play/pause events and tracks to play
+    let (btx, rx) = channel::bounded(0);
+    let (ptx, prx) = channel::bounded(0);
+    let (tx, trx) = channel::bounded(0);
+
+    // Preloading of the next track for gapless playback, and the
+    // notification that the sink moved on to it. Every advance has to reach
+    // the UI, so that channel never blocks the audio thread
+    let (ltx, lrx) = channel::bounded(0);
+    let (atx, arx) = channel::unbounded();
+
+    // Tracks the audio thread couldn't open or decode
+    let (etx, erx) = channel::unbounded();
+
+    // Output device switches requested from the settings tab
+    let (dtx, drx) = channel::bounded(0);
+
+    // Turning the loop on the current track on and off
+    let (looptx, looprx) = channel::bounded(0);
+
+    // Playback speed, equalizer and balance changes
+    let (stx, srx) = channel::bounded(0);
+    let (eqtx, eqrx) = channel::bounded(0);
+    let (baltx, balrx) = channel::bounded(0);
+
+    let audio = AudioChannels {
+        trx,
+        btx,
+        prx,
+        lrx,
+        atx,
+        etx,
+        drx,
+        looprx,
+        srx,
+        eqrx,
+        balrx,
+    };
+    let ui = UiChannels {
+        rx,
+        tx,
+        ptx,
+        arx,
+        erx,
+        ltx,
+        dtx,
+        looptx,
+        stx,
+        eqtx,
+        baltx,
+    };
+
+    (audio, ui)
+}
+
+// Sends the playback state to the UI, which moves on to the queue when
+// nothing is playing, and also receives tracks from the queue as well as
+// play/pause events
 pub struct Audio {
-    pub device: Device,
+    // None only in the tests, which play into sinks no one hears
+    device: Option<Device>,
     pub sink: Sink,
-    pub trx: Receiver<Track>,
-    pub btx: Sender<bool>,
-    pub prx: Receiver<bool>,
+    pub channels: AudioChannels,
+    pub replaygain: ReplayGainMode,
+    // Tracks appended to the sink that have not finished yet, current first
+    appended: VecDeque<Track>,
+    // Set by play, pause_play and stop, see state
+    state: PlaybackState,
+    // Play the current track over and over instead of ending it
+    looping: bool,
+    // Playback speed factor, applied to each source as it is appended
+    speed: f32,
+    // Equalizer band gains and left/right balance, also applied to each
+    // source
+    equalizer: Gains,
+    balance: f32,
+    volume: f32,
+    // Fading goes with each source, following the sink's envelope, which
+    // is also how the sink is paused
+    envelope: Envelope,
+    fade: Duration,
+    // Sinks left playing while they fade out, dropped once silent
+    fading: Vec<(Sink, Envelope)>,
+}
+
+// Slowest and fastest playback speeds
+pub const MIN_SPEED: f32 = 0.5;
+pub const MAX_SPEED: f32 = 2.0;
+
+// Why a track was passed over, for the status bar
+fn skipped_message(track: &Track) -> String {
+    match missing_decoder(Path::new(&track.file_path)) {
+        Some(extension) => format!(
+            "Skipped: {} (no decoder for .{} in this build)",
+            track.title, extension
+        ),
+        None => format!("Skipped: {} (unreadable)", track.title),
+    }
+}
+
+// A decoder for the file, failing if it can't be opened or isn't audio
+// rodio can play
+pub(crate) fn open_source(file_path: &str) -> Result<rodio::Decoder<BufReader<File>>, ()> {
+    let file = File::open(file_path).map_err(|_| ())?;
+    rodio::Decoder::new(BufReader::new(file)).map_err(|_| ())
+}
+
+fn new_sink(device: Option<&Device>) -> Sink {
+    match device {
+        Some(device) => Sink::new(device),
+        None => Sink::new_idle().0,
+    }
+}
+
+// Names of every output device the system offers
+pub fn output_device_names() -> Vec<String> {
+    match rodio::output_devices() {
+        Ok(devices) => devices.filter_map(|d| d.name().ok()).collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+pub fn default_output_device_name() -> String {
+    rodio::default_output_device()
+        .and_then(|d| d.name().ok())
+        .unwrap_or_default()
 }
 
 impl Audio {
     pub fn new(
         device: Device,
-        trx: Receiver<Track>,
-        btx: Sender<bool>,
-        prx: Receiver<bool>,
+        channels: AudioChannels,
+        replaygain: ReplayGainMode,
+        fade: Duration,
+    ) -> Audio {
+        Audio::open(Some(device), channels, replaygain, fade)
+    }
+
+    fn open(
+        device: Option<Device>,
+        channels: AudioChannels,
+        replaygain: ReplayGainMode,
+        fade: Duration,
     ) -> Audio {
         Audio {
-            sink: Sink::new(&device),
+            sink: new_sink(device.as_ref()),
             device,
-            trx,
-            btx,
-            prx,
+            channels,
+            replaygain,
+            appended: VecDeque::new(),
+            state: PlaybackState::Stopped,
+            looping: false,
+            speed: 1.0,
+            equalizer: equalizer::FLAT,
+            balance: 0.0,
+            volume: 1.0,
+            envelope: Envelope::new(true),
+            fade,
+            fading: Vec::new(),
+        }
+    }
+
+    // Start a track from the given position into it. Whatever was playing
+    // is stopped even if the track turns out to be unreadable. This always
+    // plays, even if the last track was paused, since the UI starts its
+    // clock along with it; the volume and speed carry over
+    pub fn play(&mut self, track: Track, position: Duration) -> Result<(), ()> {
+        self.replace_sink(false);
+
+        let source = open_source(&track.file_path)?;
+        let source = Skip::new(source, track.start_at(position));
+        self.append_source(source, track, true);
+        self.state = PlaybackState::Playing;
+
+        Ok(())
+    }
+
+    // Play the first track from the given position and append the rest
+    // behind it in the same sink, so a whole album plays without gaps. The
+    // UI hears about each track the sink moves on to, see check_advance
+    pub fn play_all(&mut self, tracks: Vec<Track>, position: Duration) -> Result<(), ()> {
+        let mut tracks = tracks.into_iter();
+        let first = tracks.next().ok_or(())?;
+        self.play(first, position)?;
+        for track in tracks {
+            self.append(track);
+        }
+
+        Ok(())
+    }
+
+    // Let the UI know a track it sent couldn't be played, so it can move on
+    pub fn report_unreadable(&mut self, track: &Track) {
+        warn!("Could not play {}", track.file_path);
+        if self.channels.etx.send(track.file_path.clone()).is_err() {
+            warn!("The interface did not get the unreadable track report");
+        }
+    }
+
+    // Append a track to the current sink so that rodio plays it straight
+    // after whatever is already in there. One that can't be opened is
+    // reported, so the UI doesn't wait on the sink to get to it
+    pub fn append(&mut self, track: Track) {
+        if let Ok(source) = open_source(&track.file_path) {
+            let source = Skip::new(source, track.start_at(Duration::from_secs(0)));
+            self.append_source(source, track, false);
+            return;
+        }
+        self.report_unreadable(&track);
+    }
+
+    // Everything applied to a track on its way into the sink: the
+    // equalizer, balance, fading, ReplayGain and speed
+    fn append_source<S>(&mut self, source: S, track: Track, fade_in: bool)
+    where
+        S: Source + Send + 'static,
+        S::Item: rodio::Sample,
+    {
+        self.sink.append(self.prepare(source, &track, fade_in));
+        self.appended.push_back(track);
+    }
+
+    fn prepare<S>(&self, source: S, track: &Track, fade_in: bool) -> impl Source<Item = f32>
+    where
+        S: Source,
+        S::Item: rodio::Sample,
+    {
+        let source = Equalizer::new(source.convert_samples(), self.equalizer);
+        let source = Balance::new(source, self.balance);
+        let source = Fade::new(source, self.envelope.clone(), self.fade, fade_in);
+        source.amplify(self.gain(track)).speed(self.speed)
+    }
+
+    // Move playback to another output device. A sink belongs to the device
+    // it was made for, so a new one is built with the old volume and pause
+    // state, and the current track starts over from the beginning on it
+    pub fn set_device(&mut self, name: &str) {
+        let device = match rodio::output_devices() {
+            Ok(mut devices) => devices.find(|d| d.name().ok().as_deref() == Some(name)),
+            Err(_) => None,
+        };
+
+        if let Some(device) = device {
+            info!("Switching output to {}", name);
+            self.move_to(Some(device));
+        }
+    }
+
+    fn move_to(&mut self, device: Option<Device>) {
+        let paused = !self.envelope.audible();
+        let tracks: Vec<Track> = self.appended.drain(..).collect();
+
+        self.device = device;
+        self.replace_sink(paused);
+
+        for track in tracks {
+            self.append(track);
+        }
+    }
+
+    // ReplayGain is applied to each source rather than to the sink volume,
+    // since a preloaded track shares the sink with the one before it.
+    // Album mode falls back to the track gain for untagged albums
+    fn gain(&self, track: &Track) -> f32 {
+        let db = match self.replaygain {
+            ReplayGainMode::Off => None,
+            ReplayGainMode::Track => track.track_gain,
+            ReplayGainMode::Album => track.album_gain.or(track.track_gain),
+        };
+
+        match db {
+            Some(db) => 10f32.powf(db / 20.0),
+            None => 1.0,
+        }
+    }
+
+    // Tell the UI when the sink has moved on to a preloaded track, once
+    // for each track it has got past
+    pub fn check_advance(&mut self) {
+        let remaining = self.sink.len();
+        if remaining > 0 && remaining < self.appended.len() {
+            while self.appended.len() > remaining {
+                self.appended.pop_front();
+                if self.channels.atx.send(()).is_err() {
+                    warn!("The interface did not get the track change");
+                }
+            }
+        }
+    }
+
+    // Play between half and double speed. rodio's speed works by
+    // resampling, so the pitch goes up and down along with it; there is
+    // nothing available here to stretch time while keeping the pitch.
+    // Only sources appended from now on are affected, so the UI restarts
+    // the current track at the same spot for the change to be heard
+    pub fn set_speed(&mut self, factor: f32) {
+        self.speed = factor.clamp(MIN_SPEED, MAX_SPEED);
+    }
+
+    // Band gains in dB, which like the speed only reach sources appended
+    // from now on
+    pub fn set_equalizer(&mut self, gains: Gains) {
+        self.equalizer = gains;
+    }
+
+    // From -1 for the left side only to 1 for the right side only, also
+    // only reaching sources appended from now on
+    pub fn set_balance(&mut self, pan: f32) {
+        self.balance = pan.clamp(-1.0, 1.0);
+    }
+
+    pub fn set_looping(&mut self, looping: bool) {
+        self.looping = looping;
+    }
+
+    // Volume from 0 to 1, heard right away
+    pub fn set_volume(&mut self, volume: f32) {
+        self.volume = volume.clamp(0.0, 1.0);
+        self.sink.set_volume(self.volume);
+    }
+
+    // Whether an empty sink is about to get the last track again, in which
+    // case the UI shouldn't hear that nothing is playing
+    pub fn will_loop(&self) -> bool {
+        self.looping && !self.appended.is_empty()
+    }
+
+    // Start the last track over once the sink runs dry. It is reported to
+    // the UI the same way as moving on to a preloaded track
+    pub fn check_loop(&mut self) {
+        if !self.looping || !self.sink.empty() {
+            return;
+        }
+
+        if let Some(track) = self.appended.pop_back() {
+            self.appended.clear();
+            self.append(track);
+            if self.channels.atx.send(()).is_err() {
+                warn!("The interface did not get the track change");
+            }
         }
     }
 
-    pub fn play(&mut self, track: Track) {
-        // The clear function does not work for rodio::Sink, so the
-        // sink field is just reassigned and it works just as well
-        self.sink = Sink::new(&self.device);
-        let file = File::open(&track.file_path).unwrap();
-        let source = rodio::Decoder::new(BufReader::new(file)).unwrap();
-        self.sink.append(source);
+    // Where playback is at. However it was left, it has stopped once the
+    // sink runs dry with nothing to loop
+    pub fn state(&self) -> PlaybackState {
+        if self.sink.empty() && !self.will_loop() {
+            PlaybackState::Stopped
+        } else {
+            self.state
+        }
     }
 
     // Notify the UI that there is no audio playing
     pub fn notify(&mut self) {
-        if let Ok(()) = self.btx.send(true) {}
+        if self.channels.btx.send(PlaybackState::Stopped).is_err() {
+            warn!("The interface did not get the empty sink notice");
+        }
     }
 
+    // The sources fade out and hold where they are, or fade back in, while
+    // the audio thread gets on with other things
     pub fn pause_play(&mut self) {
-        if self.sink.is_paused() {
-            self.sink.play();
+        if self.envelope.audible() {
+            self.envelope.fade_out();
+            self.state = PlaybackState::Paused;
         } else {
-            self.sink.pause();
+            self.envelope.fade_in();
+            self.state = PlaybackState::Playing;
         }
     }
 
     pub fn stop(&mut self) {
-        self.sink = Sink::new(&self.device);
+        self.replace_sink(false);
+        self.state = PlaybackState::Stopped;
+    }
+
+    // The clear function does not work for rodio::Sink, so the sink is
+    // replaced with an empty one instead. The old one is kept fading out
+    // under whatever plays next until it is silent. The volume is set again
+    // on the new sink, and it starts out paused if asked for. Speed,
+    // ReplayGain, the equalizer and balance go with each source rather
+    // than the sink
+    fn replace_sink(&mut self, paused: bool) {
+        self.fading
+            .retain(|(sink, envelope)| !sink.empty() && !envelope.is_silent());
+
+        let sink = new_sink(self.device.as_ref());
+        sink.set_volume(self.volume);
+        let old = std::mem::replace(&mut self.sink, sink);
+        let envelope = std::mem::replace(&mut self.envelope, Envelope::new(!paused));
+        if !old.empty() {
+            envelope.fade_out();
+            self.fading.push((old, envelope));
+        }
+        self.appended.clear();
     }
 }
 
@@ -179,29 +1020,98 @@ pub struct UI<'a> {
     pub tabs: TabsState<'a>,
     pub lib_cols: LibraryCols,
     pub now_playing: Track,
-    pub rx: Receiver<bool>,
-    pub tx: Sender<Track>,
-    pub ptx: Sender<bool>,
+    // Tracks already in the sink behind now_playing, in order
+    pub preloaded: VecDeque<Track>,
+    // Paths the audio thread couldn't play that weren't preloaded, waiting
+    // on the empty sink that follows
+    unreadable: Vec<String>,
+    // Playback position of now_playing is elapsed_offset plus the time
+    // since resumed_at, which is None while paused or stopped
+    pub elapsed_offset: Duration,
+    pub resumed_at: Option<Instant>,
+    // The playback state last heard from the audio thread
+    pub playback: PlaybackState,
+    pub channels: UiChannels,
+    // Set once the audio thread stops taking messages, which only happens
+    // if it has died. Nothing plays from then on
+    pub audio_lost: bool,
+    // The current track repeats until this is turned off
+    pub looping: bool,
+    // Playback speed factor, 1.0 being normal speed
+    pub speed: f32,
+    // Equalizer band gains, and the band picked in the settings tab
+    pub equalizer: Gains,
+    pub eq_band: usize,
+    // Left/right balance, -1 being the left side only and 1 the right
+    pub balance: f32,
     pub search_input: String,
     pub fuzzy_searcher: EngineGroup,
     pub search_results: Vec<Media>,
     pub search_select: usize,
+    // Search results marked to be added to the queue together
+    pub search_marked: HashSet<usize>,
+    // How many results matched, of which search_results holds the best
+    pub search_total: usize,
+    // Where the query shows up in the name of each result, see match_spans
+    pub search_spans: Vec<Vec<(usize, usize)>>,
+    pub queue_select: usize,
+    // Set when clearing the queue is waiting on confirmation
+    pub clear_armed: bool,
+    // What has been typed into the queue's find prompt, if it is open
+    pub queue_find: Option<String>,
+    // The help overlay is open and takes all input, scrolled by help_offset
+    pub show_help: bool,
+    pub help_offset: u16,
+    // The lyrics overlay works the same way. Timed lyrics follow playback
+    // instead of the offset. The lyrics are kept with the path of the track
+    // they belong to, and are None if it has none
+    pub show_lyrics: bool,
+    pub lyrics_offset: u16,
+    pub lyrics: Option<(String, Option<Lyrics>)>,
+    // Where long tracks were left off, see Config::resume_min_minutes
+    pub resume_positions: ResumePositions,
+    // The name being typed for a bookmark, and where in the playing track
+    // it goes, while the bookmark prompt is open
+    pub bookmark_input: Option<(String, Duration)>,
+    // What has been typed into the library jump prompt, if it is open
+    pub jump_input: Option<String>,
+    // The folder being typed into the open folder prompt, if it is open
+    pub folder_input: Option<String>,
+    pub jump_typed_at: Instant,
+    // Number of list rows that fit on screen, used when paging
+    pub page_size: usize,
     pub stats: Stats,
+    pub favorites: ListState<Track>,
+    pub recent: ListState<Album>,
+    pub sleep_deadline: Option<Instant>,
+    // A short message for the status bar and when it goes away
+    pub status_message: Option<(String, Instant)>,
+    // Tracks played before now_playing, most recent last
+    pub history: Vec<Track>,
+    // Each track in history once, most recently played first, or every
+    // track played to the end at least once, most played first
+    pub played: ListState<Track>,
+    pub most_played: bool,
+    pub devices: ListState<String>,
+    pub active_device: String,
+    pub theme: Theme,
+    // Cover of the playing album, None when album art is turned off
+    pub cover: Option<CoverArt>,
+    pub config: Config,
 }
 
 impl<'a> UI<'a> {
     pub fn new(
         database: &[Artist],
-        rx: Receiver<bool>,
-        tx: Sender<Track>,
-        ptx: Sender<bool>,
+        channels: UiChannels,
         fuzzy_searcher: EngineGroup,
         stats: Stats,
+        config: Config,
     ) -> UI<'a> {
         // Generate initial list states
         let art_col = ListState::new(database);
-        let al_col = ListState::new(&art_col.items[art_col.selected].albums);
-        let tr_col = ListState::new(&al_col.items[al_col.selected].tracks);
+        let al_col = ListState::new(art_col.selected_item().map_or(&[], |a| &a.albums));
+        let tr_col = ListState::new(al_col.selected_item().map_or(&[], |al| &al.tracks));
 
         // Associate them all together
         let lib_cols = LibraryCols {
@@ -209,208 +1119,1910 @@ impl<'a> UI<'a> {
             albums: al_col,
             tracks: tr_col,
             current_active: 0,
+            sort_by_plays: false,
+            album_sort: AlbumSort::Title,
+            wrap: config.wrap,
         };
 
-        UI {
-            queue: SonikQueue::new(),
+        let favorites = ListState::new(&favorite_tracks(&lib_cols.artists.items));
+        let recent = ListState::new(&recent_albums(&lib_cols.artists.items));
+
+        let mut queue = SonikQueue::new();
+        queue.repeat = config.default_repeat == RepeatMode::All;
+        queue.shuffling = config.default_shuffle;
+
+        UI {
+            queue,
             should_quit: false,
-            tabs: TabsState::new(vec!["queue", "library", "search"]),
+            tabs: TabsState::new(vec![
+                "queue",
+                "library",
+                "search",
+                "favorites",
+                "settings",
+                "recent",
+                "played",
+            ]),
             lib_cols,
             now_playing: Track::dummy(),
-            rx,
-            tx,
-            ptx,
+            preloaded: VecDeque::new(),
+            unreadable: Vec::new(),
+            elapsed_offset: Duration::from_secs(0),
+            resumed_at: None,
+            playback: PlaybackState::Stopped,
+            channels,
+            audio_lost: false,
+            // The audio side is told by whoever builds it
+            looping: config.default_repeat == RepeatMode::One,
+            speed: 1.0,
+            equalizer: config.equalizer,
+            eq_band: 0,
+            balance: 0.0,
             search_input: String::new(),
             fuzzy_searcher,
             search_results: Vec::new(),
             search_select: 0,
+            search_marked: HashSet::new(),
+            search_total: 0,
+            search_spans: Vec::new(),
+            queue_select: 0,
+            clear_armed: false,
+            queue_find: None,
+            show_help: false,
+            help_offset: 0,
+            show_lyrics: false,
+            lyrics_offset: 0,
+            lyrics: None,
+            resume_positions: ResumePositions::load(&config),
+            bookmark_input: None,
+            jump_input: None,
+            folder_input: None,
+            jump_typed_at: Instant::now(),
+            page_size: 1,
             stats,
+            favorites,
+            recent,
+            sleep_deadline: None,
+            status_message: None,
+            history: Vec::new(),
+            played: ListState::new(&[]),
+            most_played: false,
+            devices: ListState::new(&output_device_names()),
+            active_device: default_output_device_name(),
+            theme: Theme::from_config(&config.theme),
+            cover: detect_graphics(config.album_art).map(CoverArt::new),
+            config,
         }
     }
 
     pub fn on_enter(&mut self) {
         match self.tabs.index {
+            0 => {
+                if let Some(track) = self.queue.skip_to(self.queue_selected()) {
+                    self.play_track(track);
+                    self.queue_select = 0;
+                }
+            }
             1 => {
                 if self.lib_cols.current_active == 2 {
-                    let track = self.lib_cols.tracks.items[self.lib_cols.tracks.selected].clone();
-                    let audio_copy = track.clone();
-
-                    if let Ok(()) = self.tx.send(audio_copy) {}
-                    self.now_playing = track;
+                    if let Some(track) = self.lib_cols.tracks.selected_item().cloned() {
+                        self.play_track(track);
+                    }
                 } else if self.lib_cols.current_active == 1 {
-                    let mut album =
-                        self.lib_cols.albums.items[self.lib_cols.albums.selected].clone();
-                    let track = album.tracks.remove(0);
-                    let audio_copy = track.clone();
-
-                    if let Ok(()) = self.tx.send(audio_copy) {}
-                    self.now_playing = track;
-
-                    while let Some(t) = album.tracks.pop() {
-                        self.queue.add_to_front(t);
+                    if let Some(album) = self.lib_cols.albums.selected_item() {
+                        self.play_now(album.tracks.clone());
                     }
                 }
             }
             2 => {
                 if self.search_input == "" && self.search_results.is_empty() {
                 } else if self.search_input == "" {
-                    match &self.search_results[self.search_select] {
-                        Media::Artist(_a) => {}
-                        Media::Album(a) => {
-                            let mut album = a.clone();
-                            let track = album.tracks.remove(0);
-                            let audio_copy = track.clone();
-
-                            if let Ok(()) = self.tx.send(audio_copy) {}
-                            self.now_playing = track;
-
-                            while let Some(t) = album.tracks.pop() {
-                                self.queue.add_to_front(t);
-                            }
-                        }
-                        Media::Track(t) => {
-                            let track = t.clone();
-                            let audio_copy = track.clone();
-                            if let Ok(()) = self.tx.send(audio_copy) {}
-                            self.now_playing = track;
-                        }
-                    }
+                    // Artists and albums play from their first track, with
+                    // the rest put at the front of the queue in order
+                    let tracks = media_tracks(&self.search_results[self.search_select]);
+                    self.play_now(tracks);
                 } else {
                     self.search();
                 }
             }
+            3 => {
+                if let Some(track) = self.favorites.selected_item().cloned() {
+                    self.play_track(track);
+                }
+            }
+            4 => self.select_device(),
+            5 => {
+                if let Some(album) = self.recent.selected_item() {
+                    self.play_now(album.tracks.clone());
+                }
+            }
+            6 => {
+                if let Some(track) = self.played.selected_item().cloned() {
+                    self.play_track(track);
+                }
+            }
             _ => {}
         }
     }
 
-    pub fn play_from_queue(&mut self) {
-        let track = self.queue.take();
-        let audio_copy = track.clone();
-        if let Ok(()) = self.tx.send(audio_copy) {}
+    // Play the first of the tracks right away and the others after it,
+    // ahead of what was already queued
+    fn play_now(&mut self, mut tracks: Vec<Track>) {
+        if tracks.is_empty() {
+            return;
+        }
+
+        // A looping track has nothing after it in the sink
+        if self.looping || tracks.len() == 1 {
+            let track = tracks.remove(0);
+            self.play_track(track);
+            while let Some(t) = tracks.pop() {
+                self.queue.add_to_front(t);
+            }
+            return;
+        }
+
+        // Everything goes into the sink at once to play without gaps, and
+        // the rest also on the front of the queue, where each comes off as
+        // the sink gets to it
+        self.keep_resume_position();
+        let position = self.resume_position(&tracks[0]);
+        if self.channels.tx.send((tracks.clone(), position)).is_err() {
+            warn!("The audio thread did not get the tracks to play");
+            self.lose_audio();
+            return;
+        }
+        let track = tracks.remove(0);
+        if track.file_path != self.now_playing.file_path {
+            self.remember_now_playing();
+        }
+        self.start_clock(track.start_at(position));
         self.now_playing = track;
+        self.preloaded = tracks.iter().cloned().collect();
+        while let Some(t) = tracks.pop() {
+            self.queue.add_to_front(t);
+        }
     }
 
-    pub fn pause_play(&mut self) {
-        if let Ok(()) = self.ptx.send(true) {}
+    // Hand a track to the audio thread, replacing whatever is playing.
+    // The sink is rebuilt, so anything preloaded into it is gone as well
+    fn play_track(&mut self, track: Track) {
+        // Playing the same track again picks up where it is now
+        self.keep_resume_position();
+        let position = self.resume_position(&track);
+        self.play_track_at(track, position);
     }
 
-    pub fn add_to_queue(&mut self) {
-        match self.tabs.index {
-            1 => {
-                if self.lib_cols.current_active == 2 {
-                    let track = self.lib_cols.tracks.items[self.lib_cols.tracks.selected].clone();
-                    self.queue.add(track);
-                } else if self.lib_cols.current_active == 1 {
-                    for t in &self.lib_cols.albums.items[self.lib_cols.albums.selected].tracks {
-                        self.queue.add(t.clone());
-                    }
-                } else {
-                    for a in &self.lib_cols.artists.items[self.lib_cols.artists.selected].albums {
-                        for t in &a.tracks {
-                            self.queue.add(t.clone());
-                        }
-                    }
-                }
+    // Where to start a track played from the top: where it was left off if
+    // it is long enough to be remembered, otherwise the beginning
+    fn resume_position(&mut self, track: &Track) -> Duration {
+        if !self.is_long(track) {
+            return Duration::from_secs(0);
+        }
+
+        match self.resume_positions.get(&track.file_path) {
+            Some(position) => {
+                let message = format!("Resuming {} at {}", track.title, clock(position));
+                self.set_status(message, STATUS_TIMEOUT);
+                position
             }
-            2 => match &self.search_results[self.search_select] {
-                Media::Artist(a) => {
-                    for album in &a.albums {
-                        for t in &album.tracks {
-                            self.queue.add(t.clone());
-                        }
-                    }
-                }
-                Media::Album(a) => {
-                    for t in &a.tracks {
-                        self.queue.add(t.clone());
-                    }
-                }
-                Media::Track(t) => {
-                    let track = t.clone();
-                    self.queue.add(track);
-                }
+            None => Duration::from_secs(0),
+        }
+    }
+
+    fn is_long(&self, track: &Track) -> bool {
+        let min = self.config.resume_min_minutes;
+        min > 0 && u64::from(track.duration) >= min * 60 * 1000
+    }
+
+    // Note where the playing track is at if it is long enough to pick up
+    // from there later. Near either end it just starts over next time
+    pub fn keep_resume_position(&mut self) {
+        if self.now_playing.file_path.is_empty() || !self.is_long(&self.now_playing) {
+            return;
+        }
+
+        let position = self.elapsed();
+        let end = Duration::from_millis(u64::from(self.now_playing.duration));
+        if position < RESUME_MARGIN || position + RESUME_MARGIN >= end {
+            self.resume_positions.remove(&self.now_playing.file_path);
+        } else {
+            self.resume_positions
+                .set(&self.now_playing.file_path, position);
+        }
+    }
+
+    fn play_track_at(&mut self, track: Track, position: Duration) {
+        if track.file_path != self.now_playing.file_path {
+            self.remember_now_playing();
+        }
+        self.switch_to(track, position);
+    }
+
+    // Like play_track_at, but without putting what was playing into the
+    // history, for going back through it
+    fn switch_to(&mut self, track: Track, position: Duration) {
+        let audio_copy = track.clone();
+        if self.channels.tx.send((vec![audio_copy], position)).is_err() {
+            warn!("The audio thread did not get the track to play");
+            self.lose_audio();
+            return;
+        }
+        self.start_clock(track.start_at(position));
+        self.now_playing = track;
+        self.preloaded.clear();
+    }
+
+    fn start_clock(&mut self, position: Duration) {
+        self.elapsed_offset = position;
+        self.resumed_at = Some(Instant::now());
+    }
+
+    // How far playback has got into now_playing
+    pub fn elapsed(&self) -> Duration {
+        match self.resumed_at {
+            Some(resumed_at) => self.elapsed_offset + self.played_since(resumed_at),
+            None => self.elapsed_offset,
+        }
+    }
+
+    // Time into the track covered since an instant, which runs ahead of or
+    // behind the wall clock when the speed is changed
+    fn played_since(&self, instant: Instant) -> Duration {
+        instant.elapsed().mul_f32(self.speed)
+    }
+
+    // Step the playback speed up or down, or back to normal with None
+    pub fn change_speed(&mut self, step: Option<f32>) {
+        let speed = match step {
+            Some(step) => (self.speed + step).clamp(MIN_SPEED, MAX_SPEED),
+            None => 1.0,
+        };
+        if (speed - self.speed).abs() < f32::EPSILON {
+            return;
+        }
+
+        self.speed = speed;
+        if self.channels.stx.send(speed).is_err() {
+            warn!("The audio thread did not get the speed change");
+            self.lose_audio();
+        }
+        self.restart_in_place();
+    }
+
+    // Step through the equalizer presets, starting over from flat after
+    // the last one or when the bands have been changed by hand
+    pub fn cycle_eq_preset(&mut self) {
+        let next = match PRESETS.iter().position(|(_, g)| *g == self.equalizer) {
+            Some(i) => (i + 1) % PRESETS.len(),
+            None => 0,
+        };
+        let (name, gains) = PRESETS[next];
+        self.set_equalizer(gains);
+        self.set_status(format!("Equalizer: {}", name), STATUS_TIMEOUT);
+    }
+
+    // Pick the band before or after the current one in the settings tab
+    pub fn select_eq_band(&mut self, step: i32) {
+        let band = self.eq_band as i32 + step;
+        self.eq_band = band.clamp(0, BANDS.len() as i32 - 1) as usize;
+    }
+
+    // Turn the picked band up or down by some dB
+    pub fn change_eq_gain(&mut self, step: f32) {
+        let mut gains = self.equalizer;
+        gains[self.eq_band] = (gains[self.eq_band] + step).clamp(-MAX_GAIN, MAX_GAIN);
+        if gains != self.equalizer {
+            self.set_equalizer(gains);
+        }
+    }
+
+    fn set_equalizer(&mut self, gains: Gains) {
+        self.equalizer = gains;
+        if self.channels.eqtx.send(gains).is_err() {
+            warn!("The audio thread did not get the equalizer change");
+            self.lose_audio();
+        }
+        self.restart_in_place();
+    }
+
+    // Move the balance a step towards the right, or the left if negative
+    pub fn change_balance(&mut self, step: f32) {
+        // Kept to tenths so stepping back lands on the middle exactly
+        let balance = ((self.balance + step).clamp(-1.0, 1.0) * 10.0).round() / 10.0;
+        if (balance - self.balance).abs() < f32::EPSILON {
+            return;
+        }
+
+        self.balance = balance;
+        if self.channels.baltx.send(balance).is_err() {
+            warn!("The audio thread did not get the balance change");
+            self.lose_audio();
+        }
+        self.set_status(
+            format!("Balance: {}", balance_label(balance)),
+            STATUS_TIMEOUT,
+        );
+        self.restart_in_place();
+    }
+
+    // Restart the track where it was so a change to the speed, equalizer
+    // or balance is heard now rather than from the next track on, keeping
+    // it paused if it was
+    fn restart_in_place(&mut self) {
+        if self.now_playing.file_path.is_empty() {
+            return;
+        }
+
+        let position = self.elapsed();
+        let paused = self.resumed_at.is_none();
+        let track = self.now_playing.clone();
+        self.play_track_at(track, position);
+        if paused {
+            self.pause_play();
+        }
+    }
+
+    // Move through the current track by some seconds, back if negative,
+    // stopping short of the end
+    pub fn seek(&mut self, seconds: i64) {
+        let position = self.elapsed().as_millis() as i64 + seconds * 1000;
+        self.seek_to(Duration::from_millis(position.max(0) as u64));
+    }
+
+    // Play the current track from a position, keeping it paused if it was
+    pub fn seek_to(&mut self, position: Duration) {
+        if self.now_playing.file_path.is_empty() {
+            return;
+        }
+
+        let end = Duration::from_millis(u64::from(self.now_playing.duration));
+        let position = position.min(end.saturating_sub(Duration::from_secs(1)));
+
+        let paused = self.resumed_at.is_none();
+        let track = self.now_playing.clone();
+        self.play_track_at(track, position);
+        if paused {
+            self.pause_play();
+        }
+    }
+
+    // Pick up where the last session left off, provided the file is still
+    // around. It doesn't have to be part of the library
+    pub fn resume(&mut self, file_path: &str, position: Duration) {
+        if file_path.is_empty() || !Path::new(file_path).exists() {
+            return;
+        }
+
+        let track = match self.lib_cols.find_track(file_path) {
+            Some(t) => t.clone(),
+            None => match Track::new(PathBuf::from(file_path), &self.config.artist_separators) {
+                Ok(t) => t,
+                Err(_) => return,
             },
-            _ => {}
+        };
+
+        self.play_track_at(track, position);
+    }
+
+    // Give the audio thread the head of the queue ahead of time so it can
+    // start it the moment the current track ends, without a gap
+    pub fn preload_next(&mut self) {
+        if !self.preloaded.is_empty() || self.now_playing.file_path.is_empty() {
+            return;
+        }
+
+        // Nothing should follow a track the sleep timer is waiting on, or
+        // one that is looping
+        if self.sleep_expired() || self.looping {
+            return;
+        }
+
+        if let Some(next) = self.queue.peek() {
+            // A track to be picked up partway through is started by itself
+            // once this one ends
+            if self.is_long(next) && self.resume_positions.get(&next.file_path).is_some() {
+                return;
+            }
+            let track = next.clone();
+            if self.channels.ltx.send(track.clone()).is_err() {
+                warn!("The audio thread did not get the track to preload");
+                self.lose_audio();
+            }
+            self.preloaded.push_back(track);
         }
     }
 
-    pub fn add_to_front(&mut self) {
-        if let 1 = self.tabs.index {
-            if self.lib_cols.current_active == 2 {
-                let track = self.lib_cols.tracks.items[self.lib_cols.tracks.selected].clone();
-                self.queue.add_to_front(track);
-            } else if self.lib_cols.current_active == 1 {
-                let mut tracklist = self.lib_cols.albums.items[self.lib_cols.albums.selected]
-                    .tracks
-                    .clone();
-                while let Some(t) = tracklist.pop() {
-                    self.queue.add_to_front(t);
-                }
-            } else {
-                for a in &self.lib_cols.artists.items[self.lib_cols.artists.selected].albums {
-                    let mut tracklist = a.tracks.clone();
-                    while let Some(t) = tracklist.pop() {
-                        self.queue.add_to_front(t);
-                    }
-                }
+    // The audio thread finished the current track and moved straight on to
+    // the preloaded one
+    pub fn advance(&mut self) {
+        self.finish_track();
+
+        if let Some(track) = self.preloaded.pop_front() {
+            // The queue may have been reordered since the track was preloaded
+            if let Some(i) = self
+                .queue
+                .tracks
+                .iter()
+                .position(|t| t.file_path == track.file_path)
+            {
+                self.queue.take_at(i);
             }
+            self.remember_now_playing();
+            self.start_clock(track.start_at(Duration::from_secs(0)));
+            self.now_playing = track;
+        } else if self.looping {
+            // The audio thread started the same track over
+            self.start_clock(self.now_playing.start_at(Duration::from_secs(0)));
         }
     }
 
-    pub fn clear_queue(&mut self) {
-        if let Ok(()) = self.ptx.send(false) {}
-        self.queue.clear();
-        self.blank_now_play();
+    // Loop the current track, or go back to carrying on with the queue
+    // once it ends. The queue itself is left alone either way
+    pub fn toggle_loop(&mut self) {
+        self.looping = !self.looping;
+        if self.channels.looptx.send(self.looping).is_err() {
+            warn!("The audio thread did not get the loop toggle");
+            self.lose_audio();
+        }
+
+        // A preloaded track is already in the sink behind the current one,
+        // so the sink is rebuilt at the same spot without it
+        if self.looping && !self.preloaded.is_empty() {
+            let track = self.now_playing.clone();
+            let position = self.elapsed();
+            self.play_track_at(track, position);
+        }
     }
 
-    pub fn blank_now_play(&mut self) {
-        match self.now_playing.title.as_ref() {
-            "" => {}
-            _ => {
-                self.now_playing = Track::dummy();
+    pub fn repeat_mode(&self) -> RepeatMode {
+        if self.looping {
+            RepeatMode::One
+        } else if self.queue.repeat {
+            RepeatMode::All
+        } else {
+            RepeatMode::Off
+        }
+    }
+
+    // Go from no repeat to repeating the queue, then the current track,
+    // then back to none
+    pub fn cycle_repeat(&mut self) {
+        let message = match self.repeat_mode() {
+            RepeatMode::Off => {
+                self.queue.repeat = true;
+                "Repeating the queue"
             }
+            RepeatMode::All => {
+                self.queue.repeat = false;
+                self.toggle_loop();
+                "Repeating the current track"
+            }
+            RepeatMode::One => {
+                self.queue.repeat = false;
+                self.toggle_loop();
+                "Repeat off"
+            }
+        };
+        self.set_status(message.to_string(), STATUS_TIMEOUT);
+    }
+
+    // While on, tracks added to the queue go in at random places. Turning
+    // it on shuffles what is queued already
+    pub fn toggle_shuffle_mode(&mut self) {
+        self.queue.shuffling = !self.queue.shuffling;
+        let message = if self.queue.shuffling {
+            self.queue.shuffle_remaining();
+            "Shuffle on"
+        } else {
+            "Shuffle off"
+        };
+        self.set_status(message.to_string(), STATUS_TIMEOUT);
+    }
+
+    // Play the head of the queue. With nothing left in it, now playing is
+    // cleared instead
+    pub fn play_from_queue(&mut self) {
+        // Leave the queue be when nothing can play it
+        if self.audio_lost {
+            self.lose_audio();
+            return;
+        }
+        match self.queue.take() {
+            Some(track) => self.play_track(track),
+            None => self.blank_now_play(),
         }
     }
 
-    pub fn search(&mut self) {
-        if self.search_input == "" {
+    // Play the tracks in order right away, ahead of whatever was queued
+    pub fn play_tracks(&mut self, tracks: Vec<Track>) {
+        for track in tracks.into_iter().rev() {
+            self.queue.add_to_front(track);
+        }
+        if !self.queue.is_empty() {
+            self.play_from_queue();
+        }
+    }
+
+    // Cut the current track short for the next one in the queue, or stop if
+    // there isn't one. A skipped track doesn't count as played
+    pub fn skip_next(&mut self) {
+        if self.queue.is_empty() {
+            self.stop();
+        } else {
+            self.play_from_queue();
+        }
+    }
+
+    // Go back to the track played before this one, which goes back on the
+    // front of the queue. A few seconds into a track this starts it over
+    // instead, the way most players behave
+    pub fn skip_previous(&mut self) {
+        let playing = !self.now_playing.file_path.is_empty();
+        if playing && (self.elapsed() > RESTART_AFTER || self.history.is_empty()) {
+            let track = self.now_playing.clone();
+            self.switch_to(track, Duration::from_secs(0));
             return;
         }
 
-        let query_term = SearchQuery::new(self.search_input.as_str());
-        self.search_input = String::new();
+        if let Some(track) = self.history.pop() {
+            if playing {
+                self.queue.add_to_front(self.now_playing.clone());
+            }
+            self.switch_to(track, Duration::from_secs(0));
+            self.reload_played();
+        }
+    }
+
+    // Called when the sink turns up empty. If that is because the audio
+    // thread couldn't open or decode the current track, the track is
+    // skipped over without counting as played and the queue carries on.
+    // Returns true if that was the case
+    pub fn skip_unreadable(&mut self) -> bool {
+        self.drop_unreadable();
+        let unreadable = self.unreadable.contains(&self.now_playing.file_path);
+        self.unreadable.clear();
+        if !unreadable {
+            return false;
+        }
+
+        self.set_status(skipped_message(&self.now_playing), ERROR_TIMEOUT);
+
+        self.now_playing = Track::dummy();
+        self.preloaded.clear();
+        self.elapsed_offset = Duration::from_secs(0);
+        self.resumed_at = None;
+
+        // Another unreadable track ends up back here on the next empty sink
+        if !self.sleep_expired() {
+            self.play_from_queue();
+        }
+
+        true
+    }
+
+    // Go through the tracks the audio thread couldn't open. A preloaded one
+    // never made it into the sink, so it is taken off the queue straight
+    // away, as if it had played. Anything else waits for the empty sink,
+    // see skip_unreadable
+    pub fn drop_unreadable(&mut self) {
+        while let Ok(file_path) = self.channels.erx.try_recv() {
+            let preloaded = self.preloaded.iter().position(|t| t.file_path == file_path);
+            let track = match preloaded {
+                Some(i) if file_path != self.now_playing.file_path => self.preloaded.remove(i),
+                _ => None,
+            };
 
-        if query_term.is_none() {
+            match track {
+                Some(track) => {
+                    if let Some(i) = self
+                        .queue
+                        .tracks
+                        .iter()
+                        .position(|t| t.file_path == track.file_path)
+                    {
+                        self.queue.take_at(i);
+                    }
+                    self.set_status(skipped_message(&track), ERROR_TIMEOUT);
+                }
+                None => self.unreadable.push(file_path),
+            }
+        }
+    }
+
+    // Put up a status saying playback has stopped working, once the audio
+    // thread is found to be gone, so it doesn't look like it carried on
+    pub fn lose_audio(&mut self) {
+        if !self.audio_lost {
+            warn!("The audio thread has stopped, playback is unavailable");
+        }
+        self.audio_lost = true;
+        self.set_status(
+            "Playback stopped working, restart sonik (see the log)".to_string(),
+            AUDIO_LOST_TIMEOUT,
+        );
+    }
+
+    // Show a message in the status bar for ttl, replacing any message
+    // already there. It is dropped on the first redraw after that
+    pub fn set_status(&mut self, message: String, ttl: Duration) {
+        self.status_message = Some((message, Instant::now() + ttl));
+    }
+
+    // The status message, for as long as it should stay up
+    pub fn status(&self) -> Option<&str> {
+        match &self.status_message {
+            Some((message, until)) if Instant::now() < *until => Some(message),
+            _ => None,
+        }
+    }
+
+    // Make playback of the current track start from where it is now from
+    // here on, to skip a silent intro. Doing it within a second of where the
+    // track starts clears it instead
+    pub fn set_start_offset(&mut self) {
+        if self.now_playing.file_path.is_empty() {
             return;
         }
 
-        self.search_results = match db_search(&self.fuzzy_searcher, query_term.unwrap()) {
-            SearchResult::Artists(r) => r
-                .iter()
-                .map(|x| Media::Artist(self.lib_cols.artists.items[*x].clone()))
-                .collect(),
-            SearchResult::Albums(r) => r
-                .iter()
-                .map(|x| Media::Album(self.lib_cols.artists.items[x.0].albums[x.1].clone()))
-                .collect(),
-            SearchResult::Tracks(r) => r
-                .iter()
-                .map(|x| {
-                    Media::Track(self.lib_cols.artists.items[x.0].albums[x.1].tracks[x.2].clone())
-                })
-                .collect(),
+        let offset = self.elapsed().as_millis() as u64;
+        let offset = if offset < self.now_playing.start_offset + 1000 {
+            0
+        } else {
+            offset
+        };
+        self.update_now_playing(|t| t.start_offset = offset);
+
+        let message = if offset == 0 {
+            format!("Start of {} cleared", self.now_playing.title)
+        } else {
+            format!(
+                "{} now starts at {}",
+                self.now_playing.title,
+                clock(Duration::from_millis(offset))
+            )
         };
+        self.set_status(message, STATUS_TIMEOUT);
     }
 
-    pub fn on_up_search(&mut self) {
-        if self.search_select > 0 {
-            self.search_select -= 1;
+    // Make a change to the playing track, along with its copies in the
+    // library and the queue. The library's copy is the one saved with the
+    // database
+    fn update_now_playing<F>(&mut self, change: F)
+    where
+        F: Fn(&mut Track),
+    {
+        change(&mut self.now_playing);
+        if let Some(track) = self.lib_cols.find_track_mut(&self.now_playing.file_path) {
+            change(track);
+            self.lib_cols.refresh();
+        }
+        for track in self.queue.tracks.iter_mut() {
+            if track.file_path == self.now_playing.file_path {
+                change(track);
+            }
+        }
+    }
+
+    // Open the prompt for naming a bookmark at the current position
+    pub fn start_bookmark(&mut self) {
+        if self.now_playing.file_path.is_empty() {
+            return;
+        }
+        self.bookmark_input = Some((String::new(), self.elapsed()));
+    }
+
+    pub fn bookmark_active(&self) -> bool {
+        self.bookmark_input.is_some()
+    }
+
+    pub fn bookmark_type(&mut self, c: char) {
+        if let Some((name, _)) = self.bookmark_input.as_mut() {
+            name.push(c);
+        }
+    }
+
+    pub fn bookmark_backspace(&mut self) {
+        if let Some((name, _)) = self.bookmark_input.as_mut() {
+            name.pop();
+        }
+    }
+
+    pub fn cancel_bookmark(&mut self) {
+        self.bookmark_input = None;
+    }
+
+    // Add the bookmark being named to the playing track. Without a name it
+    // goes by its time. One within a second of another replaces it
+    pub fn confirm_bookmark(&mut self) {
+        let (name, position) = match self.bookmark_input.take() {
+            Some(input) => input,
+            None => return,
+        };
+        let name = if name.trim().is_empty() {
+            clock(position)
         } else {
-            self.search_select = self.search_results.len() - 1;
+            name.trim().to_string()
+        };
+
+        let message = format!("Bookmarked {} at {}", name, clock(position));
+        self.update_now_playing(|t| {
+            t.bookmarks.retain(|(_, at)| {
+                (*at).max(position) - (*at).min(position) >= Duration::from_secs(1)
+            });
+            t.bookmarks.push((name.clone(), position));
+            t.bookmarks.sort_by_key(|(_, at)| *at);
+        });
+        self.set_status(message, STATUS_TIMEOUT);
+    }
+
+    // Seek to the playing track's next bookmark, going round to the first
+    // after the last
+    pub fn next_bookmark(&mut self) {
+        let position = self.elapsed() + Duration::from_secs(1);
+        let bookmarks = &self.now_playing.bookmarks;
+        let next = bookmarks
+            .iter()
+            .find(|(_, at)| *at > position)
+            .or_else(|| bookmarks.first())
+            .cloned();
+
+        match next {
+            Some((name, at)) => {
+                self.seek_to(at);
+                self.set_status(format!("Bookmark: {}", name), STATUS_TIMEOUT);
+            }
+            None if !self.now_playing.file_path.is_empty() => {
+                let message = format!("{} has no bookmarks", self.now_playing.title);
+                self.set_status(message, STATUS_TIMEOUT);
+            }
+            None => {}
         }
     }
 
-    pub fn on_down_search(&mut self) {
-        self.search_select = (self.search_select + 1) % self.search_results.len();
+    // Open the prompt for a folder to queue up without adding it to the
+    // library
+    pub fn start_open_folder(&mut self) {
+        self.folder_input = Some(String::new());
+    }
+
+    pub fn folder_active(&self) -> bool {
+        self.folder_input.is_some()
+    }
+
+    pub fn folder_type(&mut self, c: char) {
+        if let Some(folder) = self.folder_input.as_mut() {
+            folder.push(c);
+        }
+    }
+
+    pub fn folder_backspace(&mut self) {
+        if let Some(folder) = self.folder_input.as_mut() {
+            folder.pop();
+        }
+    }
+
+    pub fn cancel_open_folder(&mut self) {
+        self.folder_input = None;
+    }
+
+    // Add the music in the typed folder to the queue, read from the files
+    // and kept out of the library. A leading ~ is the home folder
+    pub fn confirm_open_folder(&mut self) {
+        let typed = match self.folder_input.take() {
+            Some(folder) => folder.trim().to_string(),
+            None => return,
+        };
+        if typed.is_empty() {
+            return;
+        }
+        let folder = match typed.strip_prefix('~') {
+            Some(rest) => match dirs::home_dir() {
+                Some(home) => home.join(rest.trim_start_matches('/')),
+                None => PathBuf::from(&typed),
+            },
+            None => PathBuf::from(&typed),
+        };
+        if !folder.is_dir() {
+            self.set_status(format!("{} is not a folder", typed), STATUS_TIMEOUT);
+            return;
+        }
+
+        let (tracks, unreadable) = read_folder(&folder, &self.config.artist_separators);
+        let added = tracks.len();
+        for t in tracks {
+            self.queue.add(t);
+        }
+
+        let mut message = format!("Added {} from {} to the queue", track_count(added), typed);
+        if unreadable > 0 {
+            message.push_str(&format!(", skipped {} unreadable", unreadable));
+        }
+        self.set_status(message, STATUS_TIMEOUT);
+    }
+
+    fn remember_now_playing(&mut self) {
+        if self.now_playing.file_path.is_empty() {
+            return;
+        }
+        self.keep_resume_position();
+
+        self.history.push(self.now_playing.clone());
+        if self.history.len() > self.config.history_length {
+            self.history.remove(0);
+        }
+        self.reload_played();
+    }
+
+    // Bring back the history of an earlier session from the paths of its
+    // tracks, oldest first. Tracks no longer in the library are left out
+    pub fn restore_history(&mut self, file_paths: &[String]) {
+        self.history = file_paths
+            .iter()
+            .filter_map(|p| self.lib_cols.find_track(p).cloned())
+            .collect();
+        let excess = self
+            .history
+            .len()
+            .saturating_sub(self.config.history_length);
+        self.history.drain(..excess);
+        self.reload_played();
+    }
+
+    // Switch the played tab between recently and most played
+    pub fn toggle_most_played(&mut self) {
+        self.most_played = !self.most_played;
+        self.played.selected = 0;
+        self.reload_played();
+    }
+
+    fn reload_played(&mut self) {
+        let played = if self.most_played {
+            most_played_tracks(&self.lib_cols.artists.items)
+        } else {
+            let mut seen = HashSet::new();
+            self.history
+                .iter()
+                .rev()
+                .filter(|t| seen.insert(t.file_path.clone()))
+                .cloned()
+                .collect()
+        };
+
+        let selected = self.played.selected;
+        self.played = ListState::new(&played);
+        self.played.selected = selected.min(played.len().saturating_sub(1));
+    }
+
+    pub fn pause_play(&mut self) {
+        if self.channels.ptx.send(true).is_err() {
+            warn!("The audio thread did not get the pause/stop");
+            self.lose_audio();
+        }
+
+        if self.now_playing.file_path.is_empty() {
+            return;
+        }
+
+        // Mirror the pause on the playback clock
+        match self.resumed_at.take() {
+            Some(resumed_at) => self.elapsed_offset += self.played_since(resumed_at),
+            None => self.resumed_at = Some(Instant::now()),
+        }
+    }
+
+    // Called when the sink empties on its own, i.e. the track was heard
+    // through to the end rather than skipped. The count is kept on the
+    // library's copy of the track since that is what gets saved on exit
+    pub fn finish_track(&mut self) {
+        if self.now_playing.file_path.is_empty() {
+            return;
+        }
+
+        if let Some(track) = self.lib_cols.find_track_mut(&self.now_playing.file_path) {
+            track.play_count += 1;
+            self.lib_cols.refresh();
+            self.reload_played();
+        }
+    }
+
+    // Mark or unmark the selected item in the library or search results
+    pub fn toggle_mark(&mut self) {
+        match self.tabs.index {
+            1 => self.lib_cols.toggle_mark(),
+            2 if !self.search_results.is_empty() => {
+                toggle_mark(&mut self.search_marked, self.search_select)
+            }
+            _ => {}
+        }
+    }
+
+    // Tracks of everything marked in the current tab, in list order. The
+    // marks are cleared, since they have been used
+    fn take_marked(&mut self) -> Vec<Track> {
+        match self.tabs.index {
+            1 => self.lib_cols.take_marked_tracks(),
+            2 => {
+                let mut indices: Vec<usize> = self.search_marked.drain().collect();
+                indices.sort();
+                indices
+                    .into_iter()
+                    .filter_map(|i| self.search_results.get(i))
+                    .flat_map(media_tracks)
+                    .collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn add_to_queue(&mut self) {
+        let before = self.queue.tracks.len();
+        self.enqueue_selected();
+
+        let added = self.queue.tracks.len() - before;
+        if added > 0 {
+            let message = format!("Added {} to the queue", track_count(added));
+            self.set_status(message, STATUS_TIMEOUT);
+        }
+    }
+
+    // Like add_to_queue, with what is added shuffled among itself first,
+    // to shuffle an album without shuffling the whole queue
+    pub fn add_to_queue_shuffled(&mut self) {
+        let mut tracks = self.selected_for_queue();
+        tracks.shuffle(&mut thread_rng());
+
+        let added = tracks.len();
+        for t in tracks {
+            self.queue.add(t);
+        }
+        if added > 0 {
+            let message = format!("Added {} to the queue, shuffled", track_count(added));
+            self.set_status(message, STATUS_TIMEOUT);
+        }
+    }
+
+    fn enqueue_selected(&mut self) {
+        for t in self.selected_for_queue() {
+            self.queue.add(t);
+        }
+    }
+
+    // What Space adds to the queue: the marked tracks if there are any,
+    // otherwise whatever is selected in the current tab
+    fn selected_for_queue(&mut self) -> Vec<Track> {
+        let marked = self.take_marked();
+        if !marked.is_empty() {
+            return marked;
+        }
+
+        let mut tracks = Vec::new();
+        match self.tabs.index {
+            1 => {
+                let lib = &self.lib_cols;
+                if lib.current_active == 2 {
+                    tracks.extend(lib.tracks.selected_item().cloned());
+                } else if lib.current_active == 1 {
+                    if let Some(album) = lib.albums.selected_item() {
+                        tracks.extend_from_slice(&album.tracks);
+                    }
+                } else if let Some(artist) = lib.artists.selected_item() {
+                    for a in &artist.albums {
+                        tracks.extend_from_slice(&a.tracks);
+                    }
+                }
+            }
+            2 => match self.search_results.get(self.search_select) {
+                Some(Media::Artist(a)) => {
+                    for album in &a.albums {
+                        tracks.extend_from_slice(&album.tracks);
+                    }
+                }
+                Some(Media::Album(a)) => tracks.extend_from_slice(&a.tracks),
+                Some(Media::Track(t)) => tracks.push(t.clone()),
+                None => {}
+            },
+            3 => tracks.extend(self.favorites.selected_item().cloned()),
+            5 => {
+                if let Some(album) = self.recent.selected_item() {
+                    tracks.extend_from_slice(&album.tracks);
+                }
+            }
+            6 => tracks.extend(self.played.selected_item().cloned()),
+            _ => {}
+        }
+
+        tracks
+    }
+
+    pub fn add_to_front(&mut self) {
+        let before = self.queue.tracks.len();
+        self.enqueue_selected_next();
+
+        let added = self.queue.tracks.len() - before;
+        if added > 0 {
+            let message = format!("Playing {} next", track_count(added));
+            self.set_status(message, STATUS_TIMEOUT);
+        }
+    }
+
+    // Tracks go into the queue's up next lane, after any already there
+    fn enqueue_selected_next(&mut self) {
+        let marked = self.take_marked();
+        if !marked.is_empty() {
+            for t in marked {
+                self.queue.add_next(t);
+            }
+            return;
+        }
+
+        if let 1 = self.tabs.index {
+            if self.lib_cols.current_active == 2 {
+                if let Some(track) = self.lib_cols.tracks.selected_item() {
+                    self.queue.add_next(track.clone());
+                }
+            } else if self.lib_cols.current_active == 1 {
+                if let Some(album) = self.lib_cols.albums.selected_item() {
+                    for t in &album.tracks {
+                        self.queue.add_next(t.clone());
+                    }
+                }
+            } else if let Some(artist) = self.lib_cols.artists.selected_item() {
+                for a in &artist.albums {
+                    for t in &a.tracks {
+                        self.queue.add_next(t.clone());
+                    }
+                }
+            }
+        }
+    }
+
+    // Stop the current track but leave the queue as it is
+    pub fn stop(&mut self) {
+        if self.channels.ptx.send(false).is_err() {
+            warn!("The audio thread did not get the pause/stop");
+            self.lose_audio();
+        }
+        self.preloaded.clear();
+        self.blank_now_play();
+    }
+
+    // Keep the cover in line with the playing album, given where it goes on
+    // screen, if anywhere. Returns true if the screen needs a full redraw
+    pub fn update_cover(&mut self, area: Option<Rect>) -> bool {
+        let path = self.lib_cols.cover_of(&self.now_playing.file_path);
+        match &mut self.cover {
+            Some(cover) => cover.update(path, area),
+            None => false,
+        }
+    }
+
+    pub fn reveal_now_playing(&mut self) {
+        self.reveal_playing(2);
+    }
+
+    // Browse the rest of what the playing track's artist or album has
+    pub fn go_to_playing_artist(&mut self) {
+        self.reveal_playing(0);
+    }
+
+    pub fn go_to_playing_album(&mut self) {
+        self.reveal_playing(1);
+    }
+
+    // Open the library on the playing track with column active
+    fn reveal_playing(&mut self, column: usize) {
+        if self.now_playing.file_path.is_empty() {
+            return;
+        }
+
+        if self.lib_cols.reveal(&self.now_playing.file_path, column) {
+            self.tabs.index = 1;
+        } else {
+            let message = format!("{} isn't in the library", self.now_playing.title);
+            self.set_status(message, STATUS_TIMEOUT);
+        }
+    }
+
+    // Open the library on an album picked at random, for when nothing in
+    // particular comes to mind. An empty library does nothing
+    pub fn random_album(&mut self) {
+        let albums: Vec<&Album> = library::albums(&self.lib_cols.artists.items).collect();
+        let file_path = match albums.choose(&mut thread_rng()) {
+            Some(album) => match album.tracks.first() {
+                Some(t) => t.file_path.clone(),
+                None => return,
+            },
+            None => return,
+        };
+
+        if self.lib_cols.reveal(&file_path, 1) {
+            self.tabs.index = 1;
+        }
+    }
+
+    // Play a track picked at random from the library straight away
+    pub fn random_track(&mut self) {
+        let tracks: Vec<&Track> = library::tracks(&self.lib_cols.artists.items).collect();
+
+        if let Some(track) = tracks.choose(&mut thread_rng()).map(|t| (*t).clone()) {
+            self.play_tracks(vec![track]);
+        }
+    }
+
+    // Play every track by the selected artist in random order, ahead of
+    // whatever was queued, which is left as it was
+    pub fn shuffle_artist(&mut self) {
+        if self.tabs.index != 1 || self.lib_cols.current_active != 0 {
+            return;
+        }
+        let artist = match self.lib_cols.artists.selected_item() {
+            Some(artist) => artist,
+            None => return,
+        };
+
+        let mut tracks: Vec<Track> = artist
+            .albums
+            .iter()
+            .flat_map(|al| al.tracks.iter().cloned())
+            .collect();
+        tracks.shuffle(&mut thread_rng());
+        self.play_now(tracks);
+    }
+
+    // Clearing is easy to hit by accident, so unless turned off in the
+    // config it only happens once the user confirms it
+    pub fn request_clear_queue(&mut self) {
+        if self.config.confirm_clear {
+            self.clear_armed = true;
+        } else {
+            self.clear_queue();
+        }
+    }
+
+    pub fn confirm_clear_queue(&mut self) {
+        self.clear_armed = false;
+        self.clear_queue();
+    }
+
+    pub fn cancel_clear_queue(&mut self) {
+        self.clear_armed = false;
+    }
+
+    pub fn clear_queue(&mut self) {
+        if self.channels.ptx.send(false).is_err() {
+            warn!("The audio thread did not get the pause/stop");
+            self.lose_audio();
+        }
+        self.preloaded.clear();
+        self.queue.clear();
+        self.blank_now_play();
+        self.set_status("Queue cleared".to_string(), STATUS_TIMEOUT);
+    }
+
+    // Sort the queue by the next field along, or after the last one put it
+    // back the way it was. Straight after a shuffle it goes back first
+    pub fn cycle_queue_sort(&mut self) {
+        let next = match self.queue.shuffled {
+            true => None,
+            false => QueueSortField::after(self.queue.sorted_by),
+        };
+        let message = match next {
+            Some(field) => {
+                self.queue.sort_by_field(field);
+                format!("Queue sorted by {}", field.name())
+            }
+            None => {
+                self.queue.unsort();
+                "Queue back in the order it was added".to_string()
+            }
+        };
+        self.set_status(message, STATUS_TIMEOUT);
+    }
+
+    pub fn shuffle_queue(&mut self) {
+        self.queue.shuffle_remaining();
+        self.set_status(
+            "Queue shuffled after the next track".to_string(),
+            STATUS_TIMEOUT,
+        );
+    }
+
+    pub fn dedupe_queue(&mut self) {
+        let message = match self.queue.dedupe() {
+            0 => "No repeats in the queue".to_string(),
+            n => format!("Removed {} repeated in the queue", track_count(n)),
+        };
+        self.set_status(message, STATUS_TIMEOUT);
+    }
+
+    pub fn clear_played(&mut self) {
+        let message = match self.queue.clear_played() {
+            0 => "Nothing played left in the queue".to_string(),
+            n => format!("Removed {} already played", track_count(n)),
+        };
+        self.set_status(message, STATUS_TIMEOUT);
+    }
+
+    pub fn blank_now_play(&mut self) {
+        match self.now_playing.title.as_ref() {
+            "" => {}
+            _ => {
+                self.remember_now_playing();
+                self.now_playing = Track::dummy();
+                self.elapsed_offset = Duration::from_secs(0);
+                self.resumed_at = None;
+            }
+        }
+    }
+
+    // Back to an empty search tab, forgetting the last query's results
+    pub fn clear_search(&mut self) {
+        self.search_input.clear();
+        self.search_results.clear();
+        self.search_spans.clear();
+        self.search_marked.clear();
+        self.search_total = 0;
+        self.search_select = 0;
+    }
+
+    pub fn search(&mut self) {
+        if self.search_input == "" {
+            return;
+        }
+
+        let query_term = SearchQuery::new(self.search_input.as_str());
+        self.search_input = String::new();
+
+        let query_term = match query_term {
+            Some(q) => q,
+            None => return,
+        };
+
+        let words = match &query_term.terms {
+            Term::Title(s) | Term::Album(s) | Term::Artist(s) => s.clone(),
+            // Paths aren't shown, so there is nothing to highlight
+            Term::Rating(_) | Term::File(_) => String::new(),
+        };
+
+        // Marks belong to the results they were made on
+        self.search_marked.clear();
+        self.search_select = 0;
+
+        // Results come best first, so only the top ones are kept, and only
+        // those are copied out of the library
+        let limit = match self.config.search_limit {
+            0 => usize::MAX,
+            n => n,
+        };
+        let (total, results): (usize, Vec<Media>) = match db_search(
+            &self.fuzzy_searcher,
+            &self.lib_cols.artists.items,
+            query_term,
+        ) {
+            SearchResult::Artists(r) => (
+                r.len(),
+                r.iter()
+                    .take(limit)
+                    .map(|x| Media::Artist(self.lib_cols.artists.items[*x].clone()))
+                    .collect(),
+            ),
+            SearchResult::Albums(r) => (
+                r.len(),
+                r.iter()
+                    .take(limit)
+                    .map(|x| Media::Album(self.lib_cols.artists.items[x.0].albums[x.1].clone()))
+                    .collect(),
+            ),
+            SearchResult::Tracks(r) => (
+                r.len(),
+                r.iter()
+                    .take(limit)
+                    .map(|x| {
+                        Media::Track(
+                            self.lib_cols.artists.items[x.0].albums[x.1].tracks[x.2].clone(),
+                        )
+                    })
+                    .collect(),
+            ),
+        };
+        self.search_total = total;
+        self.search_spans = results
+            .iter()
+            .map(|m| match_spans(m.name(), &words))
+            .collect();
+        self.search_results = results;
+    }
+
+    // The queue shrinks as tracks are played, so the stored selection is
+    // kept within its bounds whenever it is used
+    pub fn queue_selected(&self) -> usize {
+        self.queue_select
+            .min(self.queue.tracks.len().saturating_sub(1))
+    }
+
+    pub fn on_up_queue(&mut self) {
+        if self.queue.is_empty() {
+            return;
+        }
+
+        self.queue_select = step_previous(
+            self.queue_selected(),
+            self.queue.tracks.len(),
+            self.config.wrap,
+        );
+    }
+
+    pub fn on_down_queue(&mut self) {
+        if self.queue.is_empty() {
+            return;
+        }
+
+        self.queue_select = step_next(
+            self.queue_selected(),
+            self.queue.tracks.len(),
+            self.config.wrap,
+        );
+    }
+
+    pub fn on_first_queue(&mut self) {
+        self.queue_select = 0;
+    }
+
+    pub fn on_last_queue(&mut self) {
+        self.queue_select = self.queue.tracks.len().saturating_sub(1);
+    }
+
+    // Open the prompt for finding tracks in the queue by title or artist
+    pub fn start_queue_find(&mut self) {
+        self.queue_find = Some(String::new());
+    }
+
+    pub fn queue_find_active(&self) -> bool {
+        self.tabs.index == 0 && self.queue_find.is_some()
+    }
+
+    pub fn queue_matches(&self) -> Vec<usize> {
+        match &self.queue_find {
+            Some(query) if !query.is_empty() => self.queue.find(query),
+            _ => Vec::new(),
+        }
+    }
+
+    // Typing keeps the selection where it is if it still matches
+    pub fn queue_find_type(&mut self, c: char) {
+        if let Some(query) = self.queue_find.as_mut() {
+            query.push(c);
+        }
+        self.select_queue_match(true, false);
+    }
+
+    pub fn queue_find_backspace(&mut self) {
+        if let Some(query) = self.queue_find.as_mut() {
+            query.pop();
+        }
+        self.select_queue_match(true, false);
+    }
+
+    pub fn next_queue_match(&mut self) {
+        self.select_queue_match(true, true);
+    }
+
+    pub fn prev_queue_match(&mut self) {
+        self.select_queue_match(false, true);
+    }
+
+    // Move the selection to the nearest match in either direction, wrapping
+    // around the ends of the queue
+    fn select_queue_match(&mut self, forward: bool, skip_current: bool) {
+        let matches = self.queue_matches();
+        if matches.is_empty() {
+            return;
+        }
+
+        let current = self.queue_selected();
+        let found = if forward {
+            matches
+                .iter()
+                .find(|&&i| i > current || (i == current && !skip_current))
+                .unwrap_or(&matches[0])
+        } else {
+            matches
+                .iter()
+                .rev()
+                .find(|&&i| i < current)
+                .unwrap_or(&matches[matches.len() - 1])
+        };
+
+        self.queue_select = *found;
+    }
+
+    pub fn end_queue_find(&mut self) {
+        self.queue_find = None;
+    }
+
+    pub fn toggle_help(&mut self) {
+        self.show_help = !self.show_help;
+        self.help_offset = 0;
+    }
+
+    pub fn toggle_lyrics(&mut self) {
+        self.show_lyrics = !self.show_lyrics;
+        self.lyrics_offset = 0;
+    }
+
+    // Read the lyrics of the playing track when it has changed since they
+    // were last read
+    pub fn update_lyrics(&mut self) {
+        let path = &self.now_playing.file_path;
+        if let Some((lyrics_path, _)) = &self.lyrics {
+            if lyrics_path == path {
+                return;
+            }
+        }
+
+        self.lyrics = Some((path.clone(), lyrics::load(&self.now_playing)));
+        self.lyrics_offset = 0;
+    }
+
+    pub fn current_lyrics(&self) -> Option<&Lyrics> {
+        self.lyrics.as_ref().and_then(|(_, lyrics)| lyrics.as_ref())
+    }
+
+    pub fn scroll_lyrics(&mut self, delta: i32) {
+        let lines = self.current_lyrics().map_or(0, |l| l.lines().len());
+        let offset = (i32::from(self.lyrics_offset) + delta).max(0) as usize;
+        self.lyrics_offset = offset.min(lines.saturating_sub(1)) as u16;
+    }
+
+    // Scroll the help overlay, which is at most `lines` long
+    pub fn scroll_help(&mut self, delta: i32, lines: usize) {
+        let offset = (i32::from(self.help_offset) + delta).max(0) as usize;
+        self.help_offset = offset.min(lines.saturating_sub(1)) as u16;
+    }
+
+    // Open the prompt for jumping through the active library column by
+    // typing the start of a name
+    pub fn start_jump(&mut self) {
+        self.jump_input = Some(String::new());
+        self.jump_typed_at = Instant::now();
+    }
+
+    // The prompt closes by itself once typing stops for a moment
+    pub fn jump_active(&self) -> bool {
+        self.tabs.index == 1
+            && self.jump_input.is_some()
+            && self.jump_typed_at.elapsed() < JUMP_TIMEOUT
+    }
+
+    pub fn jump_type(&mut self, c: char) {
+        if let Some(input) = self.jump_input.as_mut() {
+            input.push(c);
+            self.lib_cols.jump_to_prefix(input);
+        }
+        self.jump_typed_at = Instant::now();
+    }
+
+    pub fn jump_backspace(&mut self) {
+        if let Some(input) = self.jump_input.as_mut() {
+            input.pop();
+            if !input.is_empty() {
+                self.lib_cols.jump_to_prefix(input);
+            }
+        }
+        self.jump_typed_at = Instant::now();
+    }
+
+    pub fn end_jump(&mut self) {
+        self.jump_input = None;
+    }
+
+    pub fn page_up(&mut self) {
+        let n = self.page_size;
+        match self.tabs.index {
+            0 => self.queue_select = self.queue_selected().saturating_sub(n),
+            1 => self.lib_cols.on_page_up(n),
+            2 => self.search_select = self.search_select.saturating_sub(n),
+            _ => {}
+        }
+    }
+
+    pub fn page_down(&mut self) {
+        let n = self.page_size;
+        match self.tabs.index {
+            0 => {
+                self.queue_select =
+                    (self.queue_selected() + n).min(self.queue.tracks.len().saturating_sub(1))
+            }
+            1 => self.lib_cols.on_page_down(n),
+            2 => {
+                self.search_select =
+                    (self.search_select + n).min(self.search_results.len().saturating_sub(1))
+            }
+            _ => {}
+        }
+    }
+
+    pub fn on_up_search(&mut self) {
+        self.search_select = step_previous(
+            self.search_select,
+            self.search_results.len(),
+            self.config.wrap,
+        );
+    }
+
+    pub fn on_down_search(&mut self) {
+        self.search_select = step_next(
+            self.search_select,
+            self.search_results.len(),
+            self.config.wrap,
+        );
+    }
+
+    // Star or unstar the selected track in the library or favorites tab.
+    // The library's own copy is changed so that it is saved on exit
+    pub fn toggle_favorite(&mut self) {
+        let file_path = if self.tabs.index == 1 && self.lib_cols.current_active == 2 {
+            match self.lib_cols.tracks.selected_item() {
+                Some(t) => t.file_path.clone(),
+                None => return,
+            }
+        } else if self.tabs.index == 3 && !self.favorites.items.is_empty() {
+            self.favorites.items[self.favorites.selected]
+                .file_path
+                .clone()
+        } else {
+            return;
+        };
+
+        if let Some(track) = self.lib_cols.find_track_mut(&file_path) {
+            track.toggle_favorite();
+            self.lib_cols.refresh();
+            self.reload_favorites();
+        }
+    }
+
+    // Give the selected track in the library, search results, favorites or
+    // played tab more or fewer stars. As with favorites, the library's own
+    // copy is changed, and the copies elsewhere follow it
+    pub fn rate_selected(&mut self, change: i8) {
+        let file_path = match self.selected_track() {
+            Some(t) => t.file_path.clone(),
+            None => return,
+        };
+
+        let rating = match self.lib_cols.find_track_mut(&file_path) {
+            Some(track) => {
+                track.rate(change);
+                track.rating
+            }
+            None => return,
+        };
+        self.lib_cols.refresh();
+        self.reload_favorites();
+        self.reload_played();
+
+        let copies = self
+            .queue
+            .tracks
+            .iter_mut()
+            .chain(self.history.iter_mut())
+            .chain(iter::once(&mut self.now_playing));
+        for track in copies.filter(|t| t.file_path == file_path) {
+            track.rating = rating;
+        }
+        for result in self.search_results.iter_mut() {
+            if let Media::Track(track) = result {
+                if track.file_path == file_path {
+                    track.rating = rating;
+                }
+            }
+        }
+
+        let message = match rating {
+            0 => "Rating cleared".to_string(),
+            n => format!("Rated {} of {}", n, MAX_RATING),
+        };
+        self.set_status(message, STATUS_TIMEOUT);
+    }
+
+    fn selected_track(&self) -> Option<&Track> {
+        match self.tabs.index {
+            1 if self.lib_cols.current_active == 2 => self
+                .lib_cols
+                .tracks
+                .items
+                .get(self.lib_cols.tracks.selected),
+            2 => match self.search_results.get(self.search_select) {
+                Some(Media::Track(t)) => Some(t),
+                _ => None,
+            },
+            3 => self.favorites.items.get(self.favorites.selected),
+            6 => self.played.items.get(self.played.selected),
+            _ => None,
+        }
+    }
+
+    // Start the sleep timer, or push it further out if it is already running
+    pub fn extend_sleep_timer(&mut self, minutes: u64) {
+        let now = Instant::now();
+        let start = match self.sleep_deadline {
+            Some(deadline) if deadline > now => deadline,
+            _ => now,
+        };
+
+        self.sleep_deadline = Some(start + Duration::from_secs(minutes * 60));
+    }
+
+    pub fn cancel_sleep_timer(&mut self) {
+        self.sleep_deadline = None;
+    }
+
+    pub fn sleep_remaining(&self) -> Option<Duration> {
+        self.sleep_deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    pub fn sleep_expired(&self) -> bool {
+        match self.sleep_deadline {
+            Some(deadline) => Instant::now() >= deadline,
+            None => false,
+        }
+    }
+
+    // Called every frame. When the current track is allowed to finish, the
+    // stop happens instead at the next empty sink notification
+    pub fn check_sleep_timer(&mut self) {
+        if self.sleep_expired() && !self.config.sleep_after_track {
+            self.cancel_sleep_timer();
+            self.stop();
+        }
+    }
+
+    fn reload_favorites(&mut self) {
+        let selected = self.favorites.selected;
+        self.favorites = ListState::new(&favorite_tracks(&self.lib_cols.artists.items));
+
+        // Stay in place unless the last entry was just removed
+        if selected < self.favorites.items.len() {
+            self.favorites.selected = selected;
+        } else if !self.favorites.items.is_empty() {
+            self.favorites.selected = self.favorites.items.len() - 1;
+        }
+    }
+
+    // Switch audio output over to the selected device
+    fn select_device(&mut self) {
+        if self.devices.items.is_empty() {
+            return;
+        }
+
+        let name = self.devices.items[self.devices.selected].clone();
+        if self.channels.dtx.send(name.clone()).is_err() {
+            warn!("The audio thread did not get the output device switch");
+            self.lose_audio();
+        }
+        self.active_device = name;
+    }
+
+    pub fn on_up_devices(&mut self) {
+        if !self.devices.items.is_empty() {
+            self.devices.select_previous(self.config.wrap);
+        }
+    }
+
+    pub fn on_down_devices(&mut self) {
+        if !self.devices.items.is_empty() {
+            self.devices.select_next(self.config.wrap);
+        }
+    }
+
+    pub fn on_up_favorites(&mut self) {
+        if !self.favorites.items.is_empty() {
+            self.favorites.select_previous(self.config.wrap);
+        }
+    }
+
+    pub fn on_down_favorites(&mut self) {
+        if !self.favorites.items.is_empty() {
+            self.favorites.select_next(self.config.wrap);
+        }
+    }
+
+    pub fn on_up_recent(&mut self) {
+        if !self.recent.items.is_empty() {
+            self.recent.select_previous(self.config.wrap);
+        }
+    }
+
+    pub fn on_down_recent(&mut self) {
+        if !self.recent.items.is_empty() {
+            self.recent.select_next(self.config.wrap);
+        }
+    }
+
+    pub fn on_up_played(&mut self) {
+        if !self.played.items.is_empty() {
+            self.played.select_previous(self.config.wrap);
+        }
+    }
+
+    pub fn on_down_played(&mut self) {
+        if !self.played.items.is_empty() {
+            self.played.select_next(self.config.wrap);
+        }
+    }
+
+    // Move the selection of whichever list the current tab shows
+    pub fn on_up(&mut self) {
+        match self.tabs.index {
+            0 => self.on_up_queue(),
+            1 => self.lib_cols.on_up(),
+            2 if !self.search_results.is_empty() => self.on_up_search(),
+            3 => self.on_up_favorites(),
+            4 => self.on_up_devices(),
+            5 => self.on_up_recent(),
+            6 => self.on_up_played(),
+            _ => {}
+        }
+    }
+
+    pub fn on_down(&mut self) {
+        match self.tabs.index {
+            0 => self.on_down_queue(),
+            1 => self.lib_cols.on_down(),
+            2 if !self.search_results.is_empty() => self.on_down_search(),
+            3 => self.on_down_favorites(),
+            4 => self.on_down_devices(),
+            5 => self.on_down_recent(),
+            6 => self.on_down_played(),
+            _ => {}
+        }
+    }
+}
+
+// Every album in the library, most recently added first
+fn recent_albums(artists: &[Artist]) -> Vec<Album> {
+    let mut albums: Vec<Album> = library::albums(artists).cloned().collect();
+    albums.sort_by_key(|al| Reverse(al.date_added));
+
+    albums
+}
+
+// Every starred track in the library, in library order
+fn favorite_tracks(artists: &[Artist]) -> Vec<Track> {
+    library::tracks(artists)
+        .filter(|t| t.favorite)
+        .cloned()
+        .collect()
+}
+
+// Every track with a play count, highest first and by title among equals
+fn most_played_tracks(artists: &[Artist]) -> Vec<Track> {
+    let mut tracks: Vec<Track> = library::tracks(artists)
+        .filter(|t| t.play_count > 0)
+        .cloned()
+        .collect();
+    tracks.sort_by(|a, b| {
+        b.play_count
+            .cmp(&a.play_count)
+            .then_with(|| a.title.cmp(&b.title))
+    });
+
+    tracks
+}
+
+// "1 track" or "12 tracks"
+fn track_count(n: usize) -> String {
+    if n == 1 {
+        "1 track".to_string()
+    } else {
+        format!("{} tracks", n)
+    }
+}
+
+// "center", or how far the balance is to one side, e.g. "30% left"
+pub fn balance_label(balance: f32) -> String {
+    let percent = (balance.abs() * 100.0).round();
+    if balance < 0.0 {
+        format!("{}% left", percent)
+    } else if balance > 0.0 {
+        format!("{}% right", percent)
+    } else {
+        "center".to_string()
+    }
+}
+
+// The index before this one in a list of len items, going round to the
+// last from the first when wrapping and staying on the first otherwise
+fn step_previous(index: usize, len: usize, wrap: bool) -> usize {
+    if index > 0 {
+        index - 1
+    } else if wrap {
+        len.saturating_sub(1)
+    } else {
+        0
+    }
+}
+
+fn step_next(index: usize, len: usize, wrap: bool) -> usize {
+    if index + 1 < len {
+        index + 1
+    } else if wrap {
+        0
+    } else {
+        len.saturating_sub(1)
+    }
+}
+
+fn toggle_mark(marked: &mut HashSet<usize>, index: usize) {
+    if !marked.remove(&index) {
+        marked.insert(index);
+    }
+}
+
+// Every track of a search result, in album order
+fn media_tracks(media: &Media) -> Vec<Track> {
+    match media {
+        Media::Artist(a) => a
+            .albums
+            .iter()
+            .flat_map(|al| al.tracks.iter().cloned())
+            .collect(),
+        Media::Album(al) => al.tracks.clone(),
+        Media::Track(t) => vec![t.clone()],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rodio::buffer::SamplesBuffer;
+
+    #[test]
+    fn new_sink_keeps_volume_speed_and_pause() {
+        let (channels, _ui) = audio_channels();
+        let mut audio = Audio::open(None, channels, ReplayGainMode::Off, Duration::from_secs(0));
+        audio.set_volume(0.4);
+        audio.set_speed(1.5);
+        audio.pause_play();
+
+        audio.move_to(None);
+
+        assert_eq!(audio.sink.volume(), 0.4);
+        assert!(!audio.envelope.audible());
+        let source = SamplesBuffer::new(1, 8000, vec![0.0f32; 8000]);
+        let source = audio.prepare(source, &Track::dummy(), false);
+        assert_eq!(source.sample_rate(), 12000);
+
+        audio.pause_play();
+        audio.move_to(None);
+        assert!(audio.envelope.audible());
+    }
+
+    #[test]
+    fn broken_tracks_in_play_all_are_reported() {
+        let dir = std::env::temp_dir().join(format!("sonik-audio-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let good = dir.join("good.wav");
+        std::fs::write(&good, crate::storage::record::tests::silent_wav()).unwrap();
+        let track = |path: &Path| {
+            let mut track = Track::dummy();
+            track.file_path = path.to_string_lossy().to_string();
+            track
+        };
+        let broken = track(&dir.join("missing.wav"));
+
+        let (channels, ui) = audio_channels();
+        let mut audio = Audio::open(None, channels, ReplayGainMode::Off, Duration::from_secs(0));
+        let tracks = vec![track(&good), broken.clone(), track(&good)];
+        assert!(audio.play_all(tracks, Duration::from_secs(0)).is_ok());
+
+        assert_eq!(audio.appended.len(), 2);
+        assert_eq!(ui.erx.try_recv(), Ok(broken.file_path));
+        assert!(ui.erx.try_recv().is_err());
+
+        std::fs::remove_dir_all(&dir).ok();
     }
 }
@@ -0,0 +1,89 @@
+// Sends a command to sonik running with --daemon and prints the answer.
+//
+//     sonikctl play [PATH] | pause | next | enqueue PATH | status | quit
+//
+// The socket is looked for in the default data folder, ~/.local/share/sonik
+// on Linux or ~/.sonik for older setups, or at SONIK_SOCKET if set
+
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::process;
+
+use serde_json::{json, Value};
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let request = match parse(&args) {
+        Some(r) => r,
+        None => {
+            eprintln!("usage: sonikctl play [PATH] | pause | next | enqueue PATH | status | quit");
+            process::exit(2);
+        }
+    };
+
+    match send(&request) {
+        Ok(response) => {
+            println!("{}", response);
+            if response["ok"] != json!(true) {
+                process::exit(1);
+            }
+        }
+        Err(e) => {
+            eprintln!("Could not reach sonik: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+fn parse(args: &[String]) -> Option<Value> {
+    let command = args.first()?.as_str();
+    let path = args.get(1).map(|p| absolute(p));
+
+    match (command, path) {
+        ("play", Some(path)) => Some(json!({ "command": "play", "path": path })),
+        ("enqueue", Some(path)) => Some(json!({ "command": "enqueue", "path": path })),
+        ("play", None) | ("pause", None) | ("next", None) | ("status", None) | ("quit", None) => {
+            Some(json!({ "command": command }))
+        }
+        _ => None,
+    }
+}
+
+// The daemon runs somewhere else, so relative paths are made absolute here
+fn absolute(path: &str) -> String {
+    fs::canonicalize(path)
+        .unwrap_or_else(|_| PathBuf::from(path))
+        .to_string_lossy()
+        .to_string()
+}
+
+fn socket_path() -> PathBuf {
+    match env::var_os("SONIK_SOCKET") {
+        Some(path) => PathBuf::from(path),
+        None => {
+            // The data folder as sonik works it out when the config
+            // doesn't move it
+            let legacy = dirs::home_dir().unwrap_or_default().join(".sonik");
+            let folder = if legacy.is_dir() {
+                legacy
+            } else {
+                dirs::data_dir().map_or(legacy, |d| d.join("sonik"))
+            };
+            folder.join("sonik.sock")
+        }
+    }
+}
+
+fn send(request: &Value) -> std::io::Result<Value> {
+    let mut stream = UnixStream::connect(socket_path())?;
+    writeln!(stream, "{}", request)?;
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+
+    Ok(serde_json::from_str(&line).unwrap_or(Value::Null))
+}
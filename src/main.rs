@@ -3,24 +3,41 @@ pub mod storage;
 pub mod ui;
 mod util;
 
-use std::io;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
 use std::path::Path;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
 
 use clap::{App, Arg};
-use crossbeam_channel as channel;
+use crossbeam_channel::{self as channel, RecvTimeoutError, SendTimeoutError};
+use log::{info, warn};
+use simplelog::{LevelFilter, WriteLogger};
 use termion::event::Key;
+use termion::input::MouseTerminal;
 use termion::raw::IntoRawMode;
 use tui::backend::TermionBackend;
 use tui::layout::{Constraint, Direction, Layout};
-use tui::style::{Color, Style};
+use tui::style::Style;
 use tui::widgets::{Block, Widget};
 use tui::Terminal;
 
-use crate::application::config::Config;
-use crate::application::state::{Audio, UI};
+use crate::application::config::{Config, RepeatMode};
+use crate::application::daemon;
+use crate::application::keybindings::{Action, Keybindings};
+use crate::application::session::Session;
+use crate::application::state::{
+    audio_channels, Audio, PlaybackState, SEEK_STEP, STATUS_TIMEOUT, UI,
+};
 use crate::storage::database::*;
+use crate::storage::formats::check_decoders;
+use crate::storage::library::Library;
+use crate::storage::playlist::{load_playlist, open_paths};
+use crate::storage::record::Stats;
+use crate::ui::theme::Theme;
 use crate::util::event::{Event, Events};
 
 fn main() -> Result<(), failure::Error> {
@@ -36,67 +53,281 @@ fn main() -> Result<(), failure::Error> {
                 .help("Create the database using files from this location")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("playlist")
+                .short("l")
+                .long("playlist")
+                .value_name("FILE")
+                .help("Queue up the tracks of an m3u/m3u8 playlist")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("rebuild")
                 .short("r")
                 .long("rebuild")
                 .help("Rebuild the library using location from config file"),
         )
+        .arg(
+            Arg::with_name("enqueue")
+                .short("e")
+                .long("enqueue")
+                .help("Add the files given to the queue instead of playing them now"),
+        )
+        .arg(
+            Arg::with_name("files")
+                .value_name("FILE")
+                .help("Music files or folders to play")
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name("daemon")
+                .long("daemon")
+                .help("Run without the interface, taking commands from sonikctl"),
+        )
+        .arg(
+            Arg::with_name("remap")
+                .long("remap")
+                .value_names(&["OLD", "NEW"])
+                .help("Point tracks under OLD at the same files under NEW, after moving them, and exit")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("export_json")
+                .long("export-json")
+                .value_name("FILE")
+                .help("Write the whole library to a JSON file and exit")
+                .takes_value(true),
+        )
         .get_matches();
 
     println!("Loading configuration...");
 
-    let config = match matches.value_of("music_location") {
-        Some(ml) => Config::new(ml).expect("Could not get or create configuration"),
-        _ => Config::get_config().expect("Could not get or create configuration"),
+    // What's wrong with the config has already been printed by now
+    let loaded = match matches.value_of("music_location") {
+        Some(ml) => Config::new(ml).map(|config| (config, Vec::new())),
+        _ => Config::load_or_default(),
+    };
+    let config = match loaded {
+        Ok((config, warnings)) => {
+            for warning in warnings {
+                println!("Warning: {}", warning);
+            }
+            config
+        }
+        Err(()) => process::exit(1),
     };
 
-    println!("Loading database...");
+    start_logging(&config);
 
-    let (artists, stats) = if !Path::new(&config.database_path).exists() {
-        create_and_load_database(&config).expect("Could not create database")
-    } else if matches.is_present("rebuild") {
-        rebuild_database(&config).expect("Could not rebuild database")
+    for extension in check_decoders() {
+        println!(
+            "Warning: no decoder for .{} in this build, those files won't play",
+            extension
+        );
+    }
+
+    if let Some(roots) = matches.values_of("remap") {
+        let roots: Vec<&Path> = roots.map(Path::new).collect();
+        remap(&config, roots[0], roots[1]);
+        return Ok(());
+    }
+
+    // A scan can take minutes on a big library, so unless only exporting or
+    // running headless it is shown going in the interface. The scan hands
+    // over the key events and search index it started
+    let rebuild = matches.is_present("rebuild");
+    let mut scan_events = None;
+    let mut scan_engines = None;
+    let (mut library, mut stats, pruned) = if Library::needs_scan(&config, rebuild)
+        && !matches.is_present("daemon")
+        && !matches.is_present("export_json")
+    {
+        let events = Events::new();
+        match scan_with_progress(&config, rebuild, &events)? {
+            Some((library, stats, pruned, engines)) => {
+                scan_events = Some(events);
+                scan_engines = Some(engines);
+                (library, stats, pruned)
+            }
+            // A scan cancelled with Esc falls back on the library saved
+            // before it, or quits if there is none that can be read
+            None if database_exists(&config) && !database_outdated(&config) => {
+                scan_events = Some(events);
+                println!("Scan cancelled, loading the library from before...");
+                Library::load_saved(&config).expect("Could not load database")
+            }
+            None => return Ok(()),
+        }
     } else {
-        load_database(&config).expect("Could not load database")
+        println!("Loading database...");
+
+        // Anything deleted from disk since the last scan is dropped
+        Library::load(&config, rebuild).expect("Could not load database")
+    };
+    if pruned > 0 {
+        println!("Removed {} missing track(s) from the database", pruned);
+        info!("Removed {} missing track(s) from the database", pruned);
+    }
+
+    if let Some(path) = matches.value_of("export_json") {
+        library
+            .export_json(Path::new(path))
+            .expect("Could not export library");
+        println!("Exported {} tracks to {}", library.track_count(), path);
+        return Ok(());
+    }
+
+    // Nothing would play from a library left pointing where the music
+    // folder used to be
+    if !rebuild
+        && library.looks_moved(&config)
+        && (matches.is_present("daemon") || !recover_moved(&config, &mut library, &mut stats))
+    {
+        println!(
+            "The library wasn't changed. Run sonik with --remap OLD NEW to point it at \
+             where the music is now, or --rebuild to scan the music folder again"
+        );
+        process::exit(1);
+    }
+
+    let playlist = match matches.value_of("playlist") {
+        Some(pl) => {
+            load_playlist(Path::new(pl), &library.artists).expect("Could not read playlist")
+        }
+        _ => Vec::new(),
     };
 
-    let engine_group =
-        create_fuzzy_searcher(&artists).expect("Could not create artist fuzzy search");
+    // Files and folders given on the command line, e.g. by a file manager
+    let files = match matches.values_of("files") {
+        Some(paths) => {
+            let paths: Vec<&Path> = paths.map(Path::new).collect();
+            open_paths(&paths, &library, &config.artist_separators)
+        }
+        None => Vec::new(),
+    };
+
+    if matches.is_present("daemon") {
+        println!(
+            "Listening on {}",
+            daemon::socket_path(&config).to_string_lossy()
+        );
+        let tracks = playlist.into_iter().chain(files).collect();
+        daemon::run(&config, library, tracks).expect("Could not run headless");
+        return Ok(());
+    }
+
+    // Report problems with the keybindings before the interface takes over
+    let keybindings = Keybindings::new(&config.keybindings);
+
+    // Bindings don't change while running, so the help overlay is put
+    // together once. Each heading takes a line and a blank one after it
+    let help = keybindings.help();
+    let help_lines = help.iter().map(|(_, lines)| lines.len() + 2).sum();
+
+    let engine_group = match scan_engines {
+        Some(engines) => engines,
+        None => load_fuzzy_searcher(&config, &library.artists)
+            .expect("Could not create artist fuzzy search"),
+    };
 
     // Create the sink for the audio output device
     let device = rodio::default_output_device().expect("No audio output device found");
 
-    // Create the notification channel for empty
-    // audio sink, pause/play events, and the track transfer channel
-    let (btx, brx) = channel::bounded(0);
-    let (ptx, prx) = channel::bounded(0);
-    let (ttx, trx) = channel::bounded(0);
+    // The channels the audio thread and the interface talk over
+    let (audio_channels, ui_channels) = audio_channels();
 
     // Keypress event handler, spins a thread
-    let ui_events = Events::new();
+    let ui_events = scan_events.unwrap_or_else(Events::new);
 
     // Create structs to be managed on different threads
-    let mut ui = UI::new(&artists, brx, ttx, ptx, engine_group, stats);
-    let mut audio = Audio::new(device, trx, btx, prx);
+    let mut ui = UI::new(
+        &library.artists,
+        ui_channels,
+        engine_group,
+        stats,
+        config.clone(),
+    );
+    let mut audio = Audio::new(
+        device,
+        audio_channels,
+        config.replaygain,
+        Duration::from_millis(config.fade_ms),
+    );
+    audio.set_volume(config.default_volume as f32 / 100.0);
+    audio.set_looping(config.default_repeat == RepeatMode::One);
+
+    // The equalizer as it was last left, or as the config has it, and the
+    // balance as it was last left
+    let session = Session::load(&config);
+    if let Some(gains) = session.equalizer {
+        ui.equalizer = gains;
+    }
+    audio.set_equalizer(ui.equalizer);
+    ui.balance = session.balance.clamp(-1.0, 1.0);
+    audio.set_balance(ui.balance);
+
+    // Playback of the playlist starts once the audio thread reports an empty sink
+    for track in playlist {
+        ui.queue.add(track);
+    }
 
     // All audio-related bits are sent to their own thread
     thread::spawn(move || {
         loop {
-            // Alert the UI thread that there is no song playing
-            if audio.sink.empty() {
-                if let Ok(()) = audio.btx.send_timeout(true, Duration::from_millis(250)) {}
-            } else if let Ok(()) = audio.btx.send_timeout(false, Duration::from_millis(250)) {
+            if let Ok(looping) = audio.channels.looprx.try_recv() {
+                audio.set_looping(looping)
+            }
+
+            if let Ok(speed) = audio.channels.srx.try_recv() {
+                audio.set_speed(speed)
+            }
+
+            if let Ok(gains) = audio.channels.eqrx.try_recv() {
+                audio.set_equalizer(gains)
+            }
+
+            if let Ok(pan) = audio.channels.balrx.try_recv() {
+                audio.set_balance(pan)
+            }
+
+            audio.check_loop();
+
+            // Keep the UI thread up to date on the playback state, which
+            // moves on to the queue when there is no song playing. Once the
+            // UI thread is gone there is nothing left to play for
+            let state = audio.state();
+            let sent = audio
+                .channels
+                .btx
+                .send_timeout(state, Duration::from_millis(250));
+            if let Err(SendTimeoutError::Disconnected(_)) = sent {
+                warn!("The interface stopped listening, stopping playback");
+                break;
             }
 
             // If the UI thread semds a track from the queue,
             // receive it and send it to the sink
-            if let Ok(track) = audio.trx.try_recv() {
-                audio.play(track)
+            if let Ok((tracks, position)) = audio.channels.trx.try_recv() {
+                if let Some(track) = tracks.first().cloned() {
+                    if audio.play_all(tracks, position).is_err() {
+                        audio.report_unreadable(&track);
+                    }
+                }
             }
 
+            // Queue up the following track behind the current one
+            if let Ok(track) = audio.channels.lrx.try_recv() {
+                audio.append(track)
+            }
+
+            if let Ok(name) = audio.channels.drx.try_recv() {
+                audio.set_device(&name)
+            }
+
+            audio.check_advance();
+
             // Listen for a play/pause event
-            match audio.prx.try_recv() {
+            match audio.channels.prx.try_recv() {
                 Ok(true) => audio.pause_play(),
                 Ok(false) => audio.stop(),
                 _ => {}
@@ -104,153 +335,587 @@ fn main() -> Result<(), failure::Error> {
         }
     });
 
+    // Reopen on the tab and library selection the last session ended on
+    if session.tab < ui.tabs.titles.len() {
+        ui.tabs.index = session.tab;
+    }
+    if pruned > 0 {
+        let message = format!("Removed {} missing track(s) from the library", pruned);
+        ui.set_status(message, STATUS_TIMEOUT);
+    }
+
+    ui.lib_cols.restore(
+        session.library_column,
+        session.artist,
+        session.album,
+        session.track,
+    );
+    ui.restore_history(&session.history);
+
+    if config.resume_on_start {
+        ui.resume(
+            &session.now_playing,
+            Duration::from_millis(session.position),
+        );
+    }
+
+    // Files from the command line take over from a resumed track unless
+    // they are only meant to be queued up
+    if matches.is_present("enqueue") {
+        for track in files {
+            ui.queue.add(track);
+        }
+    } else if !files.is_empty() {
+        ui.play_tracks(files);
+    }
+
     let stdout = io::stdout().into_raw_mode()?;
+    let stdout: Box<dyn Write> = if config.mouse {
+        Box::new(MouseTerminal::from(stdout))
+    } else {
+        Box::new(stdout)
+    };
     let backend = TermionBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
     terminal.hide_cursor()?;
     terminal.clear()?;
 
     loop {
+        // Lists take up most of the 91% middle section, less their borders
+        let size = terminal.size()?;
+        ui.page_size = (size.height as usize * 91 / 100).saturating_sub(2).max(1);
+
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(
+                [
+                    Constraint::Percentage(5),
+                    Constraint::Percentage(91),
+                    Constraint::Percentage(4),
+                ]
+                .as_ref(),
+            )
+            .split(size);
+
+        // The cover is only shown alongside the queue, and not under help
+        let cover_area = if ui.tabs.index == 0 && !ui.show_help && !ui.show_lyrics {
+            Some(ui::screens::cover_pane(chunks[1]))
+        } else {
+            None
+        };
+        if ui.update_cover(cover_area) {
+            terminal.clear()?;
+        }
+        if ui.show_lyrics {
+            ui.update_lyrics();
+        }
+
         terminal.draw(|mut f| {
             let size = f.size();
-            let chunks = Layout::default()
-                .direction(Direction::Vertical)
-                .constraints(
-                    [
-                        Constraint::Percentage(5),
-                        Constraint::Percentage(91),
-                        Constraint::Percentage(4),
-                    ]
-                    .as_ref(),
-                )
-                .split(f.size());
             Block::default()
-                .style(Style::default().bg(Color::Black))
+                .style(Style::default().bg(ui.theme.background))
                 .render(&mut f, size);
             ui::screens::draw_top_bar(&mut f, &ui, chunks[0]);
             match ui.tabs.index {
                 0 => ui::screens::draw_queue(&mut f, &ui, chunks[1]),
                 1 => ui::screens::draw_library(&mut f, &ui, chunks[1]),
                 2 => ui::screens::draw_search(&mut f, &ui, chunks[1]),
+                3 => ui::screens::draw_favorites(&mut f, &ui, chunks[1]),
+                4 => ui::screens::draw_settings(&mut f, &ui, chunks[1]),
+                5 => ui::screens::draw_recent(&mut f, &ui, chunks[1]),
+                6 => ui::screens::draw_played(&mut f, &ui, chunks[1]),
                 _ => {}
             }
             ui::screens::draw_stats(&mut f, &ui, chunks[2]);
+            if ui.show_help {
+                ui::screens::draw_help(&mut f, &ui, &help, chunks[1]);
+            } else if ui.show_lyrics {
+                ui::screens::draw_lyrics(&mut f, &ui, chunks[1]);
+            }
         })?;
 
-        // Capture keypresses
-        if let Event::Input(input) = ui_events.next()? {
+        if let Some(cover) = ui.cover.as_mut() {
+            cover.emit(terminal.backend_mut())?;
+        }
+
+        // Capture keypresses and clicks
+        let event = ui_events.next()?;
+        // Start over on the next frame, which works the layout and page
+        // size out again for the new size
+        if let Event::Resize = event {
+            terminal.clear()?;
+        }
+        if let Event::Mouse(mouse) = event {
+            ui::mouse::handle(&mut ui, mouse, &chunks);
+        }
+        if let Event::Input(input) = event {
+            let action = keybindings.action(input);
             match input {
-                Key::Char('p') => {
-                    if ui.tabs.index == 2 {
-                        ui.search_input.push('p');
-                    } else {
-                        ui.pause_play();
-                    }
+                // The help overlay takes every key until it is closed
+                _ if ui.show_help => match action {
+                    Some(Action::Help) | Some(Action::Quit) => ui.toggle_help(),
+                    Some(Action::Up) => ui.scroll_help(-1, help_lines),
+                    Some(Action::Down) => ui.scroll_help(1, help_lines),
+                    Some(Action::PageUp) => ui.scroll_help(-(ui.page_size as i32), help_lines),
+                    Some(Action::PageDown) => ui.scroll_help(ui.page_size as i32, help_lines),
+                    _ if input == Key::Esc => ui.toggle_help(),
+                    _ => {}
+                },
+                // As does the lyrics overlay, apart from play/pause
+                _ if ui.show_lyrics => match action {
+                    Some(Action::Lyrics) | Some(Action::Quit) => ui.toggle_lyrics(),
+                    Some(Action::PlayPause) => ui.pause_play(),
+                    Some(Action::Up) => ui.scroll_lyrics(-1),
+                    Some(Action::Down) => ui.scroll_lyrics(1),
+                    Some(Action::PageUp) => ui.scroll_lyrics(-(ui.page_size as i32)),
+                    Some(Action::PageDown) => ui.scroll_lyrics(ui.page_size as i32),
+                    _ if input == Key::Esc => ui.toggle_lyrics(),
+                    _ => {}
+                },
+                // Answer to "Clear queue? y/N", where anything but y is a no
+                Key::Char('y') if ui.clear_armed => ui.confirm_clear_queue(),
+                _ if ui.clear_armed => ui.cancel_clear_queue(),
+                // Naming a bookmark takes all typing until Enter or Esc
+                Key::Char('\n') if ui.bookmark_active() => ui.confirm_bookmark(),
+                Key::Esc if ui.bookmark_active() => ui.cancel_bookmark(),
+                Key::Backspace if ui.bookmark_active() => ui.bookmark_backspace(),
+                Key::Char(c) if ui.bookmark_active() => ui.bookmark_type(c),
+                _ if ui.bookmark_active() => {}
+                // So does typing the folder to open
+                Key::Char('\n') if ui.folder_active() => ui.confirm_open_folder(),
+                Key::Esc if ui.folder_active() => ui.cancel_open_folder(),
+                Key::Backspace if ui.folder_active() => ui.folder_backspace(),
+                Key::Char(c) if ui.folder_active() => ui.folder_type(c),
+                _ if ui.folder_active() => {}
+                // While the library jump prompt is open it gets all typing
+                Key::Esc | Key::Char('\n') if ui.jump_active() => ui.end_jump(),
+                Key::Backspace if ui.jump_active() => ui.jump_backspace(),
+                Key::Char(c) if ui.jump_active() => ui.jump_type(c),
+                // Likewise the queue's find prompt, where the arrows go
+                // through the matches
+                Key::Esc | Key::Char('\n') if ui.queue_find_active() => ui.end_queue_find(),
+                Key::Backspace if ui.queue_find_active() => ui.queue_find_backspace(),
+                Key::Down if ui.queue_find_active() => ui.next_queue_match(),
+                Key::Up if ui.queue_find_active() => ui.prev_queue_match(),
+                Key::Char(c) if ui.queue_find_active() => ui.queue_find_type(c),
+                // Esc empties the search tab, and quits once it is empty
+                Key::Esc
+                    if ui.tabs.index == 2
+                        && !(ui.search_input.is_empty() && ui.search_results.is_empty()) =>
+                {
+                    ui.clear_search()
                 }
-                Key::Esc => {
-                    // Clear buffer so command line prompt is shown correctly
-                    terminal.clear()?;
-                    break;
+                // In the search tab most keys type into the search box
+                Key::Char(c) if ui.tabs.index == 2 && !search_command(action, &ui) => {
+                    ui.search_input.push(c)
                 }
-                Key::Char('s') => {
-                    if ui.tabs.index == 2 {
-                        ui.search_input.push('s');
-                    } else {
-                        // Shuffle queue in place
-                        ui.queue.shuffle();
-                    }
+                Key::Backspace if ui.tabs.index == 2 => {
+                    ui.search_input.pop();
                 }
-                Key::Char('r') => {
-                    if ui.tabs.index == 2 {
-                        ui.search_input.push('r');
-                    } else {
-                        // Turn on repeat
+                _ => match action {
+                    Some(Action::Quit) => {
+                        // Clear buffer so command line prompt is shown correctly
+                        terminal.clear()?;
+                        break;
                     }
-                }
-                Key::Char('>') => {
+                    Some(Action::PlayPause) => ui.pause_play(),
+                    Some(Action::Play) => ui.on_enter(),
+                    // Add track to queue
+                    Some(Action::Enqueue) => ui.add_to_queue(),
+                    Some(Action::EnqueueShuffled) => ui.add_to_queue_shuffled(),
+                    // Add track to front of queue
+                    Some(Action::PlayNext) => ui.add_to_front(),
+                    // Mark tracks to add to the queue together
+                    Some(Action::Mark) => ui.toggle_mark(),
                     // Skip to next song
-                    ui.play_from_queue();
-                }
-                Key::Char(' ') => {
-                    if ui.tabs.index == 2 {
-                        if !ui.search_results.is_empty() {
-                            ui.add_to_queue();
-                        } else {
-                            ui.search_input.push(' ');
+                    Some(Action::Next) => ui.skip_next(),
+                    // Back to the last song, or the start of this one
+                    Some(Action::Previous) => ui.skip_previous(),
+                    Some(Action::SeekBack) => ui.seek(-SEEK_STEP),
+                    Some(Action::SeekForward) => ui.seek(SEEK_STEP),
+                    Some(Action::ClearQueue) => ui.request_clear_queue(),
+                    // Shuffle queue in place
+                    Some(Action::Shuffle) => ui.shuffle_queue(),
+                    Some(Action::DedupeQueue) => ui.dedupe_queue(),
+                    Some(Action::ClearPlayed) => ui.clear_played(),
+                    Some(Action::SortQueue) if ui.tabs.index == 0 => ui.cycle_queue_sort(),
+                    // Play everything by the selected artist, shuffled
+                    Some(Action::ShuffleArtist) => ui.shuffle_artist(),
+                    // Play the current track over and over
+                    Some(Action::LoopTrack) => ui.toggle_loop(),
+                    Some(Action::SpeedUp) => ui.change_speed(Some(0.1)),
+                    Some(Action::SpeedDown) => ui.change_speed(Some(-0.1)),
+                    Some(Action::SpeedReset) => ui.change_speed(None),
+                    Some(Action::EqPreset) => ui.cycle_eq_preset(),
+                    Some(Action::BalanceLeft) => ui.change_balance(-0.1),
+                    Some(Action::BalanceRight) => ui.change_balance(0.1),
+                    // Skip the intro of the current track from now on
+                    Some(Action::SetStart) => ui.set_start_offset(),
+                    Some(Action::AddBookmark) => ui.start_bookmark(),
+                    Some(Action::NextBookmark) => ui.next_bookmark(),
+                    Some(Action::OpenFolder) => ui.start_open_folder(),
+                    Some(Action::Repeat) => ui.cycle_repeat(),
+                    Some(Action::ShuffleMode) => ui.toggle_shuffle_mode(),
+                    Some(Action::SortByPlays) => {
+                        if ui.tabs.index == 1 {
+                            // Order the track column by play count
+                            ui.lib_cols.toggle_sort_by_plays();
+                        } else if ui.tabs.index == 6 {
+                            ui.toggle_most_played();
                         }
-                    } else {
-                        // Add track to queue
-                        ui.add_to_queue();
-                    }
-                }
-                Key::Char('c') => {
-                    if ui.tabs.index == 2 {
-                        ui.search_input.push('c');
-                    } else {
-                        // Clear the queue
-                        ui.clear_queue();
                     }
-                }
-                Key::Char('n') => {
-                    if ui.tabs.index == 2 {
-                        ui.search_input.push('n');
-                    } else {
-                        // Add track to front of queue
-                        ui.add_to_front();
-                    }
-                }
-                Key::Char('1') => ui.tabs.index = 0,
-                Key::Char('2') => ui.tabs.index = 1,
-                Key::Char('3') => ui.tabs.index = 2,
-                Key::Up => {
-                    if ui.tabs.index == 1 {
-                        ui.lib_cols.on_up();
-                    } else if ui.tabs.index == 2 {
-                        ui.on_up_search();
+                    // Order albums by title or year
+                    Some(Action::AlbumSort) if ui.tabs.index == 1 => ui.lib_cols.cycle_album_sort(),
+                    // Star or unstar the selected track
+                    Some(Action::Favorite) => ui.toggle_favorite(),
+                    // In the settings tab these turn the equalizer band
+                    // up and down instead
+                    Some(Action::RateUp) if ui.tabs.index == 4 => ui.change_eq_gain(1.0),
+                    Some(Action::RateDown) if ui.tabs.index == 4 => ui.change_eq_gain(-1.0),
+                    Some(Action::RateUp) => ui.rate_selected(1),
+                    Some(Action::RateDown) => ui.rate_selected(-1),
+                    // Show the playing track in the library
+                    Some(Action::Reveal) => ui.reveal_now_playing(),
+                    Some(Action::GoToArtist) => ui.go_to_playing_artist(),
+                    Some(Action::GoToAlbum) => ui.go_to_playing_album(),
+                    Some(Action::RandomAlbum) => ui.random_album(),
+                    Some(Action::RandomTrack) => ui.random_track(),
+                    // Start or extend the sleep timer
+                    Some(Action::SleepTimer) => ui.extend_sleep_timer(15),
+                    Some(Action::CancelSleep) => ui.cancel_sleep_timer(),
+                    Some(Action::Jump) => {
+                        if ui.tabs.index == 0 {
+                            ui.start_queue_find();
+                        } else if ui.tabs.index == 1 {
+                            ui.start_jump();
+                        }
                     }
-                }
-                Key::Down => {
-                    if ui.tabs.index == 1 {
-                        ui.lib_cols.on_down();
-                    } else if ui.tabs.index == 2 {
-                        ui.on_down_search();
+                    Some(Action::NextLetter) if ui.tabs.index == 1 => ui.lib_cols.step_letter(true),
+                    Some(Action::PrevLetter) if ui.tabs.index == 1 => {
+                        ui.lib_cols.step_letter(false)
                     }
-                }
-                Key::Left => {
-                    if ui.tabs.index == 1 {
-                        ui.lib_cols.switch_left();
+                    Some(Action::Help) => ui.toggle_help(),
+                    Some(Action::Lyrics) => ui.toggle_lyrics(),
+                    Some(Action::TabQueue) => ui.tabs.index = 0,
+                    Some(Action::TabLibrary) => ui.tabs.index = 1,
+                    Some(Action::TabSearch) => ui.tabs.index = 2,
+                    Some(Action::TabFavorites) => ui.tabs.index = 3,
+                    Some(Action::TabSettings) => ui.tabs.index = 4,
+                    Some(Action::TabRecent) => ui.tabs.index = 5,
+                    Some(Action::TabPlayed) => ui.tabs.index = 6,
+                    Some(Action::NextTab) => ui.tabs.next(ui.config.wrap),
+                    Some(Action::PrevTab) => ui.tabs.previous(ui.config.wrap),
+                    Some(Action::Up) => ui.on_up(),
+                    Some(Action::Down) => ui.on_down(),
+                    // Columns in the library, seeking in the queue
+                    Some(Action::Left) => {
+                        if ui.tabs.index == 0 {
+                            ui.seek(-SEEK_STEP);
+                        } else if ui.tabs.index == 1 {
+                            ui.lib_cols.switch_left();
+                        } else if ui.tabs.index == 4 {
+                            ui.select_eq_band(-1);
+                        }
                     }
-                }
-                Key::Right => {
-                    if ui.tabs.index == 1 {
-                        ui.lib_cols.switch_right();
+                    Some(Action::Right) => {
+                        if ui.tabs.index == 0 {
+                            ui.seek(SEEK_STEP);
+                        } else if ui.tabs.index == 1 {
+                            ui.lib_cols.switch_right();
+                        } else if ui.tabs.index == 4 {
+                            ui.select_eq_band(1);
+                        }
                     }
-                }
-                Key::Char('\n') => {
-                    ui.on_enter();
-                }
-                Key::Char(c) => {
-                    if ui.tabs.index == 2 {
-                        ui.search_input.push(c);
+                    Some(Action::First) => {
+                        if ui.tabs.index == 0 {
+                            ui.on_first_queue();
+                        } else if ui.tabs.index == 1 {
+                            ui.lib_cols.on_first();
+                        }
                     }
-                }
-                Key::Backspace => {
-                    if ui.tabs.index == 2 {
-                        ui.search_input.pop();
+                    Some(Action::Last) => {
+                        if ui.tabs.index == 0 {
+                            ui.on_last_queue();
+                        } else if ui.tabs.index == 1 {
+                            ui.lib_cols.on_last();
+                        }
                     }
-                }
-                _ => {}
+                    Some(Action::PageUp) => ui.page_up(),
+                    Some(Action::PageDown) => ui.page_down(),
+                    // Keys that only do something on another tab
+                    Some(Action::SortQueue)
+                    | Some(Action::AlbumSort)
+                    | Some(Action::NextLetter)
+                    | Some(Action::PrevLetter)
+                    | None => {}
+                },
             }
         }
 
-        // Check for notifications that there is no audio being played
-        if let Ok(true) = ui.rx.recv_timeout(Duration::from_millis(250)) {
-            if ui.queue.is_empty() {
-                ui.blank_now_play();
+        ui.check_sleep_timer();
+
+        // Preloaded tracks the audio thread couldn't open are passed over
+        ui.drop_unreadable();
+
+        // The audio thread went on to the preloaded track by itself
+        if let Ok(()) = ui.channels.arx.try_recv() {
+            if ui.sleep_expired() {
+                ui.cancel_sleep_timer();
+                ui.finish_track();
+                ui.stop();
             } else {
-                ui.play_from_queue();
+                ui.advance();
             }
         }
+
+        ui.preload_next();
+
+        // Check for notifications that there is no audio being played
+        let notice = ui.channels.rx.recv_timeout(Duration::from_millis(250));
+        if let Err(RecvTimeoutError::Disconnected) = notice {
+            if !ui.audio_lost {
+                ui.lose_audio();
+            }
+        }
+        if let Ok(state) = notice {
+            ui.playback = state;
+        }
+        if let Ok(PlaybackState::Stopped) = notice {
+            // An unreadable track is skipped over without counting as played
+            if !ui.skip_unreadable() {
+                // Anything still marked as now playing ran to its end
+                ui.finish_track();
+
+                if ui.sleep_expired() {
+                    // The sleep timer was waiting for this track to end
+                    ui.cancel_sleep_timer();
+                    ui.blank_now_play();
+                } else {
+                    ui.play_from_queue();
+                }
+            }
+        }
+    }
+
+    // Keep play counts for the next session, and where a long track was
+    // left off
+    save_database(&config, &ui.lib_cols.artists.items).expect("Could not save database");
+    ui.keep_resume_position();
+    if ui.resume_positions.save(&config).is_err() {
+        warn!("Could not save resume positions");
     }
+
+    let session = Session::from_ui(&ui);
+    session.save(&config).expect("Could not save session");
+
     Ok(())
 }
+
+// Scan the library and build the search index on another thread, showing
+// how many files have been read so far. Returns None if Esc was pressed
+// before it finished, once the scan has stopped without writing anything
+fn scan_with_progress(
+    config: &Config,
+    rebuild: bool,
+    events: &Events,
+) -> Result<Option<(Library, Stats, u32, EngineGroup)>, failure::Error> {
+    let (progress, progress_rx) = channel::unbounded();
+    let (done_tx, done_rx) = channel::bounded(1);
+    let cancel = Arc::new(AtomicBool::new(false));
+
+    let scan_config = config.clone();
+    let scan = ScanControl {
+        progress,
+        cancel: Arc::clone(&cancel),
+    };
+    thread::spawn(move || {
+        let scanned = Library::load_with_progress(&scan_config, rebuild, Some(&scan)).and_then(
+            |(library, stats, pruned)| {
+                let engines = save_fuzzy_searcher(&scan_config, &library.artists)?;
+                Ok((library, stats, pruned, engines))
+            },
+        );
+        done_tx.send(scanned).ok();
+    });
+
+    let theme = Theme::from_config(&config.theme);
+    let stdout = io::stdout().into_raw_mode()?;
+    let mut terminal = Terminal::new(TermionBackend::new(stdout))?;
+    terminal.hide_cursor()?;
+    terminal.clear()?;
+
+    let mut scanned = 0;
+    let mut tick = 0;
+    loop {
+        if let Some(n) = progress_rx.try_iter().last() {
+            scanned = n;
+        }
+
+        match done_rx.try_recv() {
+            Ok(Ok(loaded)) => {
+                terminal.clear()?;
+                return Ok(Some(loaded));
+            }
+            // The scan failed, or panicked on the way
+            Ok(Err(())) | Err(channel::TryRecvError::Disconnected) => {
+                terminal.clear()?;
+                return Err(failure::err_msg("Could not load database"));
+            }
+            Err(channel::TryRecvError::Empty) => {}
+        }
+
+        terminal.draw(|mut f| {
+            let size = f.size();
+            ui::screens::draw_loading(&mut f, &theme, scanned, tick, size);
+        })?;
+
+        match events.next()? {
+            Event::Input(Key::Esc) => {
+                // Waiting for the scan to stop makes sure it doesn't write
+                // over the database afterwards. One that finished in the
+                // meantime is kept
+                cancel.store(true, Ordering::Relaxed);
+                terminal.clear()?;
+                return match done_rx.recv() {
+                    Ok(Ok(loaded)) => Ok(Some(loaded)),
+                    _ => Ok(None),
+                };
+            }
+            Event::Tick => tick += 1,
+            _ => {}
+        }
+    }
+}
+
+// Point the library at where its music was moved to without scanning it
+// again. Nothing is pruned first, which would drop every track not yet
+// where the database says
+fn remap(config: &Config, old_root: &Path, new_root: &Path) {
+    if !database_exists(config) {
+        println!("There is no library to remap yet");
+        process::exit(1);
+    }
+
+    let artists = match load_database(config) {
+        Ok((artists, _)) => artists,
+        Err(()) => {
+            println!("The library can't be read, scan it again with sonik -r");
+            process::exit(1);
+        }
+    };
+    let mut library = Library::new(artists);
+    match library.remap_root(config, old_root, new_root) {
+        Ok(remapped) => {
+            println!(
+                "Moved {} track(s) from {} to {}",
+                remapped,
+                old_root.display(),
+                new_root.display()
+            );
+            info!(
+                "Remapped {} track(s) from {:?} to {:?}",
+                remapped, old_root, new_root
+            );
+        }
+        Err(()) => {
+            println!("Could not save the library");
+            process::exit(1);
+        }
+    }
+}
+
+// The music folder is there but none of the tracks checked are, most likely
+// because it was moved. Ask whether to point the library at the music
+// folder or scan it again. Returns whether there is a library to carry on
+// with
+fn recover_moved(config: &Config, library: &mut Library, stats: &mut Stats) -> bool {
+    println!(
+        "None of the tracks checked in the library exist, but the music folder {} does",
+        config.music_folder
+    );
+
+    let new_root = Path::new(&config.music_folder);
+    let old_root = library.root().filter(|root| root != new_root);
+    if let Some(root) = &old_root {
+        println!("  m) point the library from {} to it", root.display());
+    }
+    println!("  r) scan it again");
+    println!("  q) quit");
+    print!("> ");
+    io::stdout().flush().ok();
+
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+
+    match (answer.trim(), old_root) {
+        ("m", Some(old_root)) => match library.remap_root(config, &old_root, new_root) {
+            Ok(remapped) => {
+                info!(
+                    "Remapped {} track(s) from {:?} to {:?}",
+                    remapped, old_root, new_root
+                );
+                true
+            }
+            Err(()) => {
+                println!("Could not save the library");
+                false
+            }
+        },
+        ("r", _) => {
+            println!("Scanning {}...", config.music_folder);
+            let (rescanned, rescanned_stats, _) =
+                Library::load(config, true).expect("Could not rebuild database");
+            *library = rescanned;
+            *stats = rescanned_stats;
+            true
+        }
+        _ => false,
+    }
+}
+
+// Log to the file named in the config, leaving the terminal to the
+// interface. Nothing is logged if the file can't be opened
+fn start_logging(config: &Config) {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&config.log_path);
+
+    match file {
+        Ok(file) => {
+            if WriteLogger::init(LevelFilter::Info, simplelog::Config::default(), file).is_err() {
+                println!("Could not start logging");
+            }
+        }
+        Err(_) => println!("Could not open the log file {}", config.log_path),
+    }
+}
+
+// Keys that keep their bound meaning while typing in the search tab,
+// everything else is text for the search box
+fn search_command(action: Option<Action>, ui: &UI) -> bool {
+    match action {
+        Some(Action::Play)
+        | Some(Action::Next)
+        | Some(Action::Previous)
+        | Some(Action::TabQueue)
+        | Some(Action::TabLibrary)
+        | Some(Action::TabSearch)
+        | Some(Action::TabFavorites)
+        | Some(Action::TabSettings)
+        | Some(Action::TabRecent)
+        | Some(Action::TabPlayed)
+        | Some(Action::NextTab)
+        | Some(Action::PrevTab) => true,
+        // Space only adds to the queue once there is something to add
+        Some(Action::Enqueue) | Some(Action::EnqueueShuffled) => !ui.search_results.is_empty(),
+        // Marking and rating wait until the search box is empty, so x, +
+        // and - can be typed
+        Some(Action::Mark) | Some(Action::RateUp) | Some(Action::RateDown) => {
+            ui.search_input.is_empty() && !ui.search_results.is_empty()
+        }
+        _ => false,
+    }
+}
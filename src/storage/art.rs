@@ -0,0 +1,98 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use id3::frame::PictureType;
+use id3::Tag;
+use log::warn;
+
+use crate::application::config::Config;
+use crate::storage::record::{Album, Artist};
+
+// Image files looked for next to the tracks when there is no embedded art
+const FOLDER_COVERS: [&str; 4] = ["cover.jpg", "folder.jpg", "cover.png", "folder.png"];
+
+// Find cover art for every album and record where it is. Embedded art is
+// written out to the covers folder inside the data folder, once per album
+pub fn cache_album_art(config: &Config, artists: &mut [Artist]) {
+    let cache_dir = Path::new(&config.cache_folder).join("covers");
+    if fs::create_dir_all(&cache_dir).is_err() {
+        println!("Could not create the album art cache");
+        warn!("Could not create {}", cache_dir.display());
+        return;
+    }
+
+    for artist in artists.iter_mut() {
+        for album in artist.albums.iter_mut() {
+            album.cover_path = find_cover(&cache_dir, album);
+        }
+    }
+}
+
+fn find_cover(cache_dir: &Path, album: &Album) -> Option<PathBuf> {
+    let key = cache_key(album);
+
+    // Already extracted on an earlier scan
+    for extension in ["jpg", "png"].iter() {
+        let cached = cache_dir.join(format!("{}.{}", key, extension));
+        if cached.exists() {
+            return Some(cached);
+        }
+    }
+
+    extract_embedded(cache_dir, &key, album).or_else(|| folder_cover(album))
+}
+
+// Albums are told apart by artist and title, reduced to characters that are
+// safe in a file name
+fn cache_key(album: &Album) -> String {
+    format!("{} - {}", album.artist, album.title)
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == ' ' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+fn extract_embedded(cache_dir: &Path, key: &str, album: &Album) -> Option<PathBuf> {
+    // Tracks of an album are assumed to carry the same art, so only the
+    // first one with a readable tag is checked
+    let tag = album
+        .tracks
+        .iter()
+        .find_map(|t| Tag::read_from_path(&t.file_path).ok())?;
+
+    // The front cover is preferred over whatever else is embedded
+    let picture = tag
+        .pictures()
+        .find(|p| p.picture_type == PictureType::CoverFront)
+        .or_else(|| tag.pictures().next())?;
+
+    let extension = if picture.mime_type == "image/png" {
+        "png"
+    } else {
+        "jpg"
+    };
+
+    let path = cache_dir.join(format!("{}.{}", key, extension));
+    fs::write(&path, &picture.data).ok()?;
+
+    Some(path)
+}
+
+fn folder_cover(album: &Album) -> Option<PathBuf> {
+    let folder = Path::new(&album.tracks.first()?.file_path).parent()?;
+
+    // Names are matched case-insensitively, e.g. Folder.jpg or COVER.JPG
+    fs::read_dir(folder)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .find(|path| match path.file_name().and_then(|n| n.to_str()) {
+            Some(name) => FOLDER_COVERS.contains(&name.to_lowercase().as_str()),
+            None => false,
+        })
+}
@@ -1,12 +1,31 @@
+use std::cmp::Reverse;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::io::{BufReader, BufWriter};
+use std::hash::{Hash, Hasher};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use bincode::{deserialize_from, serialize_into};
-use ignore::{DirEntry, Walk};
+use crossbeam_channel::Sender;
+use ignore::overrides::OverrideBuilder;
+use ignore::{DirEntry, Walk, WalkBuilder};
+use log::{debug, info, warn};
+use serde::Serialize;
+use serde_derive::{Deserialize, Serialize};
 use simsearch::SimSearch;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
 
-use crate::application::config::Config;
-use crate::storage::record::{Album, Artist, Stats, Track};
+use crate::application::config::{Config, DedupeStrategy};
+use crate::storage::art::cache_album_art;
+use crate::storage::formats::SCAN_EXTENSIONS;
+use crate::storage::record::{Album, Artist, Stats, Track, UNKNOWN_ALBUM};
+#[cfg(feature = "sqlite")]
+use crate::storage::sqlite;
 use crate::storage::terms::{SearchQuery, Term};
 
 pub struct EngineGroup {
@@ -23,14 +42,61 @@ pub enum Engine {
 
 impl Engine {
     pub fn search(&self, query_str: &str) -> SearchResult {
+        let query = fold_accents(query_str);
         match self {
-            Engine::Artists(e) => SearchResult::Artists(e.search(query_str)),
-            Engine::Albums(e) => SearchResult::Albums(e.search(query_str)),
-            Engine::Tracks(e) => SearchResult::Tracks(e.search(query_str)),
+            Engine::Artists(e) => SearchResult::Artists(e.search(&query)),
+            Engine::Albums(e) => SearchResult::Albums(e.search(&query)),
+            Engine::Tracks(e) => SearchResult::Tracks(e.search(&query)),
         }
     }
 }
 
+// Strip accents and other diacritics, so "bjork" finds "Björk". Only the
+// indexed copies are folded, names are shown as they are tagged
+fn fold_accents(s: &str) -> String {
+    s.nfd().filter(|c| !is_combining_mark(*c)).collect()
+}
+
+// Where each word of a query turns up in a name, as byte ranges, ignoring
+// case and accents like searching does. Fuzzy matches that aren't spelled
+// out in the name have none
+pub fn match_spans(name: &str, query: &str) -> Vec<(usize, usize)> {
+    let chars: Vec<(usize, char)> = name
+        .char_indices()
+        .map(|(i, c)| (i, fold_char(c)))
+        .collect();
+
+    let mut spans = Vec::new();
+    for word in query.split_whitespace() {
+        let word: Vec<char> = word.chars().map(fold_char).collect();
+        if word.len() > chars.len() {
+            continue;
+        }
+
+        let found = (0..=chars.len() - word.len()).find(|&i| {
+            chars[i..i + word.len()]
+                .iter()
+                .map(|(_, c)| *c)
+                .eq(word.iter().copied())
+        });
+        if let Some(start) = found {
+            let end = chars
+                .get(start + word.len())
+                .map(|(i, _)| *i)
+                .unwrap_or_else(|| name.len());
+            spans.push((chars[start].0, end));
+        }
+    }
+
+    spans
+}
+
+// A character without its accent, in lower case
+fn fold_char(c: char) -> char {
+    let base = c.nfd().next().unwrap_or(c);
+    base.to_lowercase().next().unwrap_or(base)
+}
+
 type ArtistResult = Vec<usize>;
 type AlbumResult = Vec<(usize, usize)>;
 type TrackResult = Vec<(usize, usize, usize)>;
@@ -41,90 +107,535 @@ pub enum SearchResult {
     Tracks(TrackResult),
 }
 
-fn is_music(entry: &DirEntry) -> bool {
+pub fn is_music(entry: &DirEntry) -> bool {
     let metadata = fs::metadata(entry.path()).unwrap();
     if metadata.is_dir() {
         return false;
     }
 
     // If the filename isn't a suitable audio format, return false
-    if let Some(extension) = entry.path().extension() {
-        match extension.to_str() {
-            Some("mp3") => true,
-            Some("flac") => true,
-            Some("ogg") => true,
-            _ => false,
-        }
-    } else {
-        false
+    match entry.path().extension().and_then(|e| e.to_str()) {
+        Some(extension) => SCAN_EXTENSIONS.contains(&extension),
+        None => false,
     }
 }
 
-pub fn create_and_load_database(config: &Config) -> Result<(Vec<Artist>, Stats), ()> {
+// How a scan running on another thread reports back and is stopped. The
+// number of files read so far is sent on progress, and setting cancel makes
+// the scan give up at the next file without writing anything
+pub struct ScanControl {
+    pub progress: Sender<usize>,
+    pub cancel: Arc<AtomicBool>,
+}
+
+impl ScanControl {
+    fn cancelled(&self) -> bool {
+        self.cancel.load(Ordering::Relaxed)
+    }
+}
+
+// Scan the music folder into a new database. When scan is given, progress
+// is reported on it as the scan goes, and nothing is printed since the
+// interface has the terminal. A cancelled scan leaves the database as it was.
+// Also returns how many tracks of the old database were dropped because
+// their files are gone, like pruning does when loading
+pub fn create_and_load_database(
+    config: &Config,
+    scan: Option<&ScanControl>,
+) -> Result<(Vec<Artist>, Stats, u32), ()> {
     // create vector of artists
     let mut artists: Vec<Artist> = Vec::new();
-    let mut stats = Stats::new().unwrap();
+
+    // Tracks are only sorted into artists once all of them are read, as
+    // compilations can't be told apart before then
+    let mut tracks: Vec<Track> = Vec::new();
+
+    // Keys of every track added so far, used to skip duplicates
+    let mut seen: HashSet<String> = HashSet::new();
+    let mut duplicates = 0;
+
+    // Tracks keep the date they were first added, their play count,
+    // favorite, start offset, rating and bookmarks when the library is
+    // rebuilt
+    let previous = previous_tracks(config);
+    let scan_time = seconds_since_epoch(SystemTime::now());
+
+    info!("Scanning {}", config.music_folder);
+    let mut unreadable = 0;
+    let mut scanned = 0;
 
     // Walk through the music directory and add paths for each track
-    for result in Walk::new(&config.music_folder) {
+    for result in music_walk(config, scan) {
+        if scan.map_or(false, ScanControl::cancelled) {
+            info!(
+                "Scan cancelled after {} file(s), nothing was written",
+                scanned
+            );
+            return Err(());
+        }
+        if let Err(e) = &result {
+            warn!("Skipped while scanning: {}", e);
+        }
         if let Ok(entry) = result {
             if is_music(&entry) {
-                match Track::new(entry.path().to_path_buf()) {
-                    Ok(t) => add_to_database_helper(t, &mut artists, &mut stats),
-                    _ => println!("{}", entry.path().to_str().unwrap()),
+                scanned += 1;
+                if let Some(scan) = scan {
+                    scan.progress.send(scanned).ok();
+                }
+
+                match Track::new(entry.path().to_path_buf(), &config.artist_separators) {
+                    Ok(mut t) => {
+                        if !seen.insert(dedupe_key(&t, config.dedupe)) {
+                            duplicates += 1;
+                            continue;
+                        }
+
+                        if let Some(old) = previous.get(&t.file_path) {
+                            t.play_count = old.play_count;
+                            t.favorite = old.favorite;
+                            t.start_offset = old.start_offset;
+                            t.rating = old.rating;
+                            t.bookmarks = old.bookmarks.clone();
+                        }
+
+                        t.date_added = match previous.get(&t.file_path) {
+                            Some(old) => old.date_added,
+                            // On the first scan the files themselves say
+                            // when they were imported
+                            None if previous.is_empty() => {
+                                file_date(&t.file_path).unwrap_or(scan_time)
+                            }
+                            None => scan_time,
+                        };
+
+                        tracks.push(t)
+                    }
+                    _ => {
+                        unreadable += 1;
+                        warn!("Could not read {}", entry.path().display());
+                        if scan.is_none() {
+                            println!("{}", entry.path().to_str().unwrap())
+                        }
+                    }
                 }
             }
         }
     }
 
-    let mut f = BufWriter::new(
-        fs::File::create(&config.database_path).expect("Could not write to database path"),
-    );
+    group_compilations(&mut tracks, config);
+    for t in tracks {
+        add_to_database_helper(t, &mut artists)
+    }
+
+    if duplicates > 0 && scan.is_none() {
+        println!("Skipped {} duplicate track(s)", duplicates);
+    }
 
-    let mut g = BufWriter::new(
-        fs::File::create(&config.stats_path).expect("Could not write to stats path"),
+    let stats = Stats::from_library(&artists);
+    info!(
+        "Scan finished: {} artists, {} albums, {} tracks, {} duplicate(s) and {} unreadable file(s) skipped",
+        stats.artists, stats.albums, stats.tracks, duplicates, unreadable
     );
 
     // Sort for easy finding in the UI
     artists.sort_by(|a, b| a.title.to_lowercase().cmp(&b.title.to_lowercase()));
 
-    serialize_into(&mut f, &artists).expect("Could not serialize database to file");
-    serialize_into(&mut g, &stats).expect("Could not serialize stats to file");
+    cache_album_art(config, &mut artists);
 
-    Ok((artists, stats))
+    write_library(config, &artists).expect("Could not write database to file");
+    write_atomically(&config.stats_path, &stats).expect("Could not write stats to file");
+
+    let pruned = previous
+        .keys()
+        .filter(|path| !Path::new(path).exists())
+        .count() as u32;
+
+    Ok((artists, stats, pruned))
 }
 
-pub fn load_database(config: &Config) -> Result<(Vec<Artist>, Stats), ()> {
-    let mut library_reader = BufReader::new(
-        fs::File::open(&config.database_path).expect("Could not open database file"),
-    );
+// Everything in the music folder that scanning looks at, leaving out what
+// matches the exclude globs in the config or a .sonikignore file, on top of
+// the hidden and ignored files Walk skips anyway. A bad glob is only
+// printed when there is no progress screen to be drawn over
+fn music_walk(config: &Config, scan: Option<&ScanControl>) -> Walk {
+    let mut overrides = OverrideBuilder::new(&config.music_folder);
+    for pattern in &config.exclude {
+        // Overrides whitelist by default, ! turns a glob into an ignore
+        if overrides.add(&format!("!{}", pattern)).is_err() {
+            warn!(
+                "Ignoring the exclude pattern '{}', it isn't a valid glob",
+                pattern
+            );
+            if scan.is_none() {
+                println!("Warning: exclude pattern '{}' is not a valid glob", pattern);
+            }
+        }
+    }
+
+    // Following links, Walk notices a folder that links back to one it is
+    // already inside and gives an error for it instead of going round
+    let mut walk = WalkBuilder::new(&config.music_folder);
+    walk.add_custom_ignore_filename(".sonikignore")
+        .follow_links(config.follow_symlinks);
+    match overrides.build() {
+        Ok(overrides) => {
+            walk.overrides(overrides);
+        }
+        Err(_) => warn!("Could not apply the exclude patterns"),
+    }
 
-    let mut stats_reader =
-        BufReader::new(fs::File::open(&config.stats_path).expect("Could not open database file"));
+    walk.build()
+}
 
-    let artists = deserialize_from(&mut library_reader).expect("Could not deserialize");
-    let stats = deserialize_from(&mut stats_reader).expect("Could not deserialize");
+// Every track in the existing database by path, if there is a database
+fn previous_tracks(config: &Config) -> HashMap<String, Track> {
+    let artists = if database_exists(config) {
+        read_library(config).unwrap_or_default()
+    } else {
+        Vec::new()
+    };
 
-    Ok((artists, stats))
+    artists
+        .iter()
+        .flat_map(|a| a.albums.iter())
+        .flat_map(|al| al.tracks.iter())
+        .map(|t| (t.file_path.clone(), t.clone()))
+        .collect()
 }
 
-pub fn rebuild_database(config: &Config) -> Result<(Vec<Artist>, Stats), ()> {
-    fs::remove_file(&config.database_path).expect("Could not delete database");
+// When the file was created, or last modified where that isn't recorded
+fn file_date(path: &str) -> Option<u64> {
+    let metadata = fs::metadata(path).ok()?;
+    let time = metadata.created().or_else(|_| metadata.modified()).ok()?;
+
+    Some(seconds_since_epoch(time))
+}
+
+fn seconds_since_epoch(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
 
-    let (artists, stats) = create_and_load_database(config).expect("Could not create database");
+// Serialize into a temporary file in the same directory and rename it over
+// the destination once it is complete, so that a crash mid-write leaves
+// either the old file or the new one, never a truncated mix of the two
+fn write_atomically<T: Serialize>(path: &str, value: &T) -> Result<(), ()> {
+    let tmp_path = format!("{}.tmp", path);
+
+    let mut writer = BufWriter::new(fs::File::create(&tmp_path).map_err(|_| ())?);
+    serialize_into(&mut writer, value).map_err(|_| ())?;
+    writer.flush().map_err(|_| ())?;
+
+    let file = writer.into_inner().map_err(|_| ())?;
+    file.sync_all().map_err(|_| ())?;
+
+    fs::rename(&tmp_path, path).map_err(|_| ())
+}
+
+pub fn load_database(config: &Config) -> Result<(Vec<Artist>, Stats), ()> {
+    let artists = read_library(config)?;
+    // Stats from before total_time was widened don't read, and are tallied
+    // again from the library instead, as are missing ones
+    let saved = fs::File::open(&config.stats_path)
+        .ok()
+        .and_then(|f| deserialize_from(BufReader::new(f)).ok());
+    let stats = match saved {
+        Some(stats) => stats,
+        None => {
+            let stats = Stats::from_library(&artists);
+            write_atomically(&config.stats_path, &stats)?;
+            stats
+        }
+    };
 
     Ok((artists, stats))
 }
 
-fn add_to_database_helper(t: Track, artists: &mut Vec<Artist>, stats: &mut Stats) {
+// Whether there is a library to load, as opposed to one still to be scanned
+pub fn database_exists(config: &Config) -> bool {
+    #[cfg(feature = "sqlite")]
+    return sqlite::database_path(config).exists();
+
+    #[cfg(not(feature = "sqlite"))]
+    Path::new(&config.database_path).exists()
+}
+
+// Written ahead of the library in its file, so that one saved by a build
+// that laid tracks out differently is scanned again rather than misread.
+// Bumped whenever what Artist, Album or Track hold changes
+#[cfg(not(feature = "sqlite"))]
+const LIBRARY_MAGIC: [u8; 4] = *b"SNKL";
+#[cfg(not(feature = "sqlite"))]
+const LIBRARY_VERSION: u32 = 1;
+
+// Whether the saved library is one this build can't read, from before the
+// version was written or from another version, and has to be scanned again
+#[cfg(not(feature = "sqlite"))]
+pub fn database_outdated(config: &Config) -> bool {
+    match fs::File::open(&config.database_path) {
+        Ok(f) => !header_fits(&mut BufReader::new(f)),
+        Err(_) => false,
+    }
+}
+
+// SQLite adds what an older database is missing when it is opened
+#[cfg(feature = "sqlite")]
+pub fn database_outdated(_config: &Config) -> bool {
+    false
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn header_fits<R: std::io::Read>(reader: &mut R) -> bool {
+    match deserialize_from::<_, ([u8; 4], u32)>(reader) {
+        Ok((magic, version)) => magic == LIBRARY_MAGIC && version == LIBRARY_VERSION,
+        Err(_) => false,
+    }
+}
+
+// The library is kept with bincode, or in SQLite when built with the
+// sqlite feature, see storage::sqlite
+#[cfg(not(feature = "sqlite"))]
+fn read_library(config: &Config) -> Result<Vec<Artist>, ()> {
+    let mut reader = BufReader::new(fs::File::open(&config.database_path).map_err(|_| ())?);
+    if !header_fits(&mut reader) {
+        return Err(());
+    }
+    deserialize_from(reader).map_err(|_| ())
+}
+
+#[cfg(feature = "sqlite")]
+fn read_library(config: &Config) -> Result<Vec<Artist>, ()> {
+    sqlite::read(config)
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn write_library(config: &Config, artists: &[Artist]) -> Result<(), ()> {
+    write_atomically(
+        &config.database_path,
+        &(LIBRARY_MAGIC, LIBRARY_VERSION, artists),
+    )
+}
+
+#[cfg(feature = "sqlite")]
+fn write_library(config: &Config, artists: &[Artist]) -> Result<(), ()> {
+    sqlite::write(config, artists)
+}
+
+pub fn rebuild_database(
+    config: &Config,
+    scan: Option<&ScanControl>,
+) -> Result<(Vec<Artist>, Stats, u32), ()> {
+    // The old database is left in place until the new one is fully written
+    create_and_load_database(config, scan)
+}
+
+pub fn prune_database(
+    config: &Config,
+    artists: &mut Vec<Artist>,
+    stats: &mut Stats,
+) -> Result<u32, ()> {
+    // Pruning a library whose music folder was moved or isn't mounted
+    // would empty it
+    if paths_missing(artists) {
+        warn!("None of the tracks checked exist, not removing any from the library");
+        return Ok(0);
+    }
+
+    let pruned = prune_missing_tracks(artists);
+
+    // Only rewrite the files if something actually changed
+    if pruned > 0 {
+        *stats = Stats::from_library(artists);
+        save_database(config, artists)?;
+        write_atomically(&config.stats_path, stats)?;
+    }
+
+    Ok(pruned)
+}
+
+// Write the library back out, e.g. to keep play counts between sessions
+#[cfg(not(feature = "sqlite"))]
+pub fn save_database(config: &Config, artists: &[Artist]) -> Result<(), ()> {
+    write_library(config, artists)
+}
+
+// Only the rows that changed are touched
+#[cfg(feature = "sqlite")]
+pub fn save_database(config: &Config, artists: &[Artist]) -> Result<(), ()> {
+    sqlite::save(config, artists)
+}
+
+// Dump the library as JSON, artists holding their albums holding their
+// tracks, with every field the database keeps
+pub fn export_json(artists: &[Artist], path: &Path) -> Result<(), ()> {
+    let mut writer = BufWriter::new(fs::File::create(path).map_err(|_| ())?);
+    serde_json::to_writer_pretty(&mut writer, artists).map_err(|_| ())?;
+    writer.flush().map_err(|_| ())
+}
+
+// How many stored paths are looked at to tell whether the library moved
+const MOVED_SAMPLES: usize = 5;
+
+// Whether none of a few tracks spread through the library are where the
+// database says. A track or two deleted since the last scan doesn't make it
+// look this way, a moved or unmounted music folder does
+pub fn paths_missing(artists: &[Artist]) -> bool {
+    let tracks: Vec<&Track> = artists
+        .iter()
+        .flat_map(|a| a.albums.iter())
+        .flat_map(|al| al.tracks.iter())
+        .collect();
+    if tracks.is_empty() {
+        return false;
+    }
+
+    let step = (tracks.len() / MOVED_SAMPLES).max(1);
+    tracks
+        .iter()
+        .step_by(step)
+        .take(MOVED_SAMPLES)
+        .all(|t| !Path::new(&t.file_path).exists())
+}
+
+// Point every track and cover under old_root at the same place under
+// new_root, after the music folder was moved, and write the library back.
+// Returns how many tracks were changed
+pub fn remap_database(
+    config: &Config,
+    artists: &mut [Artist],
+    old_root: &Path,
+    new_root: &Path,
+) -> Result<u32, ()> {
+    let mut remapped = 0;
+
+    for album in artists.iter_mut().flat_map(|a| a.albums.iter_mut()) {
+        if let Some(cover) = album.cover_path.as_ref() {
+            if let Ok(rest) = cover.strip_prefix(old_root) {
+                album.cover_path = Some(new_root.join(rest));
+            }
+        }
+
+        for t in album.tracks.iter_mut() {
+            let path = match Path::new(&t.file_path).strip_prefix(old_root) {
+                Ok(rest) => new_root.join(rest),
+                Err(_) => continue,
+            };
+            if let Some(path) = path.to_str() {
+                t.file_path = path.to_owned();
+                remapped += 1;
+            }
+        }
+    }
+
+    // The whole library is written again, since tracks are saved by path
+    if remapped > 0 {
+        write_library(config, artists)?;
+    }
+
+    Ok(remapped)
+}
+
+// Remove tracks whose files no longer exist on disk, along with any albums
+// and artists left empty, and return how many tracks were removed
+fn prune_missing_tracks(artists: &mut Vec<Artist>) -> u32 {
+    let mut pruned = 0;
+
+    for artist in artists.iter_mut() {
+        for album in artist.albums.iter_mut() {
+            let track_count = album.tracks.len();
+            album.tracks.retain(|t| Path::new(&t.file_path).exists());
+            pruned += (track_count - album.tracks.len()) as u32;
+        }
+
+        artist.albums.retain(|al| !al.tracks.is_empty());
+    }
+
+    artists.retain(|a| !a.albums.is_empty());
+
+    pruned
+}
+
+// Two tracks with the same key are considered the same track
+fn dedupe_key(t: &Track, strategy: DedupeStrategy) -> String {
+    match strategy {
+        DedupeStrategy::Path => match fs::canonicalize(&t.file_path) {
+            Ok(p) => p.to_string_lossy().to_string(),
+            Err(_) => t.file_path.clone(),
+        },
+        DedupeStrategy::Content => format!(
+            "{}\u{0}{}\u{0}{}\u{0}{}",
+            t.title, t.artist, t.album, t.duration
+        ),
+    }
+}
+
+// Albums in one folder credited to many different artists and without an
+// album artist tag, such as soundtracks and compilations, go under a single
+// album artist instead of each artist getting a one track album. An album
+// artist tag found on some of the tracks is used for all of them, otherwise
+// config.various_artists
+fn group_compilations(tracks: &mut [Track], config: &Config) {
+    if config.compilation_artists == 0 {
+        return;
+    }
+
+    let mut albums: HashMap<(PathBuf, String), Vec<usize>> = HashMap::new();
+    for (i, t) in tracks.iter().enumerate() {
+        if t.album == UNKNOWN_ALBUM {
+            continue;
+        }
+        let folder = Path::new(&t.file_path)
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        let album = normalize_name(&t.album).to_lowercase();
+        albums.entry((folder, album)).or_default().push(i);
+    }
+
+    for ((folder, _), indices) in albums {
+        let album_artists: HashSet<String> = indices
+            .iter()
+            .map(|&i| normalize_name(&tracks[i].album_artist).to_lowercase())
+            .collect();
+        if album_artists.len() < config.compilation_artists {
+            continue;
+        }
+
+        // Without a tag the album artist is the track's own first artist
+        let tagged = indices
+            .iter()
+            .map(|&i| &tracks[i])
+            .find(|t| !t.artists.contains(&t.album_artist))
+            .map(|t| t.album_artist.clone());
+        let album_artist = tagged.unwrap_or_else(|| config.various_artists.clone());
+        debug!(
+            "Grouping {} tracks in {} under {}",
+            indices.len(),
+            folder.display(),
+            album_artist
+        );
+        for i in indices {
+            tracks[i].album_artist = album_artist.clone();
+        }
+    }
+}
+
+fn add_to_database_helper(t: Track, artists: &mut Vec<Artist>) {
     // Copy the string information out of the track and pass it
     // to add_to_database along with the actual track struct
 
-    let artist_name = t.album_artist.clone();
-    let album_title = t.album.clone();
+    let artist_name = normalize_name(&t.album_artist);
+    let album_title = normalize_name(&t.album);
     let album_year = t.year;
 
-    add_to_database(&artist_name, &album_title, album_year, t, artists, stats);
+    add_to_database(&artist_name, &album_title, album_year, t, artists);
+}
+
+// Trim the name and collapse any runs of whitespace into single spaces,
+// so "The  Beatles " and "The Beatles" end up as the same artist
+fn normalize_name(name: &str) -> String {
+    name.split_whitespace().collect::<Vec<&str>>().join(" ")
 }
 
 fn add_to_database(
@@ -133,16 +644,19 @@ fn add_to_database(
     album_year: i32,
     t: Track,
     artists: &mut Vec<Artist>,
-    stats: &mut Stats,
 ) {
-    stats.tracks += 1;
-    stats.total_time += t.duration;
-
     // Strings should be copies of information in track
     // Use them to add/check artists/albums and add track
 
+    // Names are compared case-insensitively, while the first variant seen
+    // is the one kept for display
+    let artist_key = artist_name.to_lowercase();
+    let album_key = album_title.to_lowercase();
+
     // Find an artist that matches the artist name
-    let artist_index = artists.iter().position(|a| a.title == artist_name);
+    let artist_index = artists
+        .iter()
+        .position(|a| a.title.to_lowercase() == artist_key);
 
     match artist_index {
         // If there is an artist that matches that name...
@@ -150,19 +664,23 @@ fn add_to_database(
             let album_index = artists[idx]
                 .albums
                 .iter()
-                .position(|al| al.title == album_title);
+                .position(|al| al.title.to_lowercase() == album_key);
             match album_index {
-                Some(al_idx) => if let Ok(()) = artists[idx].albums[al_idx].update_album(t) {},
+                Some(al_idx) => {
+                    if artists[idx].albums[al_idx].update_album(t).is_err() {
+                        warn!("Could not add a track to {}", album_title);
+                    }
+                }
 
                 None => {
                     // If not, create the album and add the track
                     let mut album =
                         Album::new(album_title.to_string(), artist_name.to_string(), album_year)
                             .unwrap();
-                    //debug - println!("Created new album: {}", album_title);
-                    album.tracks.push(t);
-                    if let Ok(()) = artists[idx].add_album(album) {}
-                    stats.albums += 1;
+                    debug!("Created new album: {}", album_title);
+                    if album.update_album(t).is_err() || artists[idx].add_album(album).is_err() {
+                        warn!("Could not add {} to {}", album_title, artist_name);
+                    }
                 }
             }
         }
@@ -170,49 +688,364 @@ fn add_to_database(
         // If no artist matches that name, then create the artist and album, and add track
         None => {
             let mut artist = Artist::new(artist_name.to_string()).unwrap();
-            stats.artists += 1;
-            //debug - println!("Created new artist: {}", &artist.name);
+            debug!("Created new artist: {}", artist_name);
 
             let mut album =
                 Album::new(album_title.to_string(), artist_name.to_string(), album_year).unwrap();
-            //debug - println!("Created new album: {}", &album.title);
-            album.tracks.push(t);
-            if let Ok(()) = artist.add_album(album) {}
-            stats.albums += 1;
+            debug!("Created new album: {}", album_title);
+            if album.update_album(t).is_err() || artist.add_album(album).is_err() {
+                warn!("Could not add {} to {}", album_title, artist_name);
+            }
             artists.push(artist);
         }
     }
 }
 
-pub fn create_fuzzy_searcher(records: &[Artist]) -> Result<EngineGroup, ()> {
-    let mut artists: SimSearch<usize> = SimSearch::new();
-    let mut albums: SimSearch<(usize, usize)> = SimSearch::new();
-    let mut tracks: SimSearch<(usize, usize, usize)> = SimSearch::new();
+// Bumped whenever what goes into the index changes, so old index files are
+// rebuilt rather than read
+const INDEX_VERSION: u32 = 1;
+
+// The folded names the search engines are built from, kept between sessions
+// next to the library. Results are indices into the library, so the index
+// only fits the library it was made from, which the fingerprint records
+#[derive(Serialize, Deserialize)]
+struct SearchIndex {
+    version: u32,
+    fingerprint: u64,
+    artists: Vec<(usize, Vec<String>)>,
+    albums: Vec<((usize, usize), String)>,
+    tracks: Vec<((usize, usize, usize), String)>,
+}
+
+impl SearchIndex {
+    fn new(records: &[Artist]) -> SearchIndex {
+        let mut artists = Vec::new();
+        let mut albums = Vec::new();
+        let mut tracks = Vec::new();
+
+        for (i, record) in records.iter().enumerate() {
+            // Artists are also found by anyone credited on their tracks
+            let mut names: Vec<String> = vec![fold_accents(&record.title)];
+            for track in record.albums.iter().flat_map(|al| al.tracks.iter()) {
+                for name in &track.artists {
+                    let name = fold_accents(name);
+                    if !names.contains(&name) {
+                        names.push(name);
+                    }
+                }
+            }
+            artists.push((i, names));
+            for (j, album) in record.albums.iter().enumerate() {
+                albums.push(((i, j), fold_accents(&album.title)));
+                for (k, track) in album.tracks.iter().enumerate() {
+                    tracks.push(((i, j, k), fold_accents(&track.title)));
+                }
+            }
+        }
+
+        SearchIndex {
+            version: INDEX_VERSION,
+            fingerprint: library_fingerprint(records),
+            artists,
+            albums,
+            tracks,
+        }
+    }
+
+    fn engines(&self) -> EngineGroup {
+        let mut artists: SimSearch<usize> = SimSearch::new();
+        let mut albums: SimSearch<(usize, usize)> = SimSearch::new();
+        let mut tracks: SimSearch<(usize, usize, usize)> = SimSearch::new();
+
+        for (i, names) in &self.artists {
+            let names: Vec<&str> = names.iter().map(String::as_str).collect();
+            artists.insert_tokenized(*i, &names);
+        }
+        for (id, name) in &self.albums {
+            albums.insert(*id, name);
+        }
+        for (id, name) in &self.tracks {
+            tracks.insert(*id, name);
+        }
+
+        EngineGroup {
+            artists: Engine::Artists(artists),
+            albums: Engine::Albums(albums),
+            tracks: Engine::Tracks(tracks),
+        }
+    }
+
+    // Whether the index was made from this library by this version
+    fn fits(&self, records: &[Artist]) -> bool {
+        self.version == INDEX_VERSION && self.fingerprint == library_fingerprint(records)
+    }
+}
 
-    for (i, record) in (&records).iter().enumerate() {
-        let artist_name = &record.title;
-        artists.insert(i, &artist_name);
-        for (j, album) in (&record.albums).iter().enumerate() {
-            let album_name = &album.title;
-            albums.insert((i, j), &album_name);
-            for (k, track) in (&album.tracks).iter().enumerate() {
-                let track_name = &track.title;
-                tracks.insert((i, j, k), &track_name);
+// Hash of everything that decides what a search result points at: the
+// order of artists, albums and tracks and the names that are indexed. Play
+// counts and the like change freely without touching it
+fn library_fingerprint(records: &[Artist]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for artist in records {
+        artist.title.hash(&mut hasher);
+        for album in &artist.albums {
+            album.title.hash(&mut hasher);
+            for track in &album.tracks {
+                track.file_path.hash(&mut hasher);
+                track.title.hash(&mut hasher);
+                track.artists.hash(&mut hasher);
             }
         }
     }
 
-    Ok(EngineGroup {
-        artists: Engine::Artists(artists),
-        albums: Engine::Albums(albums),
-        tracks: Engine::Tracks(tracks),
-    })
+    hasher.finish()
+}
+
+fn index_path(config: &Config) -> PathBuf {
+    Path::new(&config.cache_folder).join("search.index")
+}
+
+// The search engines for a library, from the saved index when it was made
+// from this same library. An index that is missing, unreadable or from a
+// different library is rebuilt and saved in its place
+pub fn load_fuzzy_searcher(config: &Config, records: &[Artist]) -> Result<EngineGroup, ()> {
+    let saved = fs::File::open(index_path(config))
+        .ok()
+        .and_then(|f| deserialize_from::<_, SearchIndex>(BufReader::new(f)).ok());
+    if let Some(index) = saved {
+        if index.fits(records) {
+            return Ok(index.engines());
+        }
+        info!("The search index doesn't match the library, rebuilding it");
+    }
+
+    save_fuzzy_searcher(config, records)
+}
+
+// Build the search engines for a library and save the index they came from
+// alongside it
+pub fn save_fuzzy_searcher(config: &Config, records: &[Artist]) -> Result<EngineGroup, ()> {
+    let index = SearchIndex::new(records);
+    if let Some(path) = index_path(config).to_str() {
+        if write_atomically(path, &index).is_err() {
+            warn!("Could not save the search index");
+        }
+    }
+
+    Ok(index.engines())
 }
 
-pub fn search(engine: &EngineGroup, query: SearchQuery) -> SearchResult {
+pub fn search(engine: &EngineGroup, artists: &[Artist], query: SearchQuery) -> SearchResult {
     match query.terms {
         Term::Title(s) => engine.tracks.search(s.as_str()),
         Term::Album(s) => engine.albums.search(s.as_str()),
         Term::Artist(s) => engine.artists.search(s.as_str()),
+        Term::Rating(min) => SearchResult::Tracks(rated_tracks(artists, min)),
+        Term::File(s) => SearchResult::Tracks(tracks_by_path(artists, &s)),
+    }
+}
+
+// Tracks whose path contains the text, ignoring case, in library order.
+// Finds a file whatever its tags say, e.g. one tagged wrong
+fn tracks_by_path(artists: &[Artist], text: &str) -> TrackResult {
+    let text = text.trim().to_lowercase();
+    if text.is_empty() {
+        return Vec::new();
+    }
+
+    let mut found = Vec::new();
+    for (a, artist) in artists.iter().enumerate() {
+        for (al, album) in artist.albums.iter().enumerate() {
+            for (t, track) in album.tracks.iter().enumerate() {
+                if track.file_path.to_lowercase().contains(&text) {
+                    found.push((a, al, t));
+                }
+            }
+        }
+    }
+
+    found
+}
+
+// Tracks rated at least min stars, highest first and otherwise in library
+// order
+fn rated_tracks(artists: &[Artist], min: u8) -> TrackResult {
+    let mut rated = Vec::new();
+    for (a, artist) in artists.iter().enumerate() {
+        for (al, album) in artist.albums.iter().enumerate() {
+            for (t, track) in album.tracks.iter().enumerate() {
+                if track.rating > 0 && track.rating >= min {
+                    rated.push((a, al, t));
+                }
+            }
+        }
+    }
+    rated.sort_by_key(|&(a, al, t)| Reverse(artists[a].albums[al].tracks[t].rating));
+
+    rated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    use serde::ser::{Error, SerializeSeq, Serializer};
+
+    // A file in the temp folder unique to this test
+    fn temp_file(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("sonik-{}-{}", std::process::id(), name));
+        path.to_string_lossy().to_string()
+    }
+
+    // Gets partway through serializing, then fails like a write cut short
+    struct Interrupted;
+
+    impl Serialize for Interrupted {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let mut seq = serializer.serialize_seq(Some(3))?;
+            seq.serialize_element(&1u32)?;
+            Err(S::Error::custom("interrupted"))
+        }
+    }
+
+    fn track(path: &str, album_artist: &str, album: &str) -> Track {
+        let mut t = Track::dummy();
+        t.file_path = path.to_string();
+        t.title = path.to_string();
+        t.artist = album_artist.to_string();
+        t.album_artist = album_artist.to_string();
+        t.album = album.to_string();
+        t
+    }
+
+    #[test]
+    fn name_variants_share_an_artist_and_album() {
+        let mut artists = Vec::new();
+        add_to_database_helper(track("1", "The Beatles", "Abbey Road"), &mut artists);
+        add_to_database_helper(track("2", " the  beatles", "abbey  road "), &mut artists);
+        add_to_database_helper(track("3", "THE BEATLES\t", "ABBEY ROAD"), &mut artists);
+
+        assert_eq!(artists.len(), 1);
+        assert_eq!(artists[0].title, "The Beatles");
+        assert_eq!(artists[0].albums.len(), 1);
+        assert_eq!(artists[0].albums[0].title, "Abbey Road");
+        assert_eq!(artists[0].albums[0].tracks.len(), 3);
+    }
+
+    #[test]
+    fn unaccented_queries_find_accented_names() {
+        let mut artists = Vec::new();
+        let mut song = track("1", "Björk", "Homogenic");
+        song.title = "Jóga".to_string();
+        song.artists = vec!["Björk".to_string()];
+        add_to_database_helper(song, &mut artists);
+        add_to_database_helper(track("2", "Blur", "Parklife"), &mut artists);
+
+        let engines = SearchIndex::new(&artists).engines();
+        match engines.artists.search("bjork") {
+            SearchResult::Artists(found) => assert_eq!(found, vec![0]),
+            _ => panic!("expected artists"),
+        }
+        match engines.tracks.search("joga") {
+            SearchResult::Tracks(found) => assert_eq!(found, vec![(0, 0, 0)]),
+            _ => panic!("expected tracks"),
+        }
+
+        // The highlighted part is the accented name as it is shown
+        assert_eq!(match_spans("Björk", "bjork"), vec![(0, "Björk".len())]);
+    }
+
+    #[cfg(not(feature = "sqlite"))]
+    #[test]
+    fn library_without_the_version_is_outdated() {
+        let mut config = Config::default();
+        config.database_path = temp_file("library.db");
+        let mut artists = Vec::new();
+        add_to_database_helper(track("1", "Blur", "Parklife"), &mut artists);
+
+        write_atomically(&config.database_path, &artists).unwrap();
+        assert!(database_outdated(&config));
+        assert!(read_library(&config).is_err());
+
+        write_library(&config, &artists).unwrap();
+        assert!(!database_outdated(&config));
+        assert_eq!(read_library(&config).unwrap()[0].title, "Blur");
+
+        fs::remove_file(&config.database_path).ok();
+    }
+
+    // A config with everything under a fresh folder in the temp folder,
+    // and a music folder there holding the given silent WAV files
+    fn music_config(name: &str, files: &[&str]) -> Config {
+        let root = std::env::temp_dir().join(format!("sonik-{}-{}", std::process::id(), name));
+        let music = root.join("music");
+        fs::create_dir_all(&music).unwrap();
+        for file in files {
+            fs::write(
+                music.join(file),
+                crate::storage::record::tests::silent_wav(),
+            )
+            .unwrap();
+        }
+
+        let path = |p: &str| root.join(p).to_string_lossy().to_string();
+        let mut config = Config::default();
+        config.music_folder = path("music");
+        config.data_folder = path("data");
+        config.database_path = path("library.db");
+        config.stats_path = path("stats.db");
+        config.cache_folder = path("cache");
+        config
+    }
+
+    #[test]
+    fn rescan_counts_tracks_whose_files_are_gone() {
+        let config = music_config("pruned", &["a.wav", "b.wav"]);
+        let (artists, _, pruned) = create_and_load_database(&config, None).unwrap();
+        assert_eq!(pruned, 0);
+        assert_eq!(Stats::from_library(&artists).tracks, 2);
+
+        fs::remove_file(Path::new(&config.music_folder).join("b.wav")).unwrap();
+        let (artists, _, pruned) = rebuild_database(&config, None).unwrap();
+        assert_eq!(pruned, 1);
+        assert_eq!(Stats::from_library(&artists).tracks, 1);
+
+        fs::remove_dir_all(Path::new(&config.music_folder).parent().unwrap()).ok();
+    }
+
+    #[test]
+    fn rescan_keeps_what_was_kept_about_tracks() {
+        let config = music_config("kept", &["a.wav"]);
+        let (mut artists, _, _) = create_and_load_database(&config, None).unwrap();
+        let track = &mut artists[0].albums[0].tracks[0];
+        track.play_count = 7;
+        track.favorite = true;
+        track.bookmarks = vec![("Chorus".to_string(), Duration::from_millis(400))];
+        save_database(&config, &artists).unwrap();
+
+        let (artists, _, _) = rebuild_database(&config, None).unwrap();
+        let track = &artists[0].albums[0].tracks[0];
+        assert_eq!(track.play_count, 7);
+        assert!(track.favorite);
+        assert_eq!(track.bookmarks[0].0, "Chorus");
+
+        fs::remove_dir_all(Path::new(&config.music_folder).parent().unwrap()).ok();
+    }
+
+    #[test]
+    fn failed_write_keeps_the_old_file() {
+        let path = temp_file("atomic.db");
+        write_atomically(&path, &vec![7u32, 8, 9]).unwrap();
+
+        assert!(write_atomically(&path, &Interrupted).is_err());
+
+        let reader = BufReader::new(fs::File::open(&path).unwrap());
+        let kept: Vec<u32> = deserialize_from(reader).unwrap();
+        assert_eq!(kept, vec![7, 8, 9]);
+
+        fs::remove_file(&path).ok();
+        fs::remove_file(format!("{}.tmp", path)).ok();
     }
 }
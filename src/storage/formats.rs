@@ -0,0 +1,53 @@
+use std::path::Path;
+
+use log::warn;
+
+// File extensions scanning takes for music
+pub const SCAN_EXTENSIONS: [&str; 4] = ["mp3", "flac", "ogg", "wav"];
+
+// Which extensions this build has a decoder for. Each format is a cargo
+// feature of its own, passed on to rodio, so a build can leave some out and
+// still find those files when scanning
+const DECODERS: [(&str, bool); 4] = [
+    ("mp3", cfg!(feature = "mp3")),
+    ("flac", cfg!(feature = "flac")),
+    ("ogg", cfg!(feature = "vorbis")),
+    ("wav", cfg!(feature = "wav")),
+];
+
+pub fn has_decoder(extension: &str) -> bool {
+    DECODERS.iter().any(|&(e, built)| e == extension && built)
+}
+
+// A file's extension in lower case, empty if it has none
+pub fn extension(path: &Path) -> String {
+    path.extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+// The extension of a file this build has no decoder for, to explain why it
+// won't play
+pub fn missing_decoder(path: &Path) -> Option<String> {
+    let extension = extension(path);
+    if has_decoder(&extension) {
+        None
+    } else {
+        Some(extension)
+    }
+}
+
+// Scanned formats that won't play in this build, each warned about at
+// startup so "scans fine, won't play" doesn't come as a surprise
+pub fn check_decoders() -> Vec<&'static str> {
+    let missing: Vec<&str> = SCAN_EXTENSIONS
+        .iter()
+        .copied()
+        .filter(|e| !has_decoder(e))
+        .collect();
+    for extension in &missing {
+        warn!("No decoder for .{} in this build", extension);
+    }
+
+    missing
+}
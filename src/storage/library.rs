@@ -0,0 +1,169 @@
+use std::path::{Path, PathBuf};
+
+use log::warn;
+
+use crate::application::config::{Config, ScanOnStartup};
+use crate::storage::database::{
+    create_and_load_database, database_exists, database_outdated, export_json, load_database,
+    paths_missing, prune_database, rebuild_database, remap_database, save_database, ScanControl,
+};
+use crate::storage::record::{Album, Artist, Stats, Track};
+
+// The whole music library, artists sorted by name with their albums and
+// tracks, along with queries over it that don't need the interface
+#[derive(Clone, Debug, Default)]
+pub struct Library {
+    pub artists: Vec<Artist>,
+}
+
+impl Library {
+    pub fn new(artists: Vec<Artist>) -> Library {
+        Library { artists }
+    }
+
+    // Read the library from the database, scanning the music folder first
+    // if a rebuild is asked for or scan_on_startup calls for it. Otherwise
+    // tracks deleted since the last scan are dropped. Either way the number
+    // of tracks dropped for having gone missing is returned. Without a database or a scan the library is empty
+    pub fn load(config: &Config, rebuild: bool) -> Result<(Library, Stats, u32), ()> {
+        Library::load_with_progress(config, rebuild, None)
+    }
+
+    // Like load, with a scan reporting its progress and stopping when told
+    // to, see database::create_and_load_database
+    pub fn load_with_progress(
+        config: &Config,
+        rebuild: bool,
+        scan: Option<&ScanControl>,
+    ) -> Result<(Library, Stats, u32), ()> {
+        if !database_exists(config) {
+            if config.scan_on_startup == ScanOnStartup::Never && !rebuild {
+                return Ok((Library::default(), Stats::from_library(&[]), 0));
+            }
+            let (artists, stats, _) = create_and_load_database(config, scan)?;
+            return Ok((Library::new(artists), stats, 0));
+        }
+
+        if Library::needs_scan(config, rebuild) {
+            let (artists, stats, pruned) = rebuild_database(config, scan)?;
+            return Ok((Library::new(artists), stats, pruned));
+        }
+
+        // A library that can't be read is scanned again rather than lost
+        Library::load_saved(config).or_else(|_| {
+            warn!("Could not read the library, scanning it again");
+            let (artists, stats, pruned) = rebuild_database(config, scan)?;
+            Ok((Library::new(artists), stats, pruned))
+        })
+    }
+
+    // The library as it was last saved, without scanning, less any tracks
+    // deleted since
+    pub fn load_saved(config: &Config) -> Result<(Library, Stats, u32), ()> {
+        let (mut artists, mut stats) = load_database(config)?;
+        let pruned = prune_database(config, &mut artists, &mut stats)?;
+
+        Ok((Library::new(artists), stats, pruned))
+    }
+
+    // Whether loading means scanning the music folder, which can take a
+    // while on a big library. A library saved by a version of sonik that
+    // laid it out differently is always scanned again
+    pub fn needs_scan(config: &Config, rebuild: bool) -> bool {
+        rebuild
+            || database_outdated(config)
+            || match config.scan_on_startup {
+                ScanOnStartup::Always => true,
+                ScanOnStartup::IfMissing => !database_exists(config),
+                ScanOnStartup::Never => false,
+            }
+    }
+
+    pub fn save(&self, config: &Config) -> Result<(), ()> {
+        save_database(config, &self.artists)
+    }
+
+    // Write everything out as pretty-printed JSON for other tools to read.
+    // The database itself stays as it is
+    pub fn export_json(&self, path: &Path) -> Result<(), ()> {
+        export_json(&self.artists, path)
+    }
+
+    pub fn track_count(&self) -> usize {
+        self.all_tracks().count()
+    }
+
+    pub fn all_albums(&self) -> impl Iterator<Item = &Album> {
+        albums(&self.artists)
+    }
+
+    pub fn all_tracks(&self) -> impl Iterator<Item = &Track> {
+        tracks(&self.artists)
+    }
+
+    pub fn all_tracks_mut(&mut self) -> impl Iterator<Item = &mut Track> {
+        tracks_mut(&mut self.artists)
+    }
+
+    pub fn find_by_path(&self, path: &Path) -> Option<&Track> {
+        self.all_tracks().find(|t| Path::new(&t.file_path) == path)
+    }
+
+    pub fn find_by_path_mut(&mut self, path: &Path) -> Option<&mut Track> {
+        self.all_tracks_mut()
+            .find(|t| Path::new(&t.file_path) == path)
+    }
+
+    // The deepest folder all the tracks are in, usually the music folder
+    // the library was scanned from
+    pub fn root(&self) -> Option<PathBuf> {
+        let mut tracks = self.all_tracks();
+        let mut root = Path::new(&tracks.next()?.file_path).parent()?.to_path_buf();
+        for t in tracks {
+            while !Path::new(&t.file_path).starts_with(&root) {
+                if !root.pop() {
+                    return None;
+                }
+            }
+        }
+
+        Some(root)
+    }
+
+    // Whether the music folder looks to have been moved from under the
+    // library: it is there, but none of the tracks checked are
+    pub fn looks_moved(&self, config: &Config) -> bool {
+        Path::new(&config.music_folder).is_dir() && paths_missing(&self.artists)
+    }
+
+    // Point the tracks under old_root at new_root instead and save the
+    // library, returning how many were changed
+    pub fn remap_root(
+        &mut self,
+        config: &Config,
+        old_root: &Path,
+        new_root: &Path,
+    ) -> Result<u32, ()> {
+        remap_database(config, &mut self.artists, old_root, new_root)
+    }
+}
+
+// Every album by a list of artists, artist by artist. These save writing out
+// the artist, album and track loops wherever the library is gone through
+pub fn albums(artists: &[Artist]) -> impl Iterator<Item = &Album> {
+    artists.iter().flat_map(|a| a.albums.iter())
+}
+
+// Every track by a list of artists, artist by artist and album by album
+pub fn tracks(artists: &[Artist]) -> impl Iterator<Item = &Track> {
+    albums(artists).flat_map(|al| al.tracks.iter())
+}
+
+// The artists' own copies of every track, for changes across the whole
+// library such as resetting play counts
+pub fn tracks_mut(artists: &mut [Artist]) -> impl Iterator<Item = &mut Track> {
+    artists
+        .iter_mut()
+        .flat_map(|a| a.albums.iter_mut())
+        .flat_map(|al| al.tracks.iter_mut())
+}
@@ -0,0 +1,99 @@
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::storage::record::Track;
+
+// A track's lyrics, either lines to scroll through by hand or lines timed
+// to the music, as in an .lrc file
+pub enum Lyrics {
+    Plain(Vec<String>),
+    Synced(Vec<(Duration, String)>),
+}
+
+impl Lyrics {
+    // Turn lyrics text into lines, timed if it has [mm:ss.xx] tags
+    pub fn parse(text: &str) -> Lyrics {
+        let mut timed = Vec::new();
+        for line in text.lines() {
+            let (times, words) = split_timestamps(line);
+            for time in times {
+                timed.push((time, words.to_string()));
+            }
+        }
+
+        if timed.is_empty() {
+            Lyrics::Plain(text.lines().map(str::to_string).collect())
+        } else {
+            timed.sort_by_key(|(time, _)| *time);
+            Lyrics::Synced(timed)
+        }
+    }
+
+    pub fn lines(&self) -> Vec<&str> {
+        match self {
+            Lyrics::Plain(lines) => lines.iter().map(String::as_str).collect(),
+            Lyrics::Synced(lines) => lines.iter().map(|(_, l)| l.as_str()).collect(),
+        }
+    }
+
+    // The line being sung at a position into the track, for timed lyrics
+    pub fn current_line(&self, position: Duration) -> Option<usize> {
+        match self {
+            Lyrics::Plain(_) => None,
+            Lyrics::Synced(lines) => lines
+                .iter()
+                .rposition(|(time, _)| *time <= position)
+                .or(Some(0)),
+        }
+    }
+}
+
+// Lyrics for a track, from a file next to it with the same name ending in
+// .lrc or .txt, or else from its tag. The files are read each time, so
+// they can be added or fixed without a rescan
+pub fn load(track: &Track) -> Option<Lyrics> {
+    let path = Path::new(&track.file_path);
+    for extension in ["lrc", "txt"].iter() {
+        if let Ok(text) = fs::read_to_string(path.with_extension(extension)) {
+            if !text.trim().is_empty() {
+                return Some(Lyrics::parse(&text));
+            }
+        }
+    }
+
+    track.lyrics.as_ref().map(|text| Lyrics::parse(text))
+}
+
+// Take the [mm:ss.xx] tags off the front of an .lrc line. Other tags, like
+// [ar:Artist], make the line count as untimed
+fn split_timestamps(line: &str) -> (Vec<Duration>, &str) {
+    let mut times = Vec::new();
+    let mut rest = line.trim_start();
+    while rest.starts_with('[') {
+        let end = match rest.find(']') {
+            Some(end) => end,
+            None => break,
+        };
+        match parse_timestamp(&rest[1..end]) {
+            Some(time) => times.push(time),
+            None => return (Vec::new(), line),
+        }
+        rest = &rest[end + 1..];
+    }
+
+    (times, rest.trim())
+}
+
+fn parse_timestamp(tag: &str) -> Option<Duration> {
+    let mut parts = tag.splitn(2, ':');
+    let minutes: u64 = parts.next()?.parse().ok()?;
+    let seconds: f64 = parts.next()?.parse().ok()?;
+    if seconds < 0.0 {
+        return None;
+    }
+
+    Some(Duration::from_millis(
+        minutes * 60_000 + (seconds * 1000.0) as u64,
+    ))
+}
@@ -1,5 +1,13 @@
+pub mod art;
 pub mod database;
+pub mod formats;
+pub mod library;
+pub mod lyrics;
+pub mod playlist;
 pub mod record;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod stream;
 pub mod terms;
 
 fn vec_compare<T: PartialEq>(va: &[T], vb: &[T]) -> bool {
@@ -0,0 +1,108 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use ignore::Walk;
+use log::warn;
+
+use crate::storage::database::is_music;
+use crate::storage::library::{self, Library};
+use crate::storage::record::{Artist, Track};
+
+// Reads an m3u/m3u8 playlist and resolves each of its entries to the
+// matching track in the library, keeping the playlist's order
+pub fn load_playlist(playlist_path: &Path, artists: &[Artist]) -> Result<Vec<Track>, ()> {
+    // Plain m3u files are not guaranteed to be UTF-8
+    let bytes = fs::read(playlist_path).map_err(|_| ())?;
+    let contents = String::from_utf8_lossy(&bytes);
+
+    // Relative entries are relative to the folder holding the playlist
+    let base = playlist_path.parent().unwrap_or_else(|| Path::new(""));
+
+    let library: HashMap<PathBuf, &Track> = library::tracks(artists)
+        .map(|t| (PathBuf::from(&t.file_path), t))
+        .collect();
+
+    let mut tracks = Vec::new();
+
+    for line in contents.lines() {
+        let entry = line.trim_start_matches('\u{feff}').trim();
+
+        // Skip blank lines as well as #EXTM3U/#EXTINF directives
+        if entry.is_empty() || entry.starts_with('#') {
+            continue;
+        }
+
+        // Joining an absolute path simply replaces the base
+        match library.get(&base.join(entry)) {
+            Some(t) => tracks.push((*t).clone()),
+            None => println!("Skipping playlist entry not in library: {}", entry),
+        }
+    }
+
+    Ok(tracks)
+}
+
+// Turn files and folders, e.g. given on the command line, into tracks in
+// the order given. Folders are searched for music, sorted by path. Tracks
+// in the library come from there so their play counts carry on, anything
+// else is read straight from the file
+pub fn open_paths(paths: &[&Path], library: &Library, separators: &[String]) -> Vec<Track> {
+    let mut tracks = Vec::new();
+
+    for path in paths {
+        for file in music_files(path) {
+            match library.find_by_path(&file) {
+                Some(t) => tracks.push(t.clone()),
+                None => match Track::new(file.clone(), separators) {
+                    Ok(t) => tracks.push(t),
+                    Err(_) => {
+                        warn!("Could not read {}", file.display());
+                        println!("Skipping unreadable file: {}", file.display())
+                    }
+                },
+            }
+        }
+    }
+
+    tracks
+}
+
+// A file as it is, or the music in a folder and everything under it, sorted
+// by path
+fn music_files(path: &Path) -> Vec<PathBuf> {
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+
+    let mut files: Vec<PathBuf> = if path.is_dir() {
+        Walk::new(&path)
+            .filter_map(|entry| entry.ok())
+            .filter(is_music)
+            .map(|entry| entry.into_path())
+            .collect()
+    } else {
+        vec![path]
+    };
+    files.sort();
+
+    files
+}
+
+// The music in a folder read straight from the files, sorted by path, for
+// listening to something outside the library without adding it there.
+// Files that can't be read are logged and counted
+pub fn read_folder(folder: &Path, separators: &[String]) -> (Vec<Track>, usize) {
+    let mut tracks = Vec::new();
+    let mut unreadable = 0;
+
+    for file in music_files(folder) {
+        match Track::new(file.clone(), separators) {
+            Ok(t) => tracks.push(t),
+            Err(_) => {
+                warn!("Could not read {}", file.display());
+                unreadable += 1;
+            }
+        }
+    }
+
+    (tracks, unreadable)
+}
@@ -2,22 +2,47 @@ use std::borrow::Borrow;
 use std::cmp::Ordering;
 use std::hash::{Hash, Hasher};
 use std::path::PathBuf;
+use std::time::Duration;
 
 use id3::Tag;
 use serde_derive::{Deserialize, Serialize};
 
+use crate::storage::stream;
 use crate::storage::vec_compare;
 
-#[derive(Clone, Eq, Serialize, Deserialize, Debug)]
+#[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct Track {
     pub file_path: String,
     pub title: String,
     pub artist: String,
+    // Everyone credited in the artist tag, see split_artists
+    pub artists: Vec<String>,
     pub album_artist: String,
     pub album: String,
     pub year: i32,
     pub track_num: u32,
     pub duration: u32,
+    pub play_count: u32,
+    pub favorite: bool,
+    // ReplayGain adjustments in dB, if the file was tagged with them
+    pub track_gain: Option<f32>,
+    pub album_gain: Option<f32>,
+    // Stream properties, 0 when they couldn't be worked out. Bitrate is the
+    // average in kbps
+    pub codec: String,
+    pub sample_rate: u32,
+    pub bitrate: u32,
+    // When the track first made it into the library, in seconds since the
+    // Unix epoch. Filled in while scanning
+    pub date_added: u64,
+    // Where playback starts, in milliseconds, to skip a silent intro
+    pub start_offset: u64,
+    // Stars given to the track, 0 to 5 with 0 meaning unrated
+    pub rating: u8,
+    // Lyrics from the tag, see storage::lyrics for files next to the track
+    pub lyrics: Option<String>,
+    // Named places to jump back to in long tracks, in order of position
+    pub bookmarks: Vec<(String, Duration)>,
 }
 
 #[derive(Clone, Eq, Serialize, Deserialize, Debug)]
@@ -26,6 +51,10 @@ pub struct Album {
     pub artist: String,
     pub year: i32,
     pub tracks: Vec<Track>,
+    // Cover art found while scanning, see storage::art
+    pub cover_path: Option<PathBuf>,
+    // Date added of the newest track on the album
+    pub date_added: u64,
 }
 
 #[derive(Clone, Eq, Serialize, Deserialize, Debug)]
@@ -39,11 +68,33 @@ pub struct Stats {
     pub artists: u32,
     pub albums: u32,
     pub tracks: u32,
-    pub total_time: u32,
+    // Milliseconds, which would run out past 49 days as a u32
+    pub total_time: u64,
+}
+
+pub const MAX_RATING: u8 = 5;
+
+// Names given to tracks whose tags leave them out
+const UNKNOWN_ARTIST: &str = "Unknown Artist";
+pub const UNKNOWN_ALBUM: &str = "Unknown Album";
+
+// A length or position as 1:02:03, or 2:03 under an hour
+pub fn clock(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    } else {
+        format!("{}:{:02}", secs / 60, secs % 60)
+    }
 }
 
 pub trait Record {
     fn name(&self) -> &str;
+
+    // Extra information shown right-aligned next to the name in lists
+    fn detail(&self) -> Option<String> {
+        None
+    }
 }
 
 #[derive(Clone)]
@@ -66,39 +117,33 @@ impl Record for Media {
 impl Track {
     // Should probably implement a Default for this
 
-    pub fn new(path: PathBuf) -> Result<Track, ()> {
-        // Some paths aren't UTF-8 compliant
-        // For now, we will ignore these tracks
-        let tag = Tag::read_from_path(&path);
-        if tag.is_err() {
-            return Err(());
-        }
+    pub fn new(path: PathBuf, separators: &[String]) -> Result<Track, ()> {
+        // Files without a readable tag are still playable, so they are
+        // kept and given the fallbacks below rather than being dropped
+        let safe_tag = Tag::read_from_path(&path).unwrap_or_else(|_| Tag::new());
 
-        // Anything that gets to this stage has a safe path
-        let safe_tag = Tag::read_from_path(&path).unwrap();
+        // Untitled tracks go by their file name
+        let title = match tag_text(safe_tag.title()) {
+            Some(s) => s,
+            None => path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or_default(),
+        };
 
-        let mut title: String = "".to_string();
-        if let Some(s) = safe_tag.title() {
-            title = s.to_string();
-        }
+        let artist = tag_text(safe_tag.artist()).unwrap_or_else(|| UNKNOWN_ARTIST.to_string());
+        let artists = split_artists(&artist, separators);
 
-        let mut artist: String = "".to_string();
-        if let Some(s) = safe_tag.artist() {
-            artist = s.to_string();
-        }
-
-        let mut album: String = "".to_string();
-        if let Some(s) = safe_tag.album() {
-            album = s.to_string();
-        }
+        let album = tag_text(safe_tag.album()).unwrap_or_else(|| UNKNOWN_ALBUM.to_string());
 
         let album_artist;
-        match safe_tag.album_artist() {
+        match tag_text(safe_tag.album_artist()) {
             Some(s) => {
-                album_artist = s.to_string();
+                album_artist = s;
             }
+            // Albums are grouped under the first credited artist
             None => {
-                album_artist = artist.clone();
+                album_artist = artists[0].clone();
             }
         }
 
@@ -112,20 +157,43 @@ impl Track {
             track_num = x;
         }
 
-        let mut duration: u32 = 0;
-        if let Some(x) = safe_tag.duration() {
-            duration = x;
-        }
+        let stream = stream::read(&path);
+        let duration = stream.duration(safe_tag.duration());
+
+        let track_gain = replaygain(&safe_tag, "REPLAYGAIN_TRACK_GAIN");
+        let album_gain = replaygain(&safe_tag, "REPLAYGAIN_ALBUM_GAIN");
+
+        let lyrics = safe_tag
+            .lyrics()
+            .map(|l| clean_text(&l.text))
+            .find(|l| !l.trim().is_empty());
+
+        let codec = stream.codec.to_string();
+        let sample_rate = stream.sample_rate;
+        let bitrate = stream.bitrate(duration);
 
         Ok(Track {
             file_path: path.as_path().to_string_lossy().to_string(),
             title,
             artist,
+            artists,
             album_artist,
             album,
             year,
             track_num,
             duration,
+            play_count: 0,
+            favorite: false,
+            track_gain,
+            album_gain,
+            codec,
+            sample_rate,
+            bitrate,
+            date_added: 0,
+            start_offset: 0,
+            rating: 0,
+            lyrics,
+            bookmarks: Vec::new(),
         })
     }
 
@@ -135,13 +203,125 @@ impl Track {
             file_path: "".to_string(),
             title: "".to_string(),
             artist: "".to_string(),
+            artists: Vec::new(),
             album_artist: "".to_string(),
             album: "".to_string(),
             year: 0,
             track_num: 0,
             duration: 0,
+            play_count: 0,
+            favorite: false,
+            track_gain: None,
+            album_gain: None,
+            codec: "".to_string(),
+            sample_rate: 0,
+            bitrate: 0,
+            date_added: 0,
+            start_offset: 0,
+            rating: 0,
+            lyrics: None,
+            bookmarks: Vec::new(),
         }
     }
+
+    // Where to really start when asked to start at a position, which is
+    // never before the track's start offset
+    pub fn start_at(&self, position: Duration) -> Duration {
+        position.max(Duration::from_millis(self.start_offset))
+    }
+
+    pub fn toggle_favorite(&mut self) {
+        self.favorite = !self.favorite;
+    }
+
+    // Move the rating up or down by some stars, staying within 0 to 5
+    pub fn rate(&mut self, change: i8) {
+        self.rating = (self.rating as i8 + change).max(0).min(MAX_RATING as i8) as u8;
+    }
+
+    // The rating as that many stars, empty when unrated
+    pub fn stars(&self) -> String {
+        "★".repeat(self.rating as usize)
+    }
+}
+
+// Break an artist tag like "A; B" into the artists it credits. There is
+// always at least one, the whole tag if nothing is left after splitting
+fn split_artists(artist: &str, separators: &[String]) -> Vec<String> {
+    let mut artists = vec![artist.to_string()];
+    for separator in separators.iter().filter(|s| !s.is_empty()) {
+        artists = artists
+            .iter()
+            .flat_map(|a| a.split(separator.as_str()))
+            .map(|a| a.trim().to_string())
+            .filter(|a| !a.is_empty())
+            .collect();
+    }
+
+    if artists.is_empty() {
+        artists.push(artist.to_string());
+    }
+
+    artists
+}
+
+// A tag's text, where blank counts the same as missing
+fn tag_text(text: Option<&str>) -> Option<String> {
+    match text.map(clean_text) {
+        Some(s) if !s.trim().is_empty() => Some(s),
+        _ => None,
+    }
+}
+
+// Tags written by older software often say they are Latin-1 when the bytes
+// are really UTF-8 or UTF-16, which comes out as "BjÃ¶rk" or "ÿþB\0j\0...".
+// Those are decoded again from the original bytes, then BOMs and NULs left
+// over are dropped. NULs between words separate values in ID3v2.4, so they
+// become " / " rather than running the values together
+fn clean_text(text: &str) -> String {
+    let text = redecode(text).unwrap_or_else(|| text.to_string());
+
+    text.replace(['\u{feff}', '\u{fffe}'], "")
+        .split('\0')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>()
+        .join(" / ")
+}
+
+// The text read back as the bytes it was decoded from, if it could only
+// have come from Latin-1 and those bytes make sense as something else
+fn redecode(text: &str) -> Option<String> {
+    if text.is_ascii() || text.chars().any(|c| c as u32 > 0xff) {
+        return None;
+    }
+    let bytes: Vec<u8> = text.chars().map(|c| c as u8).collect();
+
+    match bytes.get(..2) {
+        Some([0xff, 0xfe]) => utf16(&bytes[2..], u16::from_le_bytes),
+        Some([0xfe, 0xff]) => utf16(&bytes[2..], u16::from_be_bytes),
+        _ => String::from_utf8(bytes).ok(),
+    }
+}
+
+fn utf16(bytes: &[u8], unit: fn([u8; 2]) -> u16) -> Option<String> {
+    let units: Vec<u16> = bytes.chunks_exact(2).map(|b| unit([b[0], b[1]])).collect();
+    String::from_utf16(&units).ok()
+}
+
+// ReplayGain values live in TXXX frames and look like "-6.54 dB"
+fn replaygain(tag: &Tag, description: &str) -> Option<f32> {
+    tag.extended_texts()
+        .find(|t| t.description.eq_ignore_ascii_case(description))
+        .and_then(|t| {
+            t.value
+                .to_lowercase()
+                .trim()
+                .trim_end_matches("db")
+                .trim()
+                .parse()
+                .ok()
+        })
 }
 
 impl PartialOrd for Track {
@@ -162,10 +342,30 @@ impl PartialEq for Track {
     }
 }
 
+// Equality only looks at the path, so the gain floats don't get in the way
+impl Eq for Track {}
+
 impl Record for Track {
     fn name(&self) -> &str {
         &self.title[..]
     }
+
+    // Rating and duration, each left out when there isn't one
+    fn detail(&self) -> Option<String> {
+        let mut detail = self.stars();
+        if self.duration > 0 {
+            if !detail.is_empty() {
+                detail.push(' ');
+            }
+            detail.push_str(&clock(Duration::from_millis(u64::from(self.duration))));
+        }
+
+        if detail.is_empty() {
+            None
+        } else {
+            Some(detail)
+        }
+    }
 }
 
 impl Album {
@@ -177,10 +377,18 @@ impl Album {
             artist: artist_name,
             year: release_year,
             tracks: tracklist,
+            cover_path: None,
+            date_added: 0,
         })
     }
 
+    // How long the album plays for, going by its tracks' tags
+    pub fn total_duration(&self) -> Duration {
+        Duration::from_millis(self.tracks.iter().map(|t| u64::from(t.duration)).sum())
+    }
+
     pub fn update_album(&mut self, t: Track) -> Result<(), ()> {
+        self.date_added = self.date_added.max(t.date_added);
         self.tracks.push(t);
 
         self.tracks.sort_by(|a, b| a.track_num.cmp(&b.track_num));
@@ -227,6 +435,24 @@ impl Record for Album {
     fn name(&self) -> &str {
         &self.title[..]
     }
+
+    // Year and length, each left out when there isn't one
+    fn detail(&self) -> Option<String> {
+        let mut detail = Vec::new();
+        if self.year > 0 {
+            detail.push(self.year.to_string());
+        }
+        let duration = self.total_duration();
+        if duration > Duration::from_secs(0) {
+            detail.push(clock(duration));
+        }
+
+        if detail.is_empty() {
+            None
+        } else {
+            Some(detail.join(" "))
+        }
+    }
 }
 
 impl Artist {
@@ -239,6 +465,10 @@ impl Artist {
         })
     }
 
+    pub fn total_duration(&self) -> Duration {
+        self.albums.iter().map(Album::total_duration).sum()
+    }
+
     pub fn add_album(&mut self, album: Album) -> Result<(), ()> {
         self.albums.push(album);
 
@@ -271,6 +501,20 @@ impl Record for Artist {
     fn name(&self) -> &str {
         &self.title[..]
     }
+
+    // Number of albums and how long they play for altogether
+    fn detail(&self) -> Option<String> {
+        let albums = match self.albums.len() {
+            1 => "1 album".to_string(),
+            n => format!("{} albums", n),
+        };
+        let duration = self.total_duration();
+        if duration > Duration::from_secs(0) {
+            Some(format!("{}, {}", albums, clock(duration)))
+        } else {
+            Some(albums)
+        }
+    }
 }
 
 impl Stats {
@@ -282,4 +526,137 @@ impl Stats {
             total_time: 0,
         })
     }
+
+    // Tally everything in an already built library
+    pub fn from_library(artists: &[Artist]) -> Stats {
+        let mut stats = Stats::new().unwrap();
+
+        for artist in artists {
+            stats.artists += 1;
+            for album in &artist.albums {
+                stats.albums += 1;
+                for track in &album.tracks {
+                    stats.tracks += 1;
+                    stats.total_time += u64::from(track.duration);
+                }
+            }
+        }
+
+        stats
+    }
+
+    // How long the whole library plays for, as D:HH:MM:SS
+    pub fn playtime(&self) -> String {
+        let secs = self.total_time / 1000;
+        format!(
+            "{}:{:02}:{:02}:{:02}",
+            secs / 86_400,
+            secs / 3600 % 24,
+            secs / 60 % 60,
+            secs % 60
+        )
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    use std::fs;
+    use std::path::Path;
+
+    // A second of silence as 16-bit mono WAV, with no tag of any kind
+    pub(crate) fn silent_wav() -> Vec<u8> {
+        let rate: u32 = 8000;
+        let data_len = rate * 2;
+        let mut wav = Vec::new();
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&rate.to_le_bytes());
+        wav.extend_from_slice(&(rate * 2).to_le_bytes());
+        wav.extend_from_slice(&2u16.to_le_bytes());
+        wav.extend_from_slice(&16u16.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&data_len.to_le_bytes());
+        wav.resize(wav.len() + data_len as usize, 0);
+        wav
+    }
+
+    // A few silent MPEG-1 layer III frames, 128 kbit/s at 44.1 kHz, with no
+    // ID3 tag in front
+    fn silent_mp3() -> Vec<u8> {
+        let mut frame = vec![0xff, 0xfb, 0x90, 0x00];
+        frame.resize(417, 0);
+        frame.repeat(20)
+    }
+
+    fn untagged_track(name: &str, contents: &[u8]) -> Track {
+        let dir = std::env::temp_dir().join(format!("sonik-record-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        fs::write(&path, contents).unwrap();
+
+        let track = Track::new(path.clone(), &["/".to_string()]).unwrap();
+        fs::remove_file(&path).ok();
+        track
+    }
+
+    #[test]
+    fn tag_text_is_cleaned_up() {
+        // UTF-8 read as Latin-1
+        assert_eq!(clean_text("BjÃ¶rk"), "Björk");
+
+        // UTF-16 with a byte order mark either way round, read as Latin-1
+        let le = "\u{ff}\u{fe}B\0j\0\u{f6}\0r\0k\0";
+        let be = "\u{fe}\u{ff}\0B\0j\0\u{f6}\0r\0k";
+        assert_eq!(clean_text(le), "Björk");
+        assert_eq!(clean_text(be), "Björk");
+
+        // Several values in one frame, and stray marks and padding
+        assert_eq!(clean_text("Blur\0Gorillaz\0"), "Blur / Gorillaz");
+        assert_eq!(clean_text("\u{feff}Damon Albarn \0 "), "Damon Albarn");
+
+        // Real Latin-1 isn't valid as anything else and is left alone
+        assert_eq!(clean_text("Björk"), "Björk");
+        assert_eq!(clean_text("Sigur Rós"), "Sigur Rós");
+    }
+
+    #[test]
+    fn untagged_files_fall_back_on_their_name() {
+        let files: [(&str, Vec<u8>); 2] = [
+            ("01 Opening.wav", silent_wav()),
+            ("02 Closing.mp3", silent_mp3()),
+        ];
+        for (name, contents) in files.iter() {
+            let track = untagged_track(name, contents);
+            let stem = Path::new(name).file_stem().unwrap().to_str().unwrap();
+
+            assert_eq!(track.title, stem);
+            assert_eq!(track.artist, UNKNOWN_ARTIST);
+            assert_eq!(track.artists, vec![UNKNOWN_ARTIST.to_string()]);
+            assert_eq!(track.album_artist, UNKNOWN_ARTIST);
+            assert_eq!(track.album, UNKNOWN_ALBUM);
+            assert_eq!(track.track_num, 0);
+        }
+    }
+
+    #[test]
+    fn stream_properties_come_from_the_audio() {
+        let wav = untagged_track("tone.wav", &silent_wav());
+        assert_eq!(wav.codec, "WAV");
+        assert_eq!(wav.sample_rate, 8000);
+        assert_eq!(wav.duration, 1000);
+        assert_eq!(wav.bitrate, 128);
+
+        // Without a length in the tag, an MP3 goes by its first frame
+        let mp3 = untagged_track("tone.mp3", &silent_mp3());
+        assert_eq!(mp3.codec, "MP3");
+        assert_eq!(mp3.sample_rate, 44100);
+        assert_eq!(mp3.duration, 20 * 417 * 8 / 128);
+        assert_eq!(mp3.bitrate, 128);
+    }
 }
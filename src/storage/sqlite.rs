@@ -0,0 +1,427 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use rusqlite::{params, Connection, Row, NO_PARAMS};
+
+use crate::application::config::Config;
+use crate::storage::record::{Album, Artist, Track};
+
+// The library kept in SQLite instead of one bincode blob, for builds with
+// the sqlite feature. Every track is a row of its own, so saving play counts
+// only touches the tracks that changed, and single tracks can be looked up
+// by path or artist without reading everything in
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS artists (
+        id INTEGER PRIMARY KEY,
+        title TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS albums (
+        id INTEGER PRIMARY KEY,
+        artist_id INTEGER NOT NULL REFERENCES artists(id),
+        title TEXT NOT NULL,
+        artist TEXT NOT NULL,
+        year INTEGER NOT NULL,
+        cover_path TEXT,
+        date_added INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS tracks (
+        id INTEGER PRIMARY KEY,
+        album_id INTEGER NOT NULL REFERENCES albums(id),
+        file_path TEXT NOT NULL UNIQUE,
+        title TEXT NOT NULL,
+        artist TEXT NOT NULL,
+        artists TEXT NOT NULL,
+        album_artist TEXT NOT NULL,
+        album TEXT NOT NULL,
+        year INTEGER NOT NULL,
+        track_num INTEGER NOT NULL,
+        duration INTEGER NOT NULL,
+        play_count INTEGER NOT NULL,
+        favorite INTEGER NOT NULL,
+        track_gain REAL,
+        album_gain REAL,
+        codec TEXT NOT NULL,
+        sample_rate INTEGER NOT NULL,
+        bitrate INTEGER NOT NULL,
+        date_added INTEGER NOT NULL,
+        start_offset INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS albums_by_artist ON albums(artist_id);
+    CREATE INDEX IF NOT EXISTS tracks_by_album ON tracks(album_id);
+    CREATE INDEX IF NOT EXISTS tracks_by_artist ON tracks(artist);
+";
+
+// The tables as they were first made are version 1. Each later version adds
+// a column to tracks, in this order, so a database is brought up to date by
+// adding whatever it is missing. Databases from before the version was kept
+// are at 0, with any number of these
+const SCHEMA_VERSION: u32 = 4;
+const ADDED_COLUMNS: [(&str, &str); 3] = [
+    ("rating", "INTEGER NOT NULL DEFAULT 0"),
+    ("lyrics", "TEXT"),
+    ("bookmarks", "TEXT NOT NULL DEFAULT '[]'"),
+];
+
+const TRACK_COLUMNS: &str = "file_path, title, artist, artists, album_artist, album, year, \
+                             track_num, duration, play_count, favorite, track_gain, album_gain, \
+                             codec, sample_rate, bitrate, date_added, start_offset, rating, \
+                             lyrics, bookmarks";
+
+// Names in a track's artists column are joined with the unit separator,
+// which doesn't turn up in tags
+const ARTIST_SEPARATOR: char = '\u{1f}';
+
+// The database lives next to where the bincode one would, so switching
+// between builds doesn't mix the two up
+pub fn database_path(config: &Config) -> PathBuf {
+    Path::new(&config.database_path).with_extension("sqlite")
+}
+
+fn open(config: &Config) -> Result<Connection, ()> {
+    let mut conn = Connection::open(database_path(config)).map_err(|_| ())?;
+    conn.execute_batch(SCHEMA).map_err(|_| ())?;
+    migrate(&mut conn).map_err(|_| ())?;
+
+    Ok(conn)
+}
+
+// Add the columns from later versions to an older database. One from a newer
+// sonik is left as it is, since everything read here is named
+fn migrate(conn: &mut Connection) -> rusqlite::Result<()> {
+    let version: u32 = conn.query_row("PRAGMA user_version", NO_PARAMS, |row| row.get(0))?;
+    if version >= SCHEMA_VERSION {
+        return Ok(());
+    }
+
+    let tx = conn.transaction()?;
+    let columns = {
+        let mut stmt = tx.prepare("PRAGMA table_info(tracks)")?;
+        let rows = stmt.query_map(NO_PARAMS, |row| row.get::<_, String>(1))?;
+        rows.collect::<rusqlite::Result<Vec<String>>>()?
+    };
+    for (name, definition) in ADDED_COLUMNS.iter() {
+        if !columns.iter().any(|c| c == name) {
+            tx.execute_batch(&format!(
+                "ALTER TABLE tracks ADD COLUMN {} {}",
+                name, definition
+            ))?;
+        }
+    }
+    tx.execute_batch(&format!("PRAGMA user_version = {}", SCHEMA_VERSION))?;
+
+    tx.commit()
+}
+
+// Read the whole library back in the order it was written, which keeps the
+// sorting done while scanning
+pub fn read(config: &Config) -> Result<Vec<Artist>, ()> {
+    let conn = open(config)?;
+    read_library(&conn).map_err(|_| ())
+}
+
+fn read_library(conn: &Connection) -> rusqlite::Result<Vec<Artist>> {
+    let mut artists = Vec::new();
+    let mut artist_index = HashMap::new();
+    let mut stmt = conn.prepare("SELECT id, title FROM artists ORDER BY id")?;
+    let rows = stmt.query_map(NO_PARAMS, |row| {
+        Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+    })?;
+    for row in rows {
+        let (id, title) = row?;
+        artist_index.insert(id, artists.len());
+        artists.push(Artist {
+            title,
+            albums: Vec::new(),
+        });
+    }
+
+    let mut album_index = HashMap::new();
+    let mut stmt = conn.prepare(
+        "SELECT id, artist_id, title, artist, year, cover_path, date_added
+         FROM albums ORDER BY id",
+    )?;
+    let rows = stmt.query_map(NO_PARAMS, |row| {
+        let album = Album {
+            title: row.get(2)?,
+            artist: row.get(3)?,
+            year: row.get(4)?,
+            tracks: Vec::new(),
+            cover_path: row.get::<_, Option<String>>(5)?.map(PathBuf::from),
+            date_added: row.get::<_, i64>(6)? as u64,
+        };
+        Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?, album))
+    })?;
+    for row in rows {
+        let (id, artist_id, album) = row?;
+        if let Some(&a) = artist_index.get(&artist_id) {
+            album_index.insert(id, (a, artists[a].albums.len()));
+            artists[a].albums.push(album);
+        }
+    }
+
+    let mut stmt = conn.prepare(&format!(
+        "SELECT album_id, {} FROM tracks ORDER BY id",
+        TRACK_COLUMNS
+    ))?;
+    let rows = stmt.query_map(NO_PARAMS, |row| {
+        Ok((row.get::<_, i64>(0)?, track_from_row(row, 1)?))
+    })?;
+    for row in rows {
+        let (album_id, track) = row?;
+        if let Some(&(a, al)) = album_index.get(&album_id) {
+            artists[a].albums[al].tracks.push(track);
+        }
+    }
+
+    Ok(artists)
+}
+
+// Replace everything with a freshly scanned library
+pub fn write(config: &Config, artists: &[Artist]) -> Result<(), ()> {
+    let mut conn = open(config)?;
+    write_library(&mut conn, artists).map_err(|_| ())
+}
+
+fn write_library(conn: &mut Connection, artists: &[Artist]) -> rusqlite::Result<()> {
+    let tx = conn.transaction()?;
+    tx.execute_batch("DELETE FROM tracks; DELETE FROM albums; DELETE FROM artists;")?;
+
+    {
+        let mut insert_artist = tx.prepare("INSERT INTO artists (title) VALUES (?1)")?;
+        let mut insert_album = tx.prepare(
+            "INSERT INTO albums (artist_id, title, artist, year, cover_path, date_added)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        )?;
+        let mut insert_track = tx.prepare(&format!(
+            "INSERT INTO tracks (album_id, {})
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10,
+                     ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22)",
+            TRACK_COLUMNS
+        ))?;
+
+        for artist in artists {
+            insert_artist.execute(params![artist.title])?;
+            let artist_id = tx.last_insert_rowid();
+
+            for album in &artist.albums {
+                let cover_path = album
+                    .cover_path
+                    .as_ref()
+                    .map(|p| p.to_string_lossy().to_string());
+                insert_album.execute(params![
+                    artist_id,
+                    album.title,
+                    album.artist,
+                    album.year,
+                    cover_path,
+                    album.date_added as i64
+                ])?;
+                let album_id = tx.last_insert_rowid();
+
+                for t in &album.tracks {
+                    insert_track.execute(params![
+                        album_id,
+                        t.file_path,
+                        t.title,
+                        t.artist,
+                        join_artists(&t.artists),
+                        t.album_artist,
+                        t.album,
+                        t.year,
+                        t.track_num,
+                        t.duration,
+                        t.play_count,
+                        t.favorite,
+                        t.track_gain.map(f64::from),
+                        t.album_gain.map(f64::from),
+                        t.codec,
+                        t.sample_rate,
+                        t.bitrate,
+                        t.date_added as i64,
+                        t.start_offset as i64,
+                        t.rating,
+                        t.lyrics,
+                        join_bookmarks(&t.bookmarks)
+                    ])?;
+                }
+            }
+        }
+    }
+
+    tx.commit()
+}
+
+// Bring the stored library in line with the one in memory, which only
+// differs from it by what changes while sonik runs: play counts, favorites,
+// start offsets, ratings and bookmarks, and tracks pruned after going
+// missing
+pub fn save(config: &Config, artists: &[Artist]) -> Result<(), ()> {
+    let mut conn = open(config)?;
+    save_library(&mut conn, artists).map_err(|_| ())
+}
+
+fn save_library(conn: &mut Connection, artists: &[Artist]) -> rusqlite::Result<()> {
+    let tx = conn.transaction()?;
+    tx.execute_batch("CREATE TEMP TABLE IF NOT EXISTS kept (file_path TEXT PRIMARY KEY)")?;
+
+    {
+        let mut update = tx.prepare(
+            "UPDATE tracks SET play_count = ?2, favorite = ?3, start_offset = ?4, rating = ?5,
+             bookmarks = ?6
+             WHERE file_path = ?1
+             AND (play_count != ?2 OR favorite != ?3 OR start_offset != ?4 OR rating != ?5
+             OR bookmarks != ?6)",
+        )?;
+        let mut keep = tx.prepare("INSERT OR IGNORE INTO kept (file_path) VALUES (?1)")?;
+
+        let tracks = artists
+            .iter()
+            .flat_map(|a| a.albums.iter())
+            .flat_map(|al| al.tracks.iter());
+        for t in tracks {
+            update.execute(params![
+                t.file_path,
+                t.play_count,
+                t.favorite,
+                t.start_offset as i64,
+                t.rating,
+                join_bookmarks(&t.bookmarks)
+            ])?;
+            keep.execute(params![t.file_path])?;
+        }
+    }
+
+    tx.execute_batch(
+        "DELETE FROM tracks WHERE file_path NOT IN (SELECT file_path FROM kept);
+         DELETE FROM albums WHERE id NOT IN (SELECT album_id FROM tracks);
+         DELETE FROM artists WHERE id NOT IN (SELECT artist_id FROM albums);
+         DROP TABLE kept;",
+    )?;
+
+    tx.commit()
+}
+
+// Look a single track up by its path
+pub fn find_track(config: &Config, file_path: &str) -> Option<Track> {
+    let conn = open(config).ok()?;
+    conn.query_row(
+        &format!("SELECT {} FROM tracks WHERE file_path = ?1", TRACK_COLUMNS),
+        params![file_path],
+        |row| track_from_row(row, 0),
+    )
+    .ok()
+}
+
+// Every track whose artist tag is the given name
+pub fn tracks_by_artist(config: &Config, artist: &str) -> Result<Vec<Track>, ()> {
+    let conn = open(config)?;
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT {} FROM tracks WHERE artist = ?1 ORDER BY id",
+            TRACK_COLUMNS
+        ))
+        .map_err(|_| ())?;
+    let rows = stmt
+        .query_map(params![artist], |row| track_from_row(row, 0))
+        .map_err(|_| ())?;
+
+    rows.collect::<rusqlite::Result<Vec<Track>>>()
+        .map_err(|_| ())
+}
+
+// Build a track from TRACK_COLUMNS, starting at column `first`
+fn track_from_row(row: &Row, first: usize) -> rusqlite::Result<Track> {
+    let artists: String = row.get(first + 3)?;
+
+    Ok(Track {
+        file_path: row.get(first)?,
+        title: row.get(first + 1)?,
+        artist: row.get(first + 2)?,
+        artists: split_artists(&artists),
+        album_artist: row.get(first + 4)?,
+        album: row.get(first + 5)?,
+        year: row.get(first + 6)?,
+        track_num: row.get(first + 7)?,
+        duration: row.get(first + 8)?,
+        play_count: row.get(first + 9)?,
+        favorite: row.get(first + 10)?,
+        track_gain: row.get::<_, Option<f64>>(first + 11)?.map(|g| g as f32),
+        album_gain: row.get::<_, Option<f64>>(first + 12)?.map(|g| g as f32),
+        codec: row.get(first + 13)?,
+        sample_rate: row.get(first + 14)?,
+        bitrate: row.get(first + 15)?,
+        date_added: row.get::<_, i64>(first + 16)? as u64,
+        start_offset: row.get::<_, i64>(first + 17)? as u64,
+        rating: row.get(first + 18)?,
+        lyrics: row.get(first + 19)?,
+        bookmarks: split_bookmarks(&row.get::<_, String>(first + 20)?),
+    })
+}
+
+// Bookmarks are kept as a JSON list of [name, position] pairs
+fn join_bookmarks(bookmarks: &[(String, Duration)]) -> String {
+    serde_json::to_string(bookmarks).unwrap_or_else(|_| "[]".to_string())
+}
+
+fn split_bookmarks(bookmarks: &str) -> Vec<(String, Duration)> {
+    serde_json::from_str(bookmarks).unwrap_or_default()
+}
+
+fn join_artists(artists: &[String]) -> String {
+    artists.join(&ARTIST_SEPARATOR.to_string())
+}
+
+fn split_artists(artists: &str) -> Vec<String> {
+    if artists.is_empty() {
+        return Vec::new();
+    }
+    artists.split(ARTIST_SEPARATOR).map(String::from).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_version_gets_the_added_columns() {
+        let mut config = Config::default();
+        config.database_path = std::env::temp_dir()
+            .join(format!("sonik-{}-v1.db", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        let path = database_path(&config);
+
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(SCHEMA).unwrap();
+        conn.execute_batch(
+            "INSERT INTO artists (id, title) VALUES (1, 'Blur');
+             INSERT INTO albums VALUES (1, 1, 'Parklife', 'Blur', 1994, NULL, 0);
+             INSERT INTO tracks VALUES (1, 1, '/music/girls.mp3', 'Girls & Boys', 'Blur',
+                 'Blur', 'Blur', 'Parklife', 1994, 1, 290000, 3, 1, NULL, NULL, 'MP3',
+                 44100, 320, 0, 0);",
+        )
+        .unwrap();
+        drop(conn);
+
+        let artists = read(&config).unwrap();
+        let track = &artists[0].albums[0].tracks[0];
+        assert_eq!(track.title, "Girls & Boys");
+        assert_eq!(track.play_count, 3);
+        assert_eq!(track.rating, 0);
+        assert_eq!(track.lyrics, None);
+        assert!(track.bookmarks.is_empty());
+
+        // Opening it again finds nothing left to do
+        let conn = open(&config).unwrap();
+        let version: u32 = conn
+            .query_row("PRAGMA user_version", NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, SCHEMA_VERSION);
+        drop(conn);
+        assert!(read(&config).is_ok());
+
+        std::fs::remove_file(&path).ok();
+    }
+}
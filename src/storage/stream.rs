@@ -0,0 +1,188 @@
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use rodio::{Decoder, Source};
+
+// How far into the audio to look for the first MP3 frame, past any padding
+const HEAD_LEN: usize = 4096;
+
+// Ogg pages are at most this long, so the last one is within this much of
+// the end of the file
+const OGG_TAIL_LEN: u64 = 65307;
+
+// Layer III bitrates in kbps by index, for MPEG-1 and for MPEG-2 and 2.5
+const MP3_BITRATES: [[u32; 15]; 2] = [
+    [
+        0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+    ],
+    [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],
+];
+
+// Sample rates by index, for MPEG-1, 2 and 2.5
+const MP3_SAMPLE_RATES: [[u32; 3]; 3] = [
+    [44100, 48000, 32000],
+    [22050, 24000, 16000],
+    [11025, 12000, 8000],
+];
+
+// What a file's audio says about itself, as opposed to its tags. Anything
+// that couldn't be worked out is left empty or 0
+#[derive(Debug, Default)]
+pub struct StreamInfo {
+    pub codec: &'static str,
+    pub sample_rate: u32,
+    // In milliseconds, for streams that record their length
+    duration: Option<u32>,
+    // Of the first MP3 frame, in kbps
+    frame_bitrate: u32,
+    // Size of the file less any ID3v2 tag in front, which can hold cover art
+    audio_len: u64,
+}
+
+impl StreamInfo {
+    // Length in milliseconds: the stream's own where it records one, then
+    // the tag's. An MP3 with neither is taken to be constant bitrate, going
+    // by its first frame
+    pub fn duration(&self, tagged: Option<u32>) -> u32 {
+        match self.duration.or(tagged) {
+            Some(duration) => duration,
+            None if self.frame_bitrate > 0 => {
+                (self.audio_len * 8 / u64::from(self.frame_bitrate)) as u32
+            }
+            None => 0,
+        }
+    }
+
+    // Average bitrate in kbps over a track of the given length
+    pub fn bitrate(&self, duration: u32) -> u32 {
+        if duration > 0 {
+            (self.audio_len * 8 / u64::from(duration)) as u32
+        } else {
+            self.frame_bitrate
+        }
+    }
+}
+
+// Read the stream properties of a file. The format is told by the bytes it
+// starts with, and the decoder is opened once for the sample rate and, for
+// WAV and FLAC, the length
+pub fn read(path: &Path) -> StreamInfo {
+    let mut info = StreamInfo::default();
+    let mut file = match File::open(path) {
+        Ok(f) => f,
+        Err(_) => return info,
+    };
+
+    let start = id3v2_len(&mut file);
+    let len = file.metadata().map(|m| m.len()).unwrap_or(0);
+    info.audio_len = len.saturating_sub(start);
+
+    let mut head = Vec::with_capacity(HEAD_LEN);
+    if file.seek(SeekFrom::Start(start)).is_err()
+        || (&mut file)
+            .take(HEAD_LEN as u64)
+            .read_to_end(&mut head)
+            .is_err()
+    {
+        return info;
+    }
+
+    let mut granule = None;
+    if head.starts_with(b"fLaC") {
+        info.codec = "FLAC";
+    } else if head.starts_with(b"OggS") {
+        info.codec = if contains(&head, b"\x01vorbis") {
+            "Vorbis"
+        } else {
+            "Ogg"
+        };
+        granule = last_granule(&mut file, len);
+    } else if head.starts_with(b"RIFF") && head.get(8..12) == Some(b"WAVE") {
+        info.codec = "WAV";
+    } else if let Some((bitrate, sample_rate)) = first_mp3_frame(&head) {
+        info.codec = "MP3";
+        info.frame_bitrate = bitrate;
+        info.sample_rate = sample_rate;
+    }
+
+    if file.seek(SeekFrom::Start(0)).is_ok() {
+        if let Ok(decoder) = Decoder::new(BufReader::new(file)) {
+            info.sample_rate = decoder.sample_rate();
+            info.duration = decoder.total_duration().map(|d| d.as_millis() as u32);
+        }
+    }
+
+    // Ogg pages end with how many samples have been played by then, so the
+    // last page's is the length
+    if let Some(granule) = granule {
+        if info.duration.is_none() && info.sample_rate > 0 {
+            info.duration = Some((granule * 1000 / u64::from(info.sample_rate)) as u32);
+        }
+    }
+
+    info
+}
+
+// Bytes taken up by an ID3v2 tag at the start of the file, 0 without one
+fn id3v2_len(file: &mut File) -> u64 {
+    let mut header = [0u8; 10];
+    if file.read_exact(&mut header).is_err() || &header[..3] != b"ID3" {
+        return 0;
+    }
+
+    // The size leaves out the header, and the footer if there is one. Its
+    // bytes only use their low seven bits
+    let size = header[6..10]
+        .iter()
+        .fold(0u64, |size, b| (size << 7) | u64::from(b & 0x7f));
+    let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+
+    10 + size + footer
+}
+
+// Bitrate and sample rate from the first layer III frame header
+fn first_mp3_frame(head: &[u8]) -> Option<(u32, u32)> {
+    head.windows(4).find_map(|h| {
+        if h[0] != 0xff || h[1] & 0xe0 != 0xe0 {
+            return None;
+        }
+        let version = match (h[1] >> 3) & 3 {
+            3 => 0,
+            2 => 1,
+            0 => 2,
+            _ => return None,
+        };
+        if (h[1] >> 1) & 3 != 1 {
+            return None;
+        }
+        let bitrate = MP3_BITRATES[version.min(1)]
+            .get((h[2] >> 4) as usize)
+            .copied()
+            .filter(|&b| b > 0)?;
+        let sample_rate = MP3_SAMPLE_RATES[version]
+            .get(((h[2] >> 2) & 3) as usize)
+            .copied()?;
+
+        Some((bitrate, sample_rate))
+    })
+}
+
+// The granule position of the last Ogg page in the file
+fn last_granule(file: &mut File, len: u64) -> Option<u64> {
+    let tail_start = len.saturating_sub(OGG_TAIL_LEN);
+    file.seek(SeekFrom::Start(tail_start)).ok()?;
+    let mut tail = Vec::new();
+    file.read_to_end(&mut tail).ok()?;
+
+    let page = tail.windows(4).rposition(|w| w == b"OggS")?;
+    let bytes = tail.get(page + 6..page + 14)?;
+    let mut granule = [0u8; 8];
+    granule.copy_from_slice(bytes);
+
+    Some(u64::from_le_bytes(granule))
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|w| w == needle)
+}
@@ -2,11 +2,16 @@ pub enum Term {
     Title(String),
     Album(String),
     Artist(String),
+    // Tracks with at least this many stars
+    Rating(u8),
+    // Tracks whose path contains this, matched as typed rather than fuzzily
+    File(String),
 }
 
 impl Term {
     fn from_search_query(query: &str) -> Option<Term> {
-        let elements = query.split(':').collect::<Vec<&str>>();
+        // Only the first colon separates, as paths can hold more
+        let elements = query.splitn(2, ':').collect::<Vec<&str>>();
 
         if elements.len() <= 1 {
             None
@@ -15,6 +20,8 @@ impl Term {
                 "title" => Some(Term::Title(elements[1].into())),
                 "album" => Some(Term::Album(elements[1].into())),
                 "artist" => Some(Term::Artist(elements[1].into())),
+                "rating" => elements[1].trim().parse().ok().map(Term::Rating),
+                "file" | "path" => Some(Term::File(elements[1].into())),
                 _ => None,
             }
         }
@@ -0,0 +1,282 @@
+use std::env;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use image::imageops::FilterType;
+use image::RgbaImage;
+use termion::cursor::Goto;
+use tui::buffer::Buffer;
+use tui::layout::Rect;
+use tui::style::{Color, Style};
+use tui::widgets::Widget;
+
+use crate::application::config::AlbumArtMode;
+
+// Rough size of a terminal cell in pixels, used to size sixel images since
+// the real size isn't known
+const CELL_WIDTH: u32 = 10;
+const CELL_HEIGHT: u32 = 20;
+
+// Pixels sent to kitty, which scales the image to the cells it is given
+const KITTY_SIZE: u32 = 256;
+
+// Id of the one image sonik places with the kitty protocol
+const KITTY_ID: u32 = 1;
+
+// How the cover gets onto the screen
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Graphics {
+    Kitty,
+    Sixel,
+    // Colored half blocks, which any terminal with true color can show
+    Blocks,
+}
+
+// Pick the way of drawing cover art the terminal supports. Terminals don't
+// reliably announce sixel support, so it is only picked up from a few
+// known TERM values and can otherwise be asked for in the config
+pub fn detect_graphics(mode: AlbumArtMode) -> Option<Graphics> {
+    match mode {
+        AlbumArtMode::Off => None,
+        AlbumArtMode::Kitty => Some(Graphics::Kitty),
+        AlbumArtMode::Sixel => Some(Graphics::Sixel),
+        AlbumArtMode::Blocks => Some(Graphics::Blocks),
+        AlbumArtMode::Auto => {
+            let term = env::var("TERM").unwrap_or_default();
+            if env::var("KITTY_WINDOW_ID").is_ok() || term.contains("kitty") {
+                Some(Graphics::Kitty)
+            } else if term.contains("sixel") || term.starts_with("foot") || term == "mlterm" {
+                Some(Graphics::Sixel)
+            } else {
+                Some(Graphics::Blocks)
+            }
+        }
+    }
+}
+
+// The cover currently on screen. The image is only loaded, and for kitty
+// and sixel only sent to the terminal, when the album or the space for it
+// changes rather than on every frame
+pub struct CoverArt {
+    pub graphics: Graphics,
+    path: Option<PathBuf>,
+    area: Option<Rect>,
+    pub pixels: Option<RgbaImage>,
+    // Set when the terminal still has to be sent the current image
+    pending: bool,
+}
+
+impl CoverArt {
+    pub fn new(graphics: Graphics) -> CoverArt {
+        CoverArt {
+            graphics,
+            path: None,
+            area: None,
+            pixels: None,
+            pending: false,
+        }
+    }
+
+    // Point the cover at an image and the cells it should fill, where None
+    // for the area means it isn't on screen. Returns true if a sixel image
+    // has to be cleared off the screen, which only a full redraw can do
+    pub fn update(&mut self, path: Option<&PathBuf>, area: Option<Rect>) -> bool {
+        if self.path.as_ref() == path && self.area == area {
+            return false;
+        }
+
+        let was_shown = self.area.is_some() && self.pixels.is_some();
+
+        self.path = path.cloned();
+        self.area = area;
+        self.pixels = match (path, area) {
+            (Some(p), Some(a)) => self.load(p, a),
+            _ => None,
+        };
+        self.pending = true;
+
+        was_shown && self.graphics == Graphics::Sixel
+    }
+
+    fn load(&self, path: &Path, area: Rect) -> Option<RgbaImage> {
+        let (width, height) = match self.graphics {
+            Graphics::Kitty => (KITTY_SIZE, KITTY_SIZE),
+            Graphics::Sixel => (
+                u32::from(area.width) * CELL_WIDTH,
+                u32::from(area.height) * CELL_HEIGHT,
+            ),
+            // Every cell holds two pixels, one above the other
+            Graphics::Blocks => (u32::from(area.width), u32::from(area.height) * 2),
+        };
+
+        if width == 0 || height == 0 {
+            return None;
+        }
+
+        let image = image::open(path).ok()?;
+        Some(
+            image
+                .resize_exact(width, height, FilterType::Triangle)
+                .to_rgba8(),
+        )
+    }
+
+    // Send the image to the terminal once the rest of the frame is drawn.
+    // Blocks are part of the frame itself and need nothing here
+    pub fn emit<W: Write>(&mut self, out: &mut W) -> io::Result<()> {
+        if !self.pending {
+            return Ok(());
+        }
+        self.pending = false;
+
+        if self.graphics == Graphics::Kitty {
+            write!(out, "\x1b_Ga=d,d=i,i={},q=2\x1b\\", KITTY_ID)?;
+        }
+
+        if let (Some(pixels), Some(area)) = (&self.pixels, self.area) {
+            write!(out, "{}", Goto(area.x + 1, area.y + 1))?;
+            match self.graphics {
+                Graphics::Kitty => write_kitty(out, pixels, area)?,
+                Graphics::Sixel => write_sixel(out, pixels)?,
+                Graphics::Blocks => {}
+            }
+        }
+
+        out.flush()
+    }
+}
+
+// Transmit raw RGBA in chunks of base64, as the kitty protocol requires
+fn write_kitty<W: Write>(out: &mut W, pixels: &RgbaImage, area: Rect) -> io::Result<()> {
+    let data = base64::encode(pixels.as_raw());
+    let chunks: Vec<&[u8]> = data.as_bytes().chunks(4096).collect();
+
+    for (i, chunk) in chunks.iter().enumerate() {
+        let more = if i + 1 < chunks.len() { 1 } else { 0 };
+        if i == 0 {
+            write!(
+                out,
+                "\x1b_Ga=T,f=32,s={},v={},c={},r={},i={},C=1,q=2,m={};",
+                pixels.width(),
+                pixels.height(),
+                area.width,
+                area.height,
+                KITTY_ID,
+                more
+            )?;
+        } else {
+            write!(out, "\x1b_Gm={};", more)?;
+        }
+        out.write_all(chunk)?;
+        write!(out, "\x1b\\")?;
+    }
+
+    Ok(())
+}
+
+// Encode the image as sixels using a fixed 6x6x6 color cube, which is plenty
+// for a thumbnail and avoids building a palette per image
+fn write_sixel<W: Write>(out: &mut W, pixels: &RgbaImage) -> io::Result<()> {
+    let (width, height) = pixels.dimensions();
+    let index = |x: u32, y: u32| {
+        let p = pixels.get_pixel(x, y);
+        let level = |c: u8| u32::from(c) * 5 / 255;
+        (level(p[0]) * 36 + level(p[1]) * 6 + level(p[2])) as usize
+    };
+
+    write!(out, "\x1bPq\"1;1;{};{}", width, height)?;
+    for i in 0..216 {
+        let percent = |level: usize| level * 100 / 5;
+        write!(
+            out,
+            "#{};2;{};{};{}",
+            i,
+            percent(i / 36),
+            percent(i / 6 % 6),
+            percent(i % 6)
+        )?;
+    }
+
+    // Each band covers six rows of pixels, drawn once per color in it
+    for band in (0..height).step_by(6) {
+        let rows = (height - band).min(6);
+        let mut colors: Vec<usize> = (0..width)
+            .flat_map(|x| (0..rows).map(move |dy| (x, band + dy)))
+            .map(|(x, y)| index(x, y))
+            .collect();
+        colors.sort();
+        colors.dedup();
+
+        for color in colors {
+            write!(out, "#{}", color)?;
+
+            let mut run = 0;
+            let mut last = None;
+            for x in 0..width {
+                let bits = (0..rows)
+                    .filter(|&dy| index(x, band + dy) == color)
+                    .fold(0u8, |acc, dy| acc | 1 << dy);
+                let sixel = (63 + bits) as char;
+
+                if Some(sixel) == last {
+                    run += 1;
+                } else {
+                    if let Some(c) = last {
+                        write_run(out, c, run)?;
+                    }
+                    last = Some(sixel);
+                    run = 1;
+                }
+            }
+            if let Some(c) = last {
+                write_run(out, c, run)?;
+            }
+
+            // Back to the start of the band for the next color
+            write!(out, "$")?;
+        }
+        write!(out, "-")?;
+    }
+
+    write!(out, "\x1b\\")
+}
+
+fn write_run<W: Write>(out: &mut W, c: char, run: u32) -> io::Result<()> {
+    if run > 3 {
+        write!(out, "!{}{}", run, c)
+    } else {
+        write!(out, "{}", c.to_string().repeat(run as usize))
+    }
+}
+
+// Draws the cover into the frame with half blocks, the upper half of each
+// cell taking one pixel's color and the background the one below it
+pub struct CoverBlocks<'a> {
+    pixels: &'a RgbaImage,
+}
+
+impl<'a> CoverBlocks<'a> {
+    pub fn new(pixels: &'a RgbaImage) -> CoverBlocks<'a> {
+        CoverBlocks { pixels }
+    }
+}
+
+impl<'a> Widget for CoverBlocks<'a> {
+    fn draw(&mut self, area: Rect, buf: &mut Buffer) {
+        let color = |x: u32, y: u32| {
+            let p = self.pixels.get_pixel(x, y);
+            Color::Rgb(p[0], p[1], p[2])
+        };
+
+        let width = self.pixels.width().min(u32::from(area.width));
+        let height = (self.pixels.height() / 2).min(u32::from(area.height));
+        for y in 0..height {
+            for x in 0..width {
+                let style = Style::default().fg(color(x, y * 2)).bg(color(x, y * 2 + 1));
+                buf.get_mut(area.x + x as u16, area.y + y as u16)
+                    .set_symbol("▀")
+                    .set_style(style);
+            }
+        }
+    }
+}
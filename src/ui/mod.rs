@@ -1,2 +1,5 @@
+pub mod art;
+pub mod mouse;
 pub mod screens;
+pub mod theme;
 pub mod widgets;
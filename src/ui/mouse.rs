@@ -0,0 +1,164 @@
+use std::time::Duration;
+
+use termion::event::{MouseButton, MouseEvent};
+use tui::layout::Rect;
+use tui::widgets::{Block, Borders};
+use unicode_width::UnicodeWidthStr;
+
+use crate::application::state::UI;
+use crate::ui::screens::{
+    library_areas, progress_bar, progress_bar_area, queue_areas, search_areas, settings_areas,
+    top_bar_areas, TAB_DIVIDER,
+};
+
+// Clicks switch tabs, select list rows and seek in the progress bar, and the
+// wheel moves the selection. chunks is the screen as main lays it out: the
+// top bar, the current tab and the stats
+pub fn handle(ui: &mut UI, event: MouseEvent, chunks: &[Rect]) {
+    if ui.show_help || ui.show_lyrics {
+        return;
+    }
+
+    match event {
+        MouseEvent::Press(MouseButton::WheelUp, _, _) => ui.on_up(),
+        MouseEvent::Press(MouseButton::WheelDown, _, _) => ui.on_down(),
+        // termion counts cells from 1, tui from 0
+        MouseEvent::Press(MouseButton::Left, x, y) => {
+            click(ui, x.saturating_sub(1), y.saturating_sub(1), chunks)
+        }
+        _ => {}
+    }
+}
+
+fn click(ui: &mut UI, x: u16, y: u16, chunks: &[Rect]) {
+    let top = top_bar_areas(chunks[0]);
+    if contains(top[0], x, y) {
+        if let Some(index) = tab_at(&ui.tabs.titles, inner(top[0]), x) {
+            ui.tabs.index = index;
+        }
+        return;
+    }
+    if contains(top[1], x, y) {
+        let bar = progress_bar_area(top[1]);
+        if y == bar.y {
+            if let Some(ms) = progress_bar(ui).position_at(bar, x) {
+                ui.seek_to(Duration::from_millis(ms));
+            }
+        }
+        return;
+    }
+    if !contains(chunks[1], x, y) {
+        return;
+    }
+
+    let area = chunks[1];
+    match ui.tabs.index {
+        0 => {
+            let (_, columns) = queue_areas(ui, area);
+            let selected = ui.queue_selected();
+            let len = ui.queue.tracks.len();
+            let row = columns
+                .iter()
+                .find(|c| contains(**c, x, y))
+                .and_then(|c| row_at(*c, selected, len, y));
+            if let Some(row) = row {
+                ui.queue_select = row;
+            }
+        }
+        1 => {
+            let (_, columns) = library_areas(area);
+            for (column, c) in columns.into_iter().enumerate() {
+                if !contains(c, x, y) {
+                    continue;
+                }
+                let lib = &ui.lib_cols;
+                let (selected, len) = match column {
+                    0 => (lib.artists.selected, lib.artists.items.len()),
+                    1 => (lib.albums.selected, lib.albums.items.len()),
+                    _ => (lib.tracks.selected, lib.tracks.items.len()),
+                };
+                if let Some(row) = row_at(inner(c), selected, len, y) {
+                    ui.lib_cols.select(column, row);
+                }
+            }
+        }
+        2 => {
+            let results = inner(search_areas(area)[1]);
+            if let Some(row) = row_at(results, ui.search_select, ui.search_results.len(), y) {
+                ui.search_select = row;
+            }
+        }
+        3 => {
+            let list = &mut ui.favorites;
+            if let Some(row) = row_at(inner(area), list.selected, list.items.len(), y) {
+                list.selected = row;
+            }
+        }
+        4 => {
+            let list = &mut ui.devices;
+            let devices = inner(settings_areas(area)[0]);
+            if let Some(row) = row_at(devices, list.selected, list.items.len(), y) {
+                list.selected = row;
+            }
+        }
+        5 => {
+            let list = &mut ui.recent;
+            if let Some(row) = row_at(inner(area), list.selected, list.items.len(), y) {
+                list.selected = row;
+            }
+        }
+        6 => {
+            let list = &mut ui.played;
+            if let Some(row) = row_at(inner(area), list.selected, list.items.len(), y) {
+                list.selected = row;
+            }
+        }
+        _ => {}
+    }
+}
+
+fn contains(area: Rect, x: u16, y: u16) -> bool {
+    x >= area.left() && x < area.right() && y >= area.top() && y < area.bottom()
+}
+
+// Inside a bordered block
+fn inner(area: Rect) -> Rect {
+    Block::default().borders(Borders::ALL).inner(area)
+}
+
+// Which tab title is at column x, laid out the way tui's Tabs draws them: a
+// space either side of each title and the divider in between
+fn tab_at(titles: &[&str], area: Rect, x: u16) -> Option<usize> {
+    let mut left = area.left();
+    for (i, title) in titles.iter().enumerate() {
+        let right = left + title.width() as u16 + 2;
+        if x >= left && x < right {
+            return Some(i);
+        }
+        left = right + TAB_DIVIDER.width() as u16;
+    }
+
+    None
+}
+
+// The item on row y of a list drawn in area, scrolled the way the lists
+// keep their selection in view
+fn row_at(area: Rect, selected: usize, len: usize, y: u16) -> Option<usize> {
+    if y < area.top() || y >= area.bottom() {
+        return None;
+    }
+
+    let height = area.height as usize;
+    let offset = if selected >= height {
+        selected - height + 1
+    } else {
+        0
+    };
+    let row = offset + (y - area.top()) as usize;
+
+    if row < len {
+        Some(row)
+    } else {
+        None
+    }
+}
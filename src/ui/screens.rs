@@ -1,23 +1,28 @@
+use std::collections::HashSet;
 use std::io::{self, Write};
 
 use chrono::Local;
 use tui::backend::Backend;
 use tui::layout::{Alignment, Constraint, Direction, Layout, Rect};
 use tui::style::{Color, Modifier, Style};
-use tui::widgets::{Block, Borders, List, Paragraph, Tabs, Text, Widget};
+use tui::widgets::{Block, Borders, List, Paragraph, SelectableList, Tabs, Text, Widget};
 use tui::Frame;
 
-use crate::application::state::UI;
-use crate::ui::widgets::RecordList;
+use crate::application::equalizer::{preset_name, BANDS, MAX_GAIN};
+use crate::application::state::{balance_label, index_letter, AlbumSort, INDEX_LETTERS, UI};
+use crate::storage::record::clock;
+use crate::ui::art::{CoverArt, CoverBlocks, Graphics};
+use crate::ui::theme::Theme;
+use crate::ui::widgets::{Blank, Position, ProgressBar, RecordList};
 
 // Yeah, I know this isn't elegant, but hey it works
 pub fn artist_color(app: &UI) -> Style {
     let color;
 
     if app.lib_cols.current_active == 0 {
-        color = Style::default().fg(Color::Rgb(255, 255, 0))
+        color = Style::default().fg(app.theme.highlight)
     } else {
-        color = Style::default().fg(Color::Rgb(173, 176, 73))
+        color = Style::default().fg(app.theme.inactive)
     }
 
     color
@@ -27,9 +32,9 @@ pub fn album_color(app: &UI) -> Style {
     let color;
 
     if app.lib_cols.current_active == 1 {
-        color = Style::default().fg(Color::Rgb(255, 255, 0))
+        color = Style::default().fg(app.theme.highlight)
     } else {
-        color = Style::default().fg(Color::Rgb(173, 176, 73))
+        color = Style::default().fg(app.theme.inactive)
     }
 
     color
@@ -39,17 +44,52 @@ pub fn track_color(app: &UI) -> Style {
     let color;
 
     if app.lib_cols.current_active == 2 {
-        color = Style::default().fg(Color::Rgb(255, 255, 0))
+        color = Style::default().fg(app.theme.highlight)
     } else {
-        color = Style::default().fg(Color::Rgb(173, 176, 73))
+        color = Style::default().fg(app.theme.inactive)
     }
 
     color
 }
-pub fn draw_queue<B>(f: &mut Frame<B>, app: &UI, area: Rect)
+// Space on the left of the queue for the cover of the playing album, about
+// square on a terminal whose cells are twice as tall as they are wide
+fn split_cover(area: Rect) -> (Rect, Rect) {
+    let width = (area.height.saturating_sub(2) * 2 + 2).min(area.width / 3);
+    let chunks = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Length(width), Constraint::Min(0)].as_ref())
+        .split(area);
+
+    (chunks[0], chunks[1])
+}
+
+// The cells inside the cover's border, where the image itself goes
+pub fn cover_pane(area: Rect) -> Rect {
+    let (cover_area, _) = split_cover(area);
+    Block::default().borders(Borders::ALL).inner(cover_area)
+}
+
+fn draw_cover<B>(f: &mut Frame<B>, cover: &CoverArt, area: Rect)
 where
     B: Backend,
 {
+    let mut block = Block::default().borders(Borders::ALL).title("cover");
+    block.render(f, area);
+
+    // Kitty and sixel images are sent to the terminal after the frame
+    if let (Graphics::Blocks, Some(pixels)) = (cover.graphics, &cover.pixels) {
+        CoverBlocks::new(pixels).render(f, block.inner(area));
+    }
+}
+
+// The queue's box, right of the cover when there is one, and the title,
+// artist and album columns inside it
+pub fn queue_areas(app: &UI, area: Rect) -> (Rect, Vec<Rect>) {
+    let area = match &app.cover {
+        Some(_) => split_cover(area).1,
+        None => area,
+    };
+
     let chunks = Layout::default()
         .direction(Direction::Horizontal)
         .margin(1)
@@ -63,31 +103,90 @@ where
         )
         .split(area);
 
-    let artists = app.queue.tracks.iter().map(|track| {
-        Text::styled(
-            track.artist.to_string(),
-            Style::default().fg(Color::LightGreen),
-        )
-    });
+    (area, chunks)
+}
 
-    let titles = app.queue.tracks.iter().map(|track| {
-        Text::styled(
-            track.title.to_string(),
-            Style::default().fg(Color::LightBlue),
-        )
-    });
+pub fn draw_queue<B>(f: &mut Frame<B>, app: &UI, area: Rect)
+where
+    B: Backend,
+{
+    if let Some(cover) = &app.cover {
+        draw_cover(f, cover, split_cover(area).0);
+    }
+    let (area, chunks) = queue_areas(app, area);
 
-    let albums = app.queue.tracks.iter().map(|track| {
-        Text::styled(
-            track.album.to_string(),
-            Style::default().fg(Color::LightRed),
-        )
-    });
+    // Keep the selected row in view, the same way RecordList does
+    let selected = app.queue_selected();
+    let list_height = chunks[0].height as usize;
+    let offset = if selected >= list_height {
+        selected - list_height + 1
+    } else {
+        0
+    };
+
+    let row_style = |i: usize, color: Color| {
+        if i == selected {
+            Style::default().fg(color).modifier(Modifier::REVERSED)
+        } else {
+            Style::default().fg(color)
+        }
+    };
+
+    let artists = app
+        .queue
+        .tracks
+        .iter()
+        .enumerate()
+        .map(|(i, track)| Text::styled(track.artist.to_string(), row_style(i, app.theme.artist)))
+        .skip(offset);
+
+    // Each track goes by its place in the queue, counted from 1, with the
+    // head marked as what plays next. Indices are padded to line up
+    let width = app.queue.tracks.len().to_string().len();
+    let titles = app
+        .queue
+        .tracks
+        .iter()
+        .enumerate()
+        .map(|(i, track)| {
+            let marker = if i == 0 { "▶" } else { " " };
+            // Tracks in the up next lane are set apart from the rest
+            let lane = if i < app.queue.up_next { "» " } else { "" };
+            let title = format!(
+                "{:>width$} {} {}{}",
+                i + 1,
+                marker,
+                lane,
+                track.title,
+                width = width
+            );
+            Text::styled(title, row_style(i, app.theme.title))
+        })
+        .skip(offset);
+
+    let albums = app
+        .queue
+        .tracks
+        .iter()
+        .enumerate()
+        .map(|(i, track)| Text::styled(track.album.to_string(), row_style(i, app.theme.album)))
+        .skip(offset);
+
+    let title = match app.queue.peek() {
+        Some(next) => format!("up next: {} - {}", next.title, next.artist),
+        None => "up next".to_string(),
+    };
 
     Block::default()
         .borders(Borders::ALL)
-        .title("up next")
+        .title(&title)
         .render(f, area);
+    Position::new(
+        selected,
+        app.queue.tracks.len(),
+        Style::default().fg(app.theme.foreground),
+    )
+    .render(f, area);
 
     List::new(titles)
         .block(Block::default())
@@ -102,11 +201,15 @@ where
         .render(f, chunks[2]);
 }
 
-pub fn draw_library<B>(f: &mut Frame<B>, app: &UI, area: Rect)
-where
-    B: Backend,
-{
-    let chunks = Layout::default()
+// The breadcrumb along the top, and the artist, album and track columns
+// under it
+pub fn library_areas(area: Rect) -> (Rect, Vec<Rect>) {
+    let rows = Layout::default()
+        .constraints([Constraint::Length(1), Constraint::Min(0)].as_ref())
+        .direction(Direction::Vertical)
+        .split(area);
+
+    let columns = Layout::default()
         .constraints(
             [
                 Constraint::Ratio(1, 3),
@@ -116,7 +219,137 @@ where
             .as_ref(),
         )
         .direction(Direction::Horizontal)
-        .split(area);
+        .split(rows[1]);
+
+    (rows[0], columns)
+}
+
+// Where the selection is, e.g. "The Beatles › Abbey Road › Come Together",
+// with the part play acts on picked out and anything past it dimmed
+fn draw_breadcrumb<B>(f: &mut Frame<B>, app: &UI, area: Rect)
+where
+    B: Backend,
+{
+    let lib = &app.lib_cols;
+    let parts = [
+        (
+            lib.artists
+                .items
+                .get(lib.artists.selected)
+                .map(|a| a.title.as_str()),
+            app.theme.artist,
+        ),
+        (
+            lib.albums
+                .items
+                .get(lib.albums.selected)
+                .map(|al| al.title.as_str()),
+            app.theme.album,
+        ),
+        (
+            lib.tracks
+                .items
+                .get(lib.tracks.selected)
+                .map(|t| t.title.as_str()),
+            app.theme.title,
+        ),
+    ];
+
+    let mut crumbs = vec![Text::raw(" ")];
+    for (column, (name, color)) in parts.iter().enumerate() {
+        let name = match name {
+            Some(name) => name,
+            None => break,
+        };
+        if column > 0 {
+            crumbs.push(Text::styled(
+                " › ",
+                Style::default().fg(app.theme.foreground),
+            ));
+        }
+
+        let style = if column == lib.current_active {
+            Style::default().fg(*color).modifier(Modifier::BOLD)
+        } else if column > lib.current_active {
+            Style::default().fg(app.theme.inactive)
+        } else {
+            Style::default().fg(*color)
+        };
+        crumbs.push(Text::styled(name.to_string(), style));
+    }
+
+    Paragraph::new(crumbs.iter()).render(f, area);
+}
+
+// The artist index, right of the breadcrumb where there is room: letters
+// with artists under them stand out, and the selected artist's is bold
+fn draw_letter_bar<B>(f: &mut Frame<B>, app: &UI, area: Rect)
+where
+    B: Backend,
+{
+    let width = INDEX_LETTERS.len() as u16 * 2;
+    if area.width < width * 2 {
+        return;
+    }
+    let area = Rect::new(area.right() - width, area.y, width, 1);
+
+    let lib = &app.lib_cols;
+    let current = lib.artist_letter(lib.artists.selected);
+    let present: HashSet<char> = lib
+        .artists
+        .items
+        .iter()
+        .map(|a| index_letter(&a.title))
+        .collect();
+
+    let letters: Vec<Text> = INDEX_LETTERS
+        .chars()
+        .map(|l| {
+            let style = if Some(l) == current {
+                Style::default()
+                    .fg(app.theme.highlight)
+                    .modifier(Modifier::BOLD)
+            } else if present.contains(&l) {
+                Style::default().fg(app.theme.foreground)
+            } else {
+                Style::default().fg(app.theme.inactive)
+            };
+            Text::styled(format!("{} ", l), style)
+        })
+        .collect();
+
+    Paragraph::new(letters.iter()).render(f, area);
+}
+
+pub fn draw_library<B>(f: &mut Frame<B>, app: &UI, area: Rect)
+where
+    B: Backend,
+{
+    // Nothing to browse until the music folder is scanned, which with
+    // scan_on_startup = "never" is left to the user
+    if app.lib_cols.artists.items.is_empty() {
+        let hint = [Text::styled(
+            format!(
+                "The library is empty. Run sonik -r to scan {}",
+                app.config.music_folder
+            ),
+            Style::default().fg(app.theme.foreground),
+        )];
+        Paragraph::new(hint.iter())
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .style(artist_color(app)),
+            )
+            .alignment(Alignment::Center)
+            .wrap(true)
+            .render(f, area);
+        return;
+    }
+
+    let (breadcrumb, chunks) = library_areas(area);
+    draw_breadcrumb(f, app, breadcrumb);
+    draw_letter_bar(f, app, breadcrumb);
 
     // This will be the artist block
     RecordList::default()
@@ -127,24 +360,44 @@ where
         )
         .items(&app.lib_cols.artists.items)
         .select(Some(app.lib_cols.artists.selected))
-        .style(Style::default().fg(Color::White))
+        .marked(&app.lib_cols.artists.marked)
+        .style(Style::default().fg(app.theme.foreground))
         .highlight_style(artist_color(&app).modifier(Modifier::BOLD))
         .highlight_symbol(">>")
         .render(f, chunks[0]);
+    Position::new(
+        app.lib_cols.artists.selected,
+        app.lib_cols.artists.items.len(),
+        artist_color(app),
+    )
+    .render(f, chunks[0]);
 
     // This will be the albums of that artist
+    let album_title = match app.lib_cols.album_sort {
+        AlbumSort::Title => "",
+        AlbumSort::YearAscending => "by year",
+        AlbumSort::YearDescending => "by year, newest first",
+    };
     RecordList::default()
         .block(
             Block::default()
                 .borders(Borders::ALL)
+                .title(album_title)
                 .style(album_color(&app)),
         )
         .items(&app.lib_cols.albums.items)
         .select(Some(app.lib_cols.albums.selected))
-        .style(Style::default().fg(Color::White))
+        .marked(&app.lib_cols.albums.marked)
+        .style(Style::default().fg(app.theme.foreground))
         .highlight_style(album_color(&app).modifier(Modifier::BOLD))
         .highlight_symbol(">>")
         .render(f, chunks[1]);
+    Position::new(
+        app.lib_cols.albums.selected,
+        app.lib_cols.albums.items.len(),
+        album_color(app),
+    )
+    .render(f, chunks[1]);
 
     // This will be the songs of that album of that artist
     RecordList::default()
@@ -155,20 +408,32 @@ where
         )
         .items(&app.lib_cols.tracks.items)
         .select(Some(app.lib_cols.tracks.selected))
-        .style(Style::default().fg(Color::White))
+        .marked(&app.lib_cols.tracks.marked)
+        .style(Style::default().fg(app.theme.foreground))
         .highlight_style(track_color(&app).modifier(Modifier::BOLD))
         .highlight_symbol(">>")
         .render(f, chunks[2]);
+    Position::new(
+        app.lib_cols.tracks.selected,
+        app.lib_cols.tracks.items.len(),
+        track_color(app),
+    )
+    .render(f, chunks[2]);
+}
+
+// The search box above its results
+pub fn search_areas(area: Rect) -> Vec<Rect> {
+    Layout::default()
+        .constraints([Constraint::Ratio(1, 10), Constraint::Ratio(9, 10)].as_ref())
+        .direction(Direction::Vertical)
+        .split(area)
 }
 
 pub fn draw_search<B>(f: &mut Frame<B>, app: &UI, area: Rect)
 where
     B: Backend,
 {
-    let chunks = Layout::default()
-        .constraints([Constraint::Ratio(1, 10), Constraint::Ratio(9, 10)].as_ref())
-        .direction(Direction::Vertical)
-        .split(area);
+    let chunks = search_areas(area);
 
     draw_search_input(f, app, chunks[0]);
     draw_search_results(f, app, chunks[1]);
@@ -193,8 +458,14 @@ where
         .split(area);
 
     let text = [
-        Text::styled("Available Terms:\n", Style::default().fg(Color::Yellow)),
-        Text::styled("\ntitle, album, artist", Style::default().fg(Color::Yellow)),
+        Text::styled(
+            "Available Terms:\n",
+            Style::default().fg(app.theme.highlight),
+        ),
+        Text::styled(
+            "\ntitle, album, artist",
+            Style::default().fg(app.theme.highlight),
+        ),
     ];
 
     // Enclosing border
@@ -212,7 +483,7 @@ where
 
     // Input box
     Paragraph::new([Text::raw(&app.search_input)].iter())
-        .style(Style::default().fg(Color::Yellow))
+        .style(Style::default().fg(app.theme.highlight))
         .block(Block::default().borders(Borders::ALL))
         .render(f, chunks[2]);
 
@@ -228,25 +499,248 @@ where
         .direction(Direction::Vertical)
         .split(area);
 
+    // Say when there were more matches than are shown
+    let title = if app.search_total > app.search_results.len() {
+        format!(
+            "results (best {} of {})",
+            app.search_results.len(),
+            app.search_total
+        )
+    } else {
+        "results".to_string()
+    };
+
     RecordList::default()
-        .block(Block::default().borders(Borders::ALL).title("results"))
+        .block(Block::default().borders(Borders::ALL).title(&title))
         .items(&app.search_results)
         .select(Some(app.search_select))
-        .style(Style::default().fg(Color::White))
+        .marked(&app.search_marked)
+        .spans(&app.search_spans, Style::default().fg(app.theme.accent))
+        .style(Style::default().fg(app.theme.foreground))
         .highlight_style(
             Style::default()
-                .fg(Color::Rgb(255, 255, 0))
+                .fg(app.theme.highlight)
                 .modifier(Modifier::BOLD),
         )
         .highlight_symbol(">>")
         .render(f, chunks[0]);
+    Position::new(
+        app.search_select,
+        app.search_results.len(),
+        Style::default().fg(app.theme.foreground),
+    )
+    .render(f, chunks[0]);
 }
 
-pub fn draw_top_bar<B>(f: &mut Frame<B>, app: &UI, area: Rect)
+pub fn draw_favorites<B>(f: &mut Frame<B>, app: &UI, area: Rect)
 where
     B: Backend,
 {
-    let chunks = Layout::default()
+    RecordList::default()
+        .block(Block::default().borders(Borders::ALL).title("favorites"))
+        .items(&app.favorites.items)
+        .select(Some(app.favorites.selected))
+        .style(Style::default().fg(app.theme.foreground))
+        .highlight_style(
+            Style::default()
+                .fg(app.theme.highlight)
+                .modifier(Modifier::BOLD),
+        )
+        .highlight_symbol(">>")
+        .render(f, area);
+    Position::new(
+        app.favorites.selected,
+        app.favorites.items.len(),
+        Style::default().fg(app.theme.foreground),
+    )
+    .render(f, area);
+}
+
+pub fn draw_recent<B>(f: &mut Frame<B>, app: &UI, area: Rect)
+where
+    B: Backend,
+{
+    RecordList::default()
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("recently added"),
+        )
+        .items(&app.recent.items)
+        .select(Some(app.recent.selected))
+        .style(Style::default().fg(app.theme.foreground))
+        .highlight_style(
+            Style::default()
+                .fg(app.theme.highlight)
+                .modifier(Modifier::BOLD),
+        )
+        .highlight_symbol(">>")
+        .render(f, area);
+    Position::new(
+        app.recent.selected,
+        app.recent.items.len(),
+        Style::default().fg(app.theme.foreground),
+    )
+    .render(f, area);
+}
+
+pub fn draw_played<B>(f: &mut Frame<B>, app: &UI, area: Rect)
+where
+    B: Backend,
+{
+    let title = if app.most_played {
+        "most played"
+    } else {
+        "recently played"
+    };
+    let mut list = RecordList::default()
+        .block(Block::default().borders(Borders::ALL).title(title))
+        .items(&app.played.items);
+    if app.most_played {
+        let plays = app
+            .played
+            .items
+            .iter()
+            .map(|t| Some(format!("{} plays", t.play_count)))
+            .collect();
+        list = list.details(plays);
+    }
+
+    list.select(Some(app.played.selected))
+        .style(Style::default().fg(app.theme.foreground))
+        .highlight_style(
+            Style::default()
+                .fg(app.theme.highlight)
+                .modifier(Modifier::BOLD),
+        )
+        .highlight_symbol(">>")
+        .render(f, area);
+    Position::new(
+        app.played.selected,
+        app.played.items.len(),
+        Style::default().fg(app.theme.foreground),
+    )
+    .render(f, area);
+}
+
+// The output device list above the equalizer, which has a row per band
+pub fn settings_areas(area: Rect) -> Vec<Rect> {
+    Layout::default()
+        .constraints(
+            [
+                Constraint::Min(0),
+                Constraint::Length(BANDS.len() as u16 + 2),
+            ]
+            .as_ref(),
+        )
+        .direction(Direction::Vertical)
+        .split(area)
+}
+
+pub fn draw_settings<B>(f: &mut Frame<B>, app: &UI, area: Rect)
+where
+    B: Backend,
+{
+    let chunks = settings_areas(area);
+
+    // Mark the device currently in use
+    let devices = app
+        .devices
+        .items
+        .iter()
+        .map(|name| {
+            if *name == app.active_device {
+                format!("{} *", name)
+            } else {
+                name.to_string()
+            }
+        })
+        .collect::<Vec<String>>();
+
+    SelectableList::default()
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("output device"),
+        )
+        .items(&devices)
+        .select(Some(app.devices.selected))
+        .style(Style::default().fg(app.theme.foreground))
+        .highlight_style(
+            Style::default()
+                .fg(app.theme.highlight)
+                .modifier(Modifier::BOLD),
+        )
+        .highlight_symbol(">>")
+        .render(f, chunks[0]);
+
+    draw_equalizer(f, app, chunks[1]);
+}
+
+// A bar for each band, going left of the middle for a cut and right of it
+// for a boost, one cell per dB
+fn draw_equalizer<B>(f: &mut Frame<B>, app: &UI, area: Rect)
+where
+    B: Backend,
+{
+    let max = MAX_GAIN as usize;
+    let text = BANDS
+        .iter()
+        .zip(app.equalizer.iter())
+        .enumerate()
+        .map(|(i, (freq, gain))| {
+            let label = if *freq >= 1000.0 {
+                format!("{}kHz", freq / 1000.0)
+            } else {
+                format!("{}Hz", freq)
+            };
+            let cells = gain.abs().round() as usize;
+            let bar = if *gain < 0.0 {
+                format!(
+                    "{}{}|{}",
+                    " ".repeat(max - cells),
+                    "=".repeat(cells),
+                    " ".repeat(max)
+                )
+            } else {
+                format!(
+                    "{}|{}{}",
+                    " ".repeat(max),
+                    "=".repeat(cells),
+                    " ".repeat(max - cells)
+                )
+            };
+
+            let (marker, style) = if i == app.eq_band {
+                (
+                    ">>",
+                    Style::default()
+                        .fg(app.theme.highlight)
+                        .modifier(Modifier::BOLD),
+                )
+            } else {
+                ("  ", Style::default().fg(app.theme.foreground))
+            };
+            Text::styled(
+                format!("{} {:>6} {} {:+} dB\n", marker, label, bar, gain),
+                style,
+            )
+        })
+        .collect::<Vec<Text>>();
+
+    let title = format!(
+        "equalizer: {} | balance: {}",
+        preset_name(&app.equalizer).unwrap_or("custom"),
+        balance_label(app.balance)
+    );
+    Paragraph::new(text.iter())
+        .block(Block::default().borders(Borders::ALL).title(&title))
+        .render(f, area);
+}
+
+// The tabs, now playing and status boxes
+pub fn top_bar_areas(area: Rect) -> Vec<Rect> {
+    Layout::default()
         .constraints(
             [
                 Constraint::Ratio(1, 4),
@@ -256,67 +750,175 @@ where
             .as_ref(),
         )
         .direction(Direction::Horizontal)
-        .split(area);
+        .split(area)
+}
+
+// Tabs are drawn with this between their titles
+pub const TAB_DIVIDER: &str = " | ";
+
+pub fn draw_top_bar<B>(f: &mut Frame<B>, app: &UI, area: Rect)
+where
+    B: Backend,
+{
+    let chunks = top_bar_areas(area);
 
     // Draw tab explorer box
     Tabs::default()
         .block(Block::default().borders(Borders::ALL).title("tabs"))
         .titles(&app.tabs.titles)
         .select(app.tabs.index)
-        .style(Style::default().fg(Color::Cyan))
-        .highlight_style(Style::default().fg(Color::Yellow))
-        .divider("              ")
+        .style(Style::default().fg(app.theme.accent))
+        .highlight_style(Style::default().fg(app.theme.highlight))
+        .divider(TAB_DIVIDER)
         .render(f, chunks[0]);
 
     draw_now_playing(f, chunks[1], app);
 
-    draw_status(f, chunks[2]);
+    draw_status(f, chunks[2], app);
 }
 
 fn draw_now_playing<B>(f: &mut Frame<B>, area: Rect, app: &UI)
 where
     B: Backend,
 {
-    let track_info = [
-        Text::styled(
-            &app.now_playing.title,
-            Style::default().fg(Color::LightBlue),
-        ),
+    let mut track_info = vec![
+        Text::styled(&app.now_playing.title, Style::default().fg(app.theme.title)),
         Text::raw(" - "),
         Text::styled(
             &app.now_playing.artist,
-            Style::default().fg(Color::LightGreen),
+            Style::default().fg(app.theme.artist),
         ),
         Text::raw(" - "),
-        Text::styled(&app.now_playing.album, Style::default().fg(Color::LightRed)),
+        Text::styled(&app.now_playing.album, Style::default().fg(app.theme.album)),
     ];
 
+    if app.now_playing.rating > 0 {
+        track_info.push(Text::styled(
+            format!(" {}", app.now_playing.stars()),
+            Style::default().fg(app.theme.highlight),
+        ));
+    }
+
+    if (app.speed - 1.0).abs() > f32::EPSILON {
+        track_info.insert(
+            0,
+            Text::styled(
+                format!("[{:.1}x] ", app.speed),
+                Style::default().fg(app.theme.highlight),
+            ),
+        );
+    }
+
+    if app.looping {
+        track_info.insert(
+            0,
+            Text::styled("[loop] ", Style::default().fg(app.theme.highlight)),
+        );
+    } else if app.queue.repeat {
+        track_info.insert(
+            0,
+            Text::styled("[repeat] ", Style::default().fg(app.theme.highlight)),
+        );
+    }
+
+    if app.queue.shuffling {
+        track_info.insert(
+            0,
+            Text::styled("[shuffle] ", Style::default().fg(app.theme.highlight)),
+        );
+    }
+
+    track_info.insert(
+        0,
+        Text::styled(
+            format!("{} ", app.playback.symbol()),
+            Style::default().fg(app.theme.highlight),
+        ),
+    );
+
     let chunks = Layout::default()
         .constraints([Constraint::Percentage(100)].as_ref())
         .direction(Direction::Vertical)
         .margin(1)
         .split(area);
 
+    // Stream details of the track, for spotting low quality files
+    let np = &app.now_playing;
+    let mut details = vec![np.codec.clone()];
+    if np.sample_rate > 0 {
+        details.push(format!("{:.1} kHz", np.sample_rate as f32 / 1000.0));
+    }
+    if np.bitrate > 0 {
+        details.push(format!("{} kbps", np.bitrate));
+    }
+    let title = if np.codec.is_empty() {
+        "now playing".to_string()
+    } else {
+        format!("now playing | {}", details.join(" "))
+    };
+
     Block::default()
         .borders(Borders::ALL)
-        .title("now playing")
+        .title(&title)
         .render(f, area);
 
     Paragraph::new(track_info.iter())
         .alignment(Alignment::Center)
         .render(f, chunks[0]);
+
+    // The bottom border doubles as the progress bar
+    if area.height >= 2 && area.width > 2 {
+        progress_bar(app).render(f, progress_bar_area(area));
+    }
+}
+
+pub fn progress_bar(app: &UI) -> ProgressBar {
+    ProgressBar::new(
+        app.elapsed().as_millis() as u64,
+        u64::from(app.now_playing.duration),
+        Style::default().fg(app.theme.foreground),
+        Style::default().fg(app.theme.highlight),
+    )
+    .marks(
+        app.now_playing
+            .bookmarks
+            .iter()
+            .map(|(_, at)| at.as_millis() as u64)
+            .collect(),
+    )
+}
+
+// Where the progress bar goes in the now-playing block
+pub fn progress_bar_area(area: Rect) -> Rect {
+    Rect::new(area.x + 1, area.bottom() - 1, area.width - 2, 1)
 }
 
-fn draw_status<B>(f: &mut Frame<B>, area: Rect)
+fn draw_status<B>(f: &mut Frame<B>, area: Rect, app: &UI)
 where
     B: Backend,
 {
-    let text = [
+    let mut text = vec![
         Text::raw(Local::now().date().format("%A, %B %d, %Y").to_string()),
         Text::raw(" | "),
         Text::raw(Local::now().time().format("%H:%M:%S").to_string()),
     ];
 
+    if let Some(message) = app.status() {
+        text.push(Text::raw(" | "));
+        text.push(Text::styled(
+            message,
+            Style::default().fg(app.theme.highlight),
+        ));
+    }
+
+    if let Some(remaining) = app.sleep_remaining() {
+        text.push(Text::raw(" | "));
+        text.push(Text::styled(
+            format!("sleep {}", clock(remaining)),
+            Style::default().fg(app.theme.highlight),
+        ));
+    }
+
     let chunks = Layout::default()
         .constraints([Constraint::Percentage(100)].as_ref())
         .direction(Direction::Vertical)
@@ -341,13 +943,16 @@ where
     let mins = (app.queue.total_time - (3_600_000 * hours)) / 60000;
     let secs = (app.queue.total_time - (3_600_000 * hours) - (mins * 60000)) / 1000;
 
-    let total_hours = app.stats.total_time / 3_600_000;
-    let total_mins = (app.stats.total_time - (3_600_000 * total_hours)) / 60000;
-    let total_secs =
-        (app.stats.total_time - (3_600_000 * total_hours) - (total_mins * 60000)) / 1000;
-
+    let remaining = match app.queue.up_next {
+        0 => format!("remaining: {} tracks", app.queue.tracks.len()),
+        n => format!(
+            "remaining: {} tracks, {} up next",
+            app.queue.tracks.len(),
+            n
+        ),
+    };
     let queue_info = [
-        Text::raw(format!("remaining: {} tracks", app.queue.tracks.len())),
+        Text::raw(remaining),
         Text::raw(" | "),
         Text::raw(format!(
             "{} hour(s), {} min(s), {} sec(s)",
@@ -362,16 +967,24 @@ where
         Text::raw(" | "),
         Text::raw(format!("{} tracks", app.stats.tracks)),
         Text::raw(" | "),
-        Text::raw(format!(
-            "{} hour(s), {} min(s), {} sec(s)",
-            total_hours, total_mins, total_secs
-        )),
+        Text::raw(app.stats.playtime()),
     ];
 
     let search_blurb = [Text::raw("Enter search query")];
 
     let search_info = [Text::raw(format!("{} results", app.search_results.len()))];
 
+    let favorites_info = [Text::raw(format!(
+        "{} favorites",
+        app.favorites.items.len()
+    ))];
+
+    let settings_info = [Text::raw(format!("output: {}", app.active_device))];
+
+    let recent_info = [Text::raw(format!("{} albums", app.recent.items.len()))];
+
+    let played_info = [Text::raw(format!("{} tracks", app.played.items.len()))];
+
     let chunks = Layout::default()
         .constraints([Constraint::Percentage(100)].as_ref())
         .direction(Direction::Vertical)
@@ -383,17 +996,77 @@ where
         .title("stats")
         .render(f, area);
 
+    if app.clear_armed {
+        let confirm_info = [Text::styled(
+            "Clear queue? y/N",
+            Style::default().fg(app.theme.highlight),
+        )];
+        Paragraph::new(confirm_info.iter())
+            .alignment(Alignment::Center)
+            .render(f, chunks[0]);
+        return;
+    }
+
+    if let Some((name, _)) = &app.bookmark_input {
+        let bookmark_info = [Text::styled(
+            format!("bookmark name: {} (enter to save, esc to cancel)", name),
+            Style::default().fg(app.theme.highlight),
+        )];
+        Paragraph::new(bookmark_info.iter())
+            .alignment(Alignment::Center)
+            .render(f, chunks[0]);
+        return;
+    }
+
+    if let Some(folder) = &app.folder_input {
+        let folder_info = [Text::styled(
+            format!("queue folder: {} (enter to add, esc to cancel)", folder),
+            Style::default().fg(app.theme.highlight),
+        )];
+        Paragraph::new(folder_info.iter())
+            .alignment(Alignment::Center)
+            .render(f, chunks[0]);
+        return;
+    }
+
     match app.tabs.index {
-        0 => {
-            Paragraph::new(queue_info.iter())
-                .alignment(Alignment::Center)
-                .render(f, chunks[0]);
-        }
-        1 => {
-            Paragraph::new(library_info.iter())
-                .alignment(Alignment::Center)
-                .render(f, chunks[0]);
-        }
+        0 => match &app.queue_find {
+            Some(query) => {
+                let matches = app.queue_matches();
+                let position = match matches.iter().position(|&i| i == app.queue_selected()) {
+                    Some(i) => format!("{}/{}", i + 1, matches.len()),
+                    None => format!("{} matches", matches.len()),
+                };
+                let find_info = [Text::styled(
+                    format!("find: {} ({})", query, position),
+                    Style::default().fg(app.theme.highlight),
+                )];
+                Paragraph::new(find_info.iter())
+                    .alignment(Alignment::Center)
+                    .render(f, chunks[0]);
+            }
+            None => {
+                Paragraph::new(queue_info.iter())
+                    .alignment(Alignment::Center)
+                    .render(f, chunks[0]);
+            }
+        },
+        1 => match &app.jump_input {
+            Some(input) if app.jump_active() => {
+                let jump_info = [Text::styled(
+                    format!("jump to: {}", input),
+                    Style::default().fg(app.theme.highlight),
+                )];
+                Paragraph::new(jump_info.iter())
+                    .alignment(Alignment::Center)
+                    .render(f, chunks[0]);
+            }
+            _ => {
+                Paragraph::new(library_info.iter())
+                    .alignment(Alignment::Center)
+                    .render(f, chunks[0]);
+            }
+        },
         2 => {
             if !app.search_results.is_empty() {
                 Paragraph::new(search_info.iter())
@@ -405,6 +1078,171 @@ where
                     .render(f, chunks[0]);
             }
         }
+        3 => {
+            Paragraph::new(favorites_info.iter())
+                .alignment(Alignment::Center)
+                .render(f, chunks[0]);
+        }
+        4 => {
+            Paragraph::new(settings_info.iter())
+                .alignment(Alignment::Center)
+                .render(f, chunks[0]);
+        }
+        5 => {
+            Paragraph::new(recent_info.iter())
+                .alignment(Alignment::Center)
+                .render(f, chunks[0]);
+        }
+        6 => {
+            Paragraph::new(played_info.iter())
+                .alignment(Alignment::Center)
+                .render(f, chunks[0]);
+        }
         _ => {}
     }
 }
+
+// Frames of the spinner shown while the library is scanned
+const SPINNER: [&str; 4] = ["|", "/", "-", "\\"];
+
+// Shown while the library is first scanned, before there is anything else
+// to draw. tick moves the spinner on
+pub fn draw_loading<B>(f: &mut Frame<B>, theme: &Theme, scanned: usize, tick: usize, area: Rect)
+where
+    B: Backend,
+{
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(
+            [
+                Constraint::Percentage(40),
+                Constraint::Length(5),
+                Constraint::Min(0),
+            ]
+            .as_ref(),
+        )
+        .split(area);
+
+    Block::default()
+        .style(Style::default().bg(theme.background))
+        .render(f, area);
+
+    let text = [
+        Text::styled(
+            format!(
+                "{} Scanning library, {} files read\n",
+                SPINNER[tick % SPINNER.len()],
+                scanned
+            ),
+            Style::default().fg(theme.highlight),
+        ),
+        Text::styled("\nEsc to cancel", Style::default().fg(theme.inactive)),
+    ];
+
+    Paragraph::new(text.iter())
+        .block(Block::default().borders(Borders::ALL).title("sonik"))
+        .style(Style::default().fg(theme.foreground))
+        .alignment(Alignment::Center)
+        .render(f, chunks[1]);
+}
+
+// Every key binding, grouped by where it applies, over the middle of the
+// screen
+pub fn draw_help<B>(f: &mut Frame<B>, app: &UI, help: &[(&str, Vec<(String, &str)>)], area: Rect)
+where
+    B: Backend,
+{
+    let width = area.width.min(64);
+    let overlay = Rect::new(
+        area.x + (area.width - width) / 2,
+        area.y,
+        width,
+        area.height,
+    );
+
+    let key_width = help
+        .iter()
+        .flat_map(|(_, lines)| lines.iter().map(|(keys, _)| keys.len()))
+        .max()
+        .unwrap_or(0);
+
+    let mut text = Vec::new();
+    for (heading, lines) in help {
+        text.push(Text::styled(
+            format!("{}\n", heading),
+            Style::default().fg(app.theme.accent),
+        ));
+        for (keys, description) in lines {
+            text.push(Text::styled(
+                format!("  {:width$}  ", keys, width = key_width),
+                Style::default().fg(app.theme.highlight),
+            ));
+            text.push(Text::raw(format!("{}\n", description)));
+        }
+        text.push(Text::raw("\n"));
+    }
+
+    Blank::new(Style::default().bg(app.theme.background)).render(f, overlay);
+
+    Paragraph::new(text.iter())
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("help | up/down to scroll, esc to close"),
+        )
+        .scroll(app.help_offset)
+        .render(f, overlay);
+}
+
+// The playing track's lyrics over the current tab, laid out like help.
+// Timed lyrics keep the line being sung highlighted in the middle
+pub fn draw_lyrics<B>(f: &mut Frame<B>, app: &UI, area: Rect)
+where
+    B: Backend,
+{
+    let width = area.width.min(64);
+    let overlay = Rect::new(
+        area.x + (area.width - width) / 2,
+        area.y,
+        width,
+        area.height,
+    );
+
+    let mut text = Vec::new();
+    let mut offset = app.lyrics_offset;
+    match app.current_lyrics() {
+        Some(lyrics) => {
+            let current = lyrics.current_line(app.elapsed());
+            if let Some(line) = current {
+                let middle = overlay.height.saturating_sub(2) / 2;
+                offset = (line as u16).saturating_sub(middle);
+            }
+            for (i, line) in lyrics.lines().into_iter().enumerate() {
+                let style = if Some(i) == current {
+                    Style::default()
+                        .fg(app.theme.highlight)
+                        .modifier(Modifier::BOLD)
+                } else {
+                    Style::default().fg(app.theme.foreground)
+                };
+                text.push(Text::styled(format!("{}\n", line), style));
+            }
+        }
+        None if app.now_playing.file_path.is_empty() => {
+            text.push(Text::raw("Nothing is playing"));
+        }
+        None => text.push(Text::raw("No lyrics for this track")),
+    }
+
+    Blank::new(Style::default().bg(app.theme.background)).render(f, overlay);
+
+    Paragraph::new(text.iter())
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("lyrics | up/down to scroll, esc to close"),
+        )
+        .alignment(Alignment::Center)
+        .scroll(offset)
+        .render(f, overlay);
+}
@@ -0,0 +1,126 @@
+use tui::style::Color;
+
+use crate::application::config::ThemeConfig;
+
+// Colors the screens are drawn with
+#[derive(Debug, Clone, Copy)]
+pub struct Theme {
+    pub foreground: Color,
+    pub background: Color,
+    // Selected rows, the active library column, and prompts
+    pub highlight: Color,
+    // Columns that aren't active
+    pub inactive: Color,
+    // Tab titles
+    pub accent: Color,
+    pub title: Color,
+    pub artist: Color,
+    pub album: Color,
+}
+
+impl Theme {
+    // The look sonik has always had
+    pub fn classic() -> Theme {
+        Theme {
+            foreground: Color::White,
+            background: Color::Black,
+            highlight: Color::Rgb(255, 255, 0),
+            inactive: Color::Rgb(173, 176, 73),
+            accent: Color::Cyan,
+            title: Color::LightBlue,
+            artist: Color::LightGreen,
+            album: Color::LightRed,
+        }
+    }
+
+    pub fn gruvbox() -> Theme {
+        Theme {
+            foreground: Color::Rgb(235, 219, 178),
+            background: Color::Rgb(40, 40, 40),
+            highlight: Color::Rgb(250, 189, 47),
+            inactive: Color::Rgb(146, 131, 116),
+            accent: Color::Rgb(142, 192, 124),
+            title: Color::Rgb(131, 165, 152),
+            artist: Color::Rgb(184, 187, 38),
+            album: Color::Rgb(251, 73, 52),
+        }
+    }
+
+    pub fn nord() -> Theme {
+        Theme {
+            foreground: Color::Rgb(216, 222, 233),
+            background: Color::Rgb(46, 52, 64),
+            highlight: Color::Rgb(235, 203, 139),
+            inactive: Color::Rgb(76, 86, 106),
+            accent: Color::Rgb(136, 192, 208),
+            title: Color::Rgb(129, 161, 193),
+            artist: Color::Rgb(163, 190, 140),
+            album: Color::Rgb(191, 97, 106),
+        }
+    }
+
+    // Start from the named preset and apply any colors set on top of it.
+    // Anything that can't be read is reported and left as the preset has it
+    pub fn from_config(config: &ThemeConfig) -> Theme {
+        let mut theme = match config.preset.as_str() {
+            "" | "default" | "classic" => Theme::classic(),
+            "gruvbox" => Theme::gruvbox(),
+            "nord" => Theme::nord(),
+            other => {
+                println!("Warning: unknown theme preset '{}'", other);
+                Theme::classic()
+            }
+        };
+
+        let mut overrides = [
+            (&config.foreground, &mut theme.foreground),
+            (&config.background, &mut theme.background),
+            (&config.highlight, &mut theme.highlight),
+            (&config.inactive, &mut theme.inactive),
+            (&config.accent, &mut theme.accent),
+            (&config.title, &mut theme.title),
+            (&config.artist, &mut theme.artist),
+            (&config.album, &mut theme.album),
+        ];
+
+        for (value, color) in overrides.iter_mut() {
+            if let Some(value) = value {
+                match parse_color(value) {
+                    Some(c) => **color = c,
+                    None => println!("Warning: unknown theme color '{}'", value),
+                }
+            }
+        }
+
+        theme
+    }
+}
+
+// Colors are either one of the terminal's named colors or "#rrggbb"
+fn parse_color(value: &str) -> Option<Color> {
+    if value.starts_with('#') && value.len() == 7 {
+        let channel = |i: usize| u8::from_str_radix(value.get(i..i + 2)?, 16).ok();
+        return Some(Color::Rgb(channel(1)?, channel(3)?, channel(5)?));
+    }
+
+    match value.to_lowercase().replace(&['_', ' '][..], "").as_str() {
+        "reset" => Some(Color::Reset),
+        "black" => Some(Color::Black),
+        "red" => Some(Color::Red),
+        "green" => Some(Color::Green),
+        "yellow" => Some(Color::Yellow),
+        "blue" => Some(Color::Blue),
+        "magenta" => Some(Color::Magenta),
+        "cyan" => Some(Color::Cyan),
+        "gray" | "grey" => Some(Color::Gray),
+        "darkgray" | "darkgrey" => Some(Color::DarkGray),
+        "lightred" => Some(Color::LightRed),
+        "lightgreen" => Some(Color::LightGreen),
+        "lightyellow" => Some(Color::LightYellow),
+        "lightblue" => Some(Color::LightBlue),
+        "lightmagenta" => Some(Color::LightMagenta),
+        "lightcyan" => Some(Color::LightCyan),
+        "white" => Some(Color::White),
+        _ => None,
+    }
+}
@@ -1,4 +1,6 @@
+use std::collections::HashSet;
 use std::iter::{self, Iterator};
+use std::time::Duration;
 
 use unicode_width::UnicodeWidthStr;
 
@@ -7,30 +9,22 @@ use tui::layout::Rect;
 use tui::style::Style;
 use tui::widgets::{Block, List, Text, Widget};
 
-use crate::storage::record::Record;
+use crate::storage::record::{clock, Record};
 
+#[derive(Default)]
 pub struct RecordList<'b> {
     block: Option<Block<'b>>,
     items: Vec<&'b str>,
+    details: Vec<Option<String>>,
     selected: Option<usize>,
+    marked: Option<&'b HashSet<usize>>,
+    spans: Option<&'b [Vec<(usize, usize)>]>,
+    span_style: Style,
     style: Style,
     highlight_style: Style,
     highlight_symbol: Option<&'b str>,
 }
 
-impl<'b> Default for RecordList<'b> {
-    fn default() -> RecordList<'b> {
-        RecordList {
-            block: None,
-            items: Vec::new(),
-            selected: None,
-            style: Default::default(),
-            highlight_style: Default::default(),
-            highlight_symbol: None,
-        }
-    }
-}
-
 impl<'b> RecordList<'b> {
     pub fn block(mut self, block: Block<'b>) -> RecordList<'b> {
         self.block = Some(block);
@@ -39,6 +33,7 @@ impl<'b> RecordList<'b> {
 
     pub fn items<I: Record>(mut self, items: &'b [I]) -> RecordList<'b> {
         self.items = items.iter().map(|a| a.name()).collect::<Vec<&str>>();
+        self.details = items.iter().map(|a| a.detail()).collect();
         self
     }
 
@@ -61,6 +56,26 @@ impl<'b> RecordList<'b> {
         self.selected = index;
         self
     }
+
+    // Shown right-aligned instead of what the items give, one per item
+    pub fn details(mut self, details: Vec<Option<String>>) -> RecordList<'b> {
+        self.details = details;
+        self
+    }
+
+    // Byte ranges of each name to draw in another style, e.g. the parts a
+    // search matched
+    pub fn spans(mut self, spans: &'b [Vec<(usize, usize)>], style: Style) -> RecordList<'b> {
+        self.spans = Some(spans);
+        self.span_style = style;
+        self
+    }
+
+    // Items to show a check mark next to, for lists that can be marked
+    pub fn marked(mut self, marked: &'b HashSet<usize>) -> RecordList<'b> {
+        self.marked = Some(marked);
+        self
+    }
 }
 
 impl<'b> Widget for RecordList<'b> {
@@ -71,6 +86,7 @@ impl<'b> Widget for RecordList<'b> {
         };
 
         let list_height = list_area.height as usize;
+        let list_width = list_area.width as usize;
 
         // Use highlight_style only if something is selected
         let (selected, highlight_style) = match self.selected {
@@ -98,14 +114,25 @@ impl<'b> Widget for RecordList<'b> {
             .iter()
             .enumerate()
             .map(|(i, &item)| {
+                let detail = self.details.get(i).and_then(|d| d.as_ref());
+                let mark = match self.marked {
+                    Some(marked) if marked.contains(&i) => "✓ ",
+                    Some(_) => "  ",
+                    None => "",
+                };
                 if let Some(s) = selected {
                     if i == s {
-                        Text::styled(format!("{} {}", highlight_symbol, item), highlight_style)
+                        let line = format!("{} {}{}", highlight_symbol, mark, item);
+                        Text::styled(with_detail(line, detail, list_width), highlight_style)
                     } else {
-                        Text::styled(format!("{} {}", blank_symbol, item), self.style)
+                        let line = format!("{} {}{}", blank_symbol, mark, item);
+                        Text::styled(with_detail(line, detail, list_width), self.style)
                     }
                 } else {
-                    Text::styled(item, self.style)
+                    Text::styled(
+                        with_detail(item.to_string(), detail, list_width),
+                        self.style,
+                    )
                 }
             })
             .skip(offset as usize);
@@ -113,5 +140,198 @@ impl<'b> Widget for RecordList<'b> {
             .block(self.block.unwrap_or_default())
             .style(self.style)
             .draw(area, buf);
+
+        // Spans are drawn over the names where they ended up on screen,
+        // which is after the symbol and mark columns
+        if let (Some(spans), Some(s)) = (self.spans, selected) {
+            let name_start =
+                highlight_symbol.width() + 1 + if self.marked.is_some() { 2 } else { 0 };
+            for (row, i) in (offset..self.items.len()).take(list_height).enumerate() {
+                let item = self.items[i];
+                let style = if i == s {
+                    highlight_style.fg(self.span_style.fg)
+                } else {
+                    self.span_style
+                };
+
+                for &(start, end) in spans.get(i).map(|v| v.as_slice()).unwrap_or(&[]) {
+                    let (before, matched) = match (item.get(..start), item.get(start..end)) {
+                        (Some(before), Some(matched)) => (before, matched),
+                        _ => continue,
+                    };
+                    let x = name_start + before.width();
+                    if x >= list_width {
+                        continue;
+                    }
+                    buf.set_stringn(
+                        list_area.left() + x as u16,
+                        list_area.top() + row as u16,
+                        matched,
+                        list_width - x,
+                        style,
+                    );
+                }
+            }
+        }
+    }
+}
+
+// Pad the line so the detail sits against the right edge. If both don't fit,
+// the name is what gets shown
+fn with_detail(line: String, detail: Option<&String>, width: usize) -> String {
+    match detail {
+        Some(d) if line.width() + d.width() < width => {
+            let padding = width - line.width() - d.width();
+            format!("{}{}{}", line, " ".repeat(padding), d)
+        }
+        _ => line,
+    }
+}
+
+// Wipes an area, so whatever is drawn on top of it doesn't show what was
+// underneath through its gaps
+pub struct Blank {
+    style: Style,
+}
+
+impl Blank {
+    pub fn new(style: Style) -> Blank {
+        Blank { style }
+    }
+}
+
+impl Widget for Blank {
+    fn draw(&mut self, area: Rect, buf: &mut Buffer) {
+        for y in area.top()..area.bottom() {
+            for x in area.left()..area.right() {
+                buf.get_mut(x, y).set_symbol(" ").set_style(self.style);
+            }
+        }
+    }
+}
+
+// Where the selection is in a list, e.g. "[123/4000]", drawn on the right
+// of the bottom border of the list's area
+pub struct Position {
+    selected: usize,
+    total: usize,
+    style: Style,
+}
+
+impl Position {
+    pub fn new(selected: usize, total: usize, style: Style) -> Position {
+        Position {
+            selected,
+            total,
+            style,
+        }
+    }
+}
+
+impl Widget for Position {
+    fn draw(&mut self, area: Rect, buf: &mut Buffer) {
+        if self.total == 0 || area.height < 2 {
+            return;
+        }
+
+        let text = format!("[{}/{}]", self.selected.min(self.total - 1) + 1, self.total);
+        // Clear of the corner, and only where it fits
+        let width = text.width() as u16;
+        if width + 2 > area.width {
+            return;
+        }
+        buf.set_string(
+            area.right() - width - 1,
+            area.bottom() - 1,
+            &text,
+            self.style,
+        );
+    }
+}
+
+// How far through a track playback is, as "1:23 / 4:56" followed by a bar
+// filled up to there, on one row
+pub struct ProgressBar {
+    elapsed: u64,
+    total: u64,
+    style: Style,
+    filled_style: Style,
+    // Times in milliseconds marked along the bar, e.g. bookmarks
+    marks: Vec<u64>,
+}
+
+impl ProgressBar {
+    // Both times in milliseconds
+    pub fn new(elapsed: u64, total: u64, style: Style, filled_style: Style) -> ProgressBar {
+        ProgressBar {
+            elapsed: elapsed.min(total),
+            total,
+            style,
+            filled_style,
+            marks: Vec::new(),
+        }
+    }
+
+    pub fn marks(mut self, marks: Vec<u64>) -> ProgressBar {
+        self.marks = marks;
+        self
+    }
+
+    fn label(&self) -> String {
+        format!(
+            " {} / {} ",
+            clock(Duration::from_millis(self.elapsed)),
+            clock(Duration::from_millis(self.total))
+        )
+    }
+
+    // The time in milliseconds at column x of the bar when drawn in area,
+    // or None if x is on the label or outside the bar
+    pub fn position_at(&self, area: Rect, x: u16) -> Option<u64> {
+        let label_width = self.label().width() as u16;
+        if self.total == 0 || label_width >= area.width {
+            return None;
+        }
+
+        let start = area.left() + label_width;
+        if x < start || x >= area.right() {
+            return None;
+        }
+        let bar_width = u64::from(area.width - label_width);
+        Some(self.total * u64::from(x - start) / bar_width)
+    }
+}
+
+impl Widget for ProgressBar {
+    fn draw(&mut self, area: Rect, buf: &mut Buffer) {
+        if self.total == 0 || area.height == 0 {
+            return;
+        }
+
+        let label = self.label();
+        let label_width = label.width() as u16;
+        if label_width >= area.width {
+            return;
+        }
+        buf.set_string(area.left(), area.top(), &label, self.style);
+
+        let bar_width = area.width - label_width;
+        let filled = (u64::from(bar_width) * self.elapsed / self.total) as u16;
+        for x in 0..bar_width {
+            let (symbol, style) = if x < filled {
+                ("━", self.filled_style)
+            } else {
+                ("─", self.style)
+            };
+            buf.get_mut(area.left() + label_width + x, area.top())
+                .set_symbol(symbol)
+                .set_style(style);
+        }
+        for mark in &self.marks {
+            let x = (u64::from(bar_width) * mark.min(&self.total) / self.total) as u16;
+            let x = x.min(bar_width - 1);
+            buf.get_mut(area.left() + label_width + x, area.top())
+                .set_symbol("┃");
+        }
     }
 }
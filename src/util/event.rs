@@ -5,11 +5,15 @@ use std::sync::mpsc;
 use std::thread;
 use std::time::Duration;
 
-use termion::event::Key;
+use termion::event::{self as input, Key, MouseEvent};
 use termion::input::TermRead;
 
 pub enum Event<I> {
     Input(I),
+    // Only sent when the terminal reports the mouse, see MouseTerminal
+    Mouse(MouseEvent),
+    // The terminal changed size
+    Resize,
     Tick,
 }
 
@@ -19,19 +23,22 @@ pub struct Events {
     rx: mpsc::Receiver<Event<Key>>,
     input_handle: thread::JoinHandle<()>,
     tick_handle: thread::JoinHandle<()>,
+    _resize_handle: thread::JoinHandle<()>,
 }
 
+// Esc closes prompts and clears the search as well as quitting, so input
+// keeps being read after it rather than stopping at an exit key
 #[derive(Debug, Clone, Copy)]
 pub struct Config {
-    pub exit_key: Key,
     pub tick_rate: Duration,
+    pub resize_rate: Duration,
 }
 
 impl Default for Config {
     fn default() -> Config {
         Config {
-            exit_key: Key::Esc,
             tick_rate: Duration::from_millis(250),
+            resize_rate: Duration::from_millis(50),
         }
     }
 }
@@ -47,14 +54,14 @@ impl Events {
             let tx = tx.clone();
             thread::spawn(move || {
                 let stdin = io::stdin();
-                for evt in stdin.keys() {
-                    if let Ok(key) = evt {
-                        if tx.send(Event::Input(key)).is_err() {
-                            return;
-                        }
-                        if key == config.exit_key {
-                            return;
-                        }
+                for evt in stdin.events() {
+                    let event = match evt {
+                        Ok(input::Event::Key(key)) => Event::Input(key),
+                        Ok(input::Event::Mouse(mouse)) => Event::Mouse(mouse),
+                        _ => continue,
+                    };
+                    if tx.send(event).is_err() {
+                        return;
                     }
                 }
             })
@@ -69,10 +76,29 @@ impl Events {
                 }
             })
         };
+        // termion doesn't report resizes, so the size is checked for
+        // changes more often than the tick comes round
+        let resize_handle = {
+            let tx = tx.clone();
+            thread::spawn(move || {
+                let mut size = termion::terminal_size().ok();
+                loop {
+                    thread::sleep(config.resize_rate);
+                    let current = termion::terminal_size().ok();
+                    if current != size {
+                        size = current;
+                        if tx.send(Event::Resize).is_err() {
+                            return;
+                        }
+                    }
+                }
+            })
+        };
         Events {
             rx,
             input_handle,
             tick_handle,
+            _resize_handle: resize_handle,
         }
     }
 